@@ -1449,7 +1449,7 @@ impl Group {
     pub fn is_capturing(&self) -> bool {
         match self.kind {
             GroupKind::CaptureIndex(_) | GroupKind::CaptureName { .. } => true,
-            GroupKind::NonCapturing(_) => false,
+            GroupKind::NonCapturing(_) | GroupKind::Atomic => false,
         }
     }
 
@@ -1460,7 +1460,7 @@ impl Group {
         match self.kind {
             GroupKind::CaptureIndex(i) => Some(i),
             GroupKind::CaptureName { ref name, .. } => Some(name.index),
-            GroupKind::NonCapturing(_) => None,
+            GroupKind::NonCapturing(_) | GroupKind::Atomic => None,
         }
     }
 }
@@ -1480,6 +1480,8 @@ pub enum GroupKind {
     },
     /// `(?:a)` and `(?i:a)`
     NonCapturing(Flags),
+    /// `(?>a)`, which never backtracks into `a` once it has matched
+    Atomic,
 }
 
 /// A capture name.
@@ -1268,6 +1268,12 @@ impl<'s, P: Borrow<Parser>> ParserI<'s, P> {
                 kind: ast::GroupKind::CaptureName { starts_with_p, name },
                 ast: Box::new(Ast::empty(self.span())),
             }))
+        } else if self.bump_if("?>") {
+            Ok(Either::Right(ast::Group {
+                span: open_span,
+                kind: ast::GroupKind::Atomic,
+                ast: Box::new(Ast::empty(self.span())),
+            }))
         } else if self.bump_if("?") {
             if self.is_eof() {
                 return Err(
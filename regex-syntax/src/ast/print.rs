@@ -167,6 +167,7 @@ impl<W: fmt::Write> Writer<W> {
                 self.wtr.write_str(":")?;
                 Ok(())
             }
+            Atomic => self.wtr.write_str("(?>"),
         }
     }
 
@@ -536,6 +537,7 @@ mod tests {
         roundtrip("(?P<foo>a)");
         roundtrip("(?<foo>a)");
         roundtrip("(a)");
+        roundtrip("(?>a|ab)");
     }
 
     #[test]
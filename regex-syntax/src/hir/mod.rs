@@ -386,10 +386,11 @@ impl Hir {
         // true even when 'a' is an expression that never matches anything
         // (like '\P{any}').
         //
-        // Additionally, the regex 'a{1}' is always equivalent to 'a'.
+        // Additionally, the regex 'a{1}' is always equivalent to 'a', except
+        // when it is possessive: 'a{1}+' is the atomic group '(?>a)'.
         if rep.min == 0 && rep.max == Some(0) {
             return Hir::empty();
-        } else if rep.min == 1 && rep.max == Some(1) {
+        } else if rep.min == 1 && rep.max == Some(1) && !rep.is_possessive() {
             return *rep.sub;
         }
         let props = Properties::repetition(&rep);
@@ -995,6 +995,15 @@ impl<'t, 'p> TranslatorI<'t, 'p> {
             // The HIR doesn't need to use non-capturing groups, since the way
             // in which the data type is defined handles this automatically.
             ast::GroupKind::NonCapturing(_) => return expr,
+            // An atomic group is a possessive repetition of exactly one.
+            ast::GroupKind::Atomic => {
+                return Hir::repetition(hir::Repetition {
+                    min: 1,
+                    max: Some(1),
+                    kind: hir::RepetitionKind::Possessive,
+                    sub: Box::new(expr),
+                })
+            }
         };
         Hir::capture(hir::Capture { index, name, sub: Box::new(expr) })
     }
@@ -2025,6 +2034,20 @@ mod tests {
         assert_eq!(t("a{5,9}+"), hir_range(hir::RepetitionKind::Possessive, 5, Some(9), hir_lit("a")));
     }
 
+    #[test]
+    fn atomic_group() {
+        let atomic = |expr| hir_range(hir::RepetitionKind::Possessive, 1, Some(1), expr);
+        assert_eq!(t("(?>a)"), atomic(hir_lit("a")));
+        assert_eq!(t("a{1}+"), atomic(hir_lit("a")));
+        assert_eq!(
+            t("(?>a|ab)c"),
+            hir_cat(vec![atomic(hir_alt(vec![hir_lit("a"), hir_lit("ab")])), hir_lit("c")])
+        );
+        assert_eq!(t("(?>(a))"), atomic(hir_capture(1, hir_lit("a"))));
+        // Only possessive repetitions of exactly one are kept
+        assert_eq!(t("a{1}"), hir_lit("a"));
+    }
+
     #[test]
     fn repetition() {
         assert_eq!(t("a?"), hir_quest(hir::RepetitionKind::Greedy, hir_lit("a")));
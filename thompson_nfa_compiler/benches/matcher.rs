@@ -0,0 +1,80 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use regex_syntax::ParserBuilder;
+use std::collections::HashSet;
+use thompson_nfa_compiler::{Compiler, Matcher, StateSet};
+
+/// The tokenizer pattern exercised by the `main.rs` demo
+const TOKENIZER: &str = "(?:[sdmt]|ll|ve|re)| ?\\p{L}++| ?\\p{N}++| ?[^\\s\\p{L}\\p{N}]++|\\s++$|\\s+\\S|\\s";
+
+fn corpus() -> String {
+    let paragraph = "The quick brown fox doesn't jump over 42 lazy dogs; it'll rest instead.\n\
+                     Tokenizers see words, numbers like 3.14159, and punctuation!?  ";
+    paragraph.repeat(200)
+}
+
+fn bench_tokenizer(c: &mut Criterion) {
+    let hir = ParserBuilder::new().build().parse(TOKENIZER).unwrap();
+    let nfa = Compiler::new().compile(&hir).unwrap();
+    let input = corpus();
+    
+    let mut group = c.benchmark_group("tokenizer");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    
+    group.bench_function("find_all", |b| {
+        let matcher = Matcher::new(&nfa);
+        b.iter(|| matcher.find_all(&input).len())
+    });
+    
+    group.bench_function("find_all_prepared", |b| {
+        let matcher = Matcher::prepare(&nfa);
+        b.iter(|| matcher.find_all(&input).len())
+    });
+    
+    group.finish();
+}
+
+fn bench_sparse_find(c: &mut Criterion) {
+    let hir = ParserBuilder::new().build().parse("zq[0-9]+").unwrap();
+    let nfa = Compiler::new().compile(&hir).unwrap();
+    let input = format!("{}zq123", corpus());
+    let matcher = Matcher::new(&nfa);
+    
+    let mut group = c.benchmark_group("sparse");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    
+    // `find` skips start positions outside the FIRST set
+    group.bench_function("find", |b| b.iter(|| matcher.find(&input).map(|m| m.start)));
+    
+    // `find_with_stats` tries every start position
+    group.bench_function("find_every_position", |b| {
+        b.iter(|| matcher.find_with_stats(&input).0.map(|m| m.start))
+    });
+    
+    group.finish();
+}
+
+fn bench_state_sets(c: &mut Criterion) {
+    // The access pattern of one matcher step: insert a spread of states, then start over
+    let states: Vec<usize> = (0..256).map(|i| (i * 37) % 512).collect();
+    
+    let mut group = c.benchmark_group("state_set");
+    
+    group.bench_function("hash_set", |b| {
+        b.iter(|| {
+            let mut seen = HashSet::new();
+            states.iter().filter(|&&id| seen.insert(id)).count()
+        })
+    });
+    
+    group.bench_function("bitset", |b| {
+        b.iter(|| {
+            let mut seen = StateSet::new(512);
+            states.iter().filter(|&&id| seen.insert(id)).count()
+        })
+    });
+    
+    group.finish();
+}
+
+criterion_group!(benches, bench_tokenizer, bench_sparse_find, bench_state_sets);
+criterion_main!(benches);
@@ -0,0 +1,239 @@
+use crate::nfa::{AssertionKind, CharacterPredicate, NFA, State};
+use std::fmt::Write;
+
+/// Generator for a C function that simulates a two-character NFA
+///
+/// The generated function keeps one flag per state and steps the whole set over the
+/// input a byte at a time, so it needs no allocation and no library beyond `<string.h>`.
+/// Each byte is compared as the character with the same value, as `Matcher::find_bytes`
+/// does; predicate characters above `0xFF` can never match. Lookbehind assertions
+/// aren't supported and never hold.
+pub struct CGenerator;
+
+impl CGenerator {
+    /// Create a new C generator
+    pub fn new() -> Self {
+        CGenerator
+    }
+    
+    /// Generate `int fn_name(const char *input)`, returning 1 when the NFA matches the
+    /// whole NUL-terminated input and 0 otherwise
+    pub fn generate_function(&self, nfa: &NFA, fn_name: &str) -> String {
+        let state_count = nfa.states.len();
+        let add = format!("{}_add", fn_name);
+        let mut out = String::new();
+        
+        writeln!(out, "/* Generated from a two-character Thompson NFA with {} states */", state_count).unwrap();
+        writeln!(out, "#include <string.h>").unwrap();
+        writeln!(out).unwrap();
+        
+        // Epsilon closure: mark a state and follow its non-consuming edges
+        writeln!(out, "static void {}(unsigned char *set, size_t state, const unsigned char *input, size_t pos, size_t len)", add).unwrap();
+        writeln!(out, "{{").unwrap();
+        writeln!(out, "    (void)input; (void)pos; (void)len;").unwrap();
+        writeln!(out, "    if (set[state]) return;").unwrap();
+        writeln!(out, "    set[state] = 1;").unwrap();
+        writeln!(out, "    switch (state) {{").unwrap();
+        for (id, state) in nfa.states.iter().enumerate() {
+            let targets: Vec<usize> = match state {
+                State::Epsilon { next } | State::Assertion { next, .. } => vec![*next],
+                State::Split { targets } => targets.clone(),
+                _ => continue,
+            };
+            let calls: String = targets.iter()
+                .filter(|&&target| target < state_count)
+                .map(|target| format!(" {}(set, {}, input, pos, len);", add, target))
+                .collect();
+            match state {
+                State::Assertion { kind, .. } => {
+                    writeln!(out, "    case {}: if ({}) {{{} }} break;", id, assertion_condition(kind), calls).unwrap();
+                },
+                _ => writeln!(out, "    case {}:{} break;", id, calls).unwrap(),
+            }
+        }
+        writeln!(out, "    default: break;").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out, "}}").unwrap();
+        writeln!(out).unwrap();
+        
+        // Main loop: step every live state over the current byte and its lookahead
+        writeln!(out, "int {}(const char *input)", fn_name).unwrap();
+        writeln!(out, "{{").unwrap();
+        writeln!(out, "    const unsigned char *bytes = (const unsigned char *)input;").unwrap();
+        writeln!(out, "    size_t len = strlen(input);").unwrap();
+        writeln!(out, "    unsigned char current[{}], next[{}];", state_count, state_count).unwrap();
+        writeln!(out, "    memset(current, 0, sizeof current);").unwrap();
+        writeln!(out, "    {}(current, {}, bytes, 0, len);", add, nfa.start).unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "    for (size_t pos = 0; pos < len; pos++) {{").unwrap();
+        writeln!(out, "        int c = bytes[pos];").unwrap();
+        writeln!(out, "        int la = pos + 1 < len ? bytes[pos + 1] : -1;").unwrap();
+        writeln!(out, "        (void)la;").unwrap();
+        writeln!(out, "        memset(next, 0, sizeof next);").unwrap();
+        writeln!(out, "        for (size_t state = 0; state < {}; state++) {{", state_count).unwrap();
+        writeln!(out, "            if (!current[state]) continue;").unwrap();
+        writeln!(out, "            switch (state) {{").unwrap();
+        for (id, state) in nfa.states.iter().enumerate() {
+            let State::Transitions { transitions } = state else {
+                continue;
+            };
+            writeln!(out, "            case {}:", id).unwrap();
+            for transition in transitions.iter().filter(|t| t.target < state_count) {
+                let mut condition = predicate_condition(&transition.current, "c");
+                if let Some(lookahead) = &transition.lookahead {
+                    condition = format!("{} && {}", condition, predicate_condition(lookahead, "la"));
+                }
+                writeln!(out, "                if ({}) {}(next, {}, bytes, pos + 1, len);", condition, add, transition.target).unwrap();
+            }
+            writeln!(out, "                break;").unwrap();
+        }
+        writeln!(out, "            default: break;").unwrap();
+        writeln!(out, "            }}").unwrap();
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "        memcpy(current, next, sizeof current);").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out).unwrap();
+        
+        let mut accepting: Vec<usize> = nfa.accepting.iter().copied().collect();
+        accepting.sort_unstable();
+        let accepted: Vec<String> = accepting.iter().map(|id| format!("current[{}]", id)).collect();
+        writeln!(out, "    return {};", if accepted.is_empty() { "0".to_string() } else { accepted.join(" || ") }).unwrap();
+        writeln!(out, "}}").unwrap();
+        
+        out
+    }
+}
+
+impl Default for CGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Render a byte value as a C character constant where that reads better than a number
+fn c_char(ch: char) -> String {
+    match ch {
+        ' '..='~' if ch != '\'' && ch != '\\' => format!("'{}'", ch),
+        _ => (ch as u32).to_string(),
+    }
+}
+
+/// Build the C condition for `var` (a byte, or -1 at end of input) satisfying a predicate
+fn predicate_condition(predicate: &CharacterPredicate, var: &str) -> String {
+    let is_byte = |ch: &char| (*ch as u32) <= 0xFF;
+    let range = |start: char, end: char| {
+        if !is_byte(&start) {
+            return None;
+        }
+        let end = if is_byte(&end) { end } else { '\u{FF}' };
+        Some(if start == end {
+            format!("{} == {}", var, c_char(start))
+        } else {
+            format!("({} >= {} && {} <= {})", var, c_char(start), var, c_char(end))
+        })
+    };
+    let sorted = |set: &std::collections::HashSet<char>| {
+        let mut chars: Vec<char> = set.iter().copied().filter(is_byte).collect();
+        chars.sort_unstable();
+        chars
+    };
+    let any_of = |terms: Vec<String>| match terms.len() {
+        0 => "0".to_string(),
+        1 => terms.into_iter().next().unwrap_or_default(),
+        _ => format!("({})", terms.join(" || ")),
+    };
+    
+    match predicate {
+        CharacterPredicate::Any => format!("{} >= 0", var),
+        CharacterPredicate::Char(ch) => range(*ch, *ch).unwrap_or_else(|| "0".to_string()),
+        CharacterPredicate::CharSet(set) => {
+            any_of(sorted(set).into_iter().map(|ch| format!("{} == {}", var, c_char(ch))).collect())
+        },
+        // Like `TwoCharTransition::matches`, a negated set also accepts end of input
+        CharacterPredicate::NotCharSet(set) => {
+            let terms: Vec<String> = sorted(set).into_iter().map(|ch| format!("{} != {}", var, c_char(ch))).collect();
+            match terms.len() {
+                0 => "1".to_string(),
+                _ => format!("({})", terms.join(" && ")),
+            }
+        },
+        CharacterPredicate::EndOfInput => format!("{} < 0", var),
+        CharacterPredicate::Range(start, end) => range(*start, *end).unwrap_or_else(|| "0".to_string()),
+        CharacterPredicate::Ranges(ranges) => {
+            any_of(ranges.iter().filter_map(|&(start, end)| range(start, end)).collect())
+        },
+    }
+}
+
+/// Build the C condition for an assertion holding at `pos`
+fn assertion_condition(kind: &AssertionKind) -> String {
+    match kind {
+        AssertionKind::LookBehind { .. } => "0 /* lookbehind unsupported */".to_string(),
+        AssertionKind::StartText => "pos == 0".to_string(),
+        AssertionKind::StartLine { crlf: false } => "pos == 0 || input[pos - 1] == '\\n'".to_string(),
+        AssertionKind::StartLine { crlf: true } => {
+            "pos == 0 || input[pos - 1] == '\\n' || (input[pos - 1] == '\\r' && input[pos] != '\\n')".to_string()
+        },
+        AssertionKind::EndLine { crlf: false } => "pos == len || input[pos] == '\\n'".to_string(),
+        AssertionKind::EndLine { crlf: true } => {
+            "pos == len || input[pos] == '\\r' || (input[pos] == '\\n' && (pos == 0 || input[pos - 1] != '\\r'))".to_string()
+        },
+        AssertionKind::EndText { allow_trailing_newline: false } => "pos == len".to_string(),
+        AssertionKind::EndText { allow_trailing_newline: true } => {
+            "pos == len || (pos + 1 == len && input[pos] == '\\n')".to_string()
+        },
+        // Bytes are classified as ASCII, so other Unicode word characters don't count
+        AssertionKind::WordBoundary { negated, .. } => {
+            let is_word = |byte: &str| format!(
+                "(({b} >= '0' && {b} <= '9') || ({b} >= 'A' && {b} <= 'Z') || ({b} >= 'a' && {b} <= 'z') || {b} == '_')",
+                b = byte
+            );
+            format!(
+                "(pos > 0 && {}) {} (pos < len && {})",
+                is_word("input[pos - 1]"), if *negated { "==" } else { "!=" }, is_word("input[pos]")
+            )
+        },
+        AssertionKind::NotFollowedBy { sequences } => {
+            let followed: Vec<String> = sequences.iter()
+                .map(|sequence| {
+                    let terms: Vec<String> = sequence.iter()
+                        .enumerate()
+                        .map(|(offset, predicate)| {
+                            format!("pos + {} < len && {}", offset, predicate_condition(predicate, &format!("input[pos + {}]", offset)))
+                        })
+                        .collect();
+                    match terms.len() {
+                        0 => "1".to_string(),
+                        _ => format!("({})", terms.join(" && ")),
+                    }
+                })
+                .collect();
+            match followed.len() {
+                0 => "1".to_string(),
+                _ => format!("!({})", followed.join(" || ")),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_pattern;
+    
+    #[test]
+    fn test_generate_function() {
+        let nfa = compile_pattern("a[0-9]+|x$").unwrap();
+        let code = CGenerator::new().generate_function(&nfa, "match_token");
+        
+        assert!(code.contains("int match_token(const char *input)"));
+        assert!(code.contains("switch (state) {"));
+        assert!(code.contains("(c >= '0' && c <= '9')"));
+        assert!(code.contains("pos == len"));
+        for id in 2..nfa.states.len() {
+            if let State::Transitions { .. } = nfa.states[id] {
+                assert!(code.contains(&format!("case {}:", id)), "state {}", id);
+            }
+        }
+    }
+}
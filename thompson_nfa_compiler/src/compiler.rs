@@ -1,10 +1,19 @@
-use crate::{nfa::{NFA, Fragment, TwoCharTransition, StateId}, CompileError, CompileResult};
+use crate::{nfa::{AssertionKind, NFA, Fragment, QuantifierInfo, QuantifierKind, TwoCharTransition, StateId}, CompileError, CompileResult, CompileWarning};
 use regex_syntax::hir::{Hir, HirKind, RepetitionKind, Class, ClassBytes, ClassUnicode};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 /// Compiler that converts regex-syntax HIR to two-character Thompson NFA
 pub struct Compiler {
     nfa: NFA,
+    case_insensitive: bool,
+    trailing_newline_at_end: bool,
+    /// Most construction steps a compile may take, set by `with_step_budget`
+    step_budget: Option<usize>,
+    /// Construction steps taken so far
+    steps: usize,
+    warn_redundant_repetition: bool,
+    warnings: Vec<CompileWarning>,
+    utf8_bytes: bool,
 }
 
 impl Compiler {
@@ -12,38 +21,337 @@ impl Compiler {
     pub fn new() -> Self {
         Self {
             nfa: NFA::new(),
+            case_insensitive: false,
+            trailing_newline_at_end: true,
+            step_budget: None,
+            steps: 0,
+            warn_redundant_repetition: false,
+            warnings: Vec::new(),
+            utf8_bytes: false,
+        }
+    }
+    
+    /// Match letters case-insensitively
+    ///
+    /// Use this when the HIR was parsed with case folding disabled but the pattern is
+    /// meant to be case-insensitive. Every literal and class is widened to its simple case
+    /// folds before compiling, so lookaheads also accept the folded variants. Folds that
+    /// change the length, like `ß` to `SS`, aren't applied, and `compile_keywords` still
+    /// matches its words exactly.
+    pub fn case_insensitive(mut self, yes: bool) -> Self {
+        self.case_insensitive = yes;
+        self
+    }
+    
+    /// Let `$` also match just before a newline that ends the input
+    ///
+    /// On by default, so `abc$` matches both `"abc"` and `"abc\n"`. Turn it off for
+    /// `$` to match only at the very end of the input.
+    pub fn trailing_newline_at_end(mut self, yes: bool) -> Self {
+        self.trailing_newline_at_end = yes;
+        self
+    }
+    
+    /// Abort with `CompileError::TooComplex` after `steps` construction steps
+    ///
+    /// A step is compiling one HIR node, and a counted repetition like `a{1,100000}` takes
+    /// one per copy of its body. The check runs as each node is entered, so a pattern that
+    /// would spend a long time building states is stopped before it has built them.
+    pub fn with_step_budget(mut self, steps: usize) -> Self {
+        self.step_budget = Some(steps);
+        self
+    }
+    
+    /// Compile to an NFA over UTF-8 bytes instead of characters, for `Matcher::find_bytes`
+    ///
+    /// Literals become one transition per byte of their encoding, and each range of a
+    /// Unicode class is split into UTF-8 byte-range sequences, so `é` needs the two
+    /// transitions `\xC3` then `\xA9`. Possessive repetition is only supported over a
+    /// single ASCII character or class, since its lookahead sees one byte, and word
+    /// boundaries have to be the ASCII `(?-u:\b)` for the same reason.
+    pub fn utf8_bytes_mode(mut self, yes: bool) -> Self {
+        self.utf8_bytes = yes;
+        self
+    }
+    
+    /// Report unbounded repetitions of unbounded repetitions, like `(a*)*` or `(?:a+)*`
+    ///
+    /// These match the same strings as a single loop but add epsilon cycles that every
+    /// simulation step has to walk. The warnings come back from `compile_with_warnings`.
+    pub fn warn_redundant_repetition(mut self, yes: bool) -> Self {
+        self.warn_redundant_repetition = yes;
+        self
+    }
+    
+    /// Count one construction step against the budget
+    fn charge_step(&mut self) -> CompileResult<()> {
+        self.steps += 1;
+        match self.step_budget {
+            Some(budget) if self.steps > budget => Err(CompileError::TooComplex),
+            _ => Ok(()),
         }
     }
     
     /// Compile HIR to Thompson NFA with two-character transitions
-    pub fn compile(mut self, hir: &Hir) -> CompileResult<NFA> {
-        let fragment = self.compile_hir(hir)?;
+    pub fn compile(self, hir: &Hir) -> CompileResult<NFA> {
+        self.compile_with_warnings(hir).map(|(nfa, _)| nfa)
+    }
+    
+    /// Compile like `compile`, also returning any warnings the enabled checks raised
+    pub fn compile_with_warnings(mut self, hir: &Hir) -> CompileResult<(NFA, Vec<CompileWarning>)> {
+        let hir = self.fold_case(hir);
+        let fragment = self.compile_hir(&hir)?;
         
         // Set start state and ensure there's a match state
         self.nfa.start = fragment.start;
         let match_state = self.nfa.match_state();
         self.nfa.connect(fragment.end, match_state);
         
+        // A pattern ending in a loop or alternation ends on a join epsilon, which only
+        // forwards to the match state
+        self.nfa.bypass_epsilon(fragment.end);
+        
+        Ok((self.nfa, self.warnings))
+    }
+    
+    /// Compile several patterns into one NFA, tagging each accepting state with its pattern ID
+    ///
+    /// Pattern IDs follow the order of `hirs`. The first pattern keeps the reserved match
+    /// state; every other pattern gets its own accepting state.
+    pub fn compile_many(mut self, hirs: &[Hir]) -> CompileResult<NFA> {
+        let mut starts = Vec::new();
+        
+        for (pattern_id, hir) in hirs.iter().enumerate() {
+            let hir = self.fold_case(hir);
+            let fragment = self.compile_hir(&hir)?;
+            let match_state = if pattern_id == 0 {
+                self.nfa.match_state()
+            } else {
+                self.nfa.add_match_state(pattern_id)
+            };
+            self.nfa.connect(fragment.end, match_state);
+            starts.push(fragment.start);
+        }
+        
+        self.nfa.start = self.nfa.split(starts);
+        
+        Ok(self.nfa)
+    }
+    
+    /// Compile a list of literal keywords into a trie-shaped NFA
+    ///
+    /// Keywords sharing a prefix share the `Transitions` states for it, so scanning for
+    /// hundreds of keywords costs one state per distinct prefix rather than one chain per
+    /// word. Each keyword ends in an accepting state tagged with its index in `words`;
+    /// a repeated keyword keeps its first index. Where one keyword is a prefix of another,
+    /// the longer keyword takes priority in `Matcher::find`.
+    pub fn compile_keywords(mut self, words: &[&str]) -> CompileResult<NFA> {
+        let mut trie = vec![TrieNode::default()];
+        
+        for (pattern_id, word) in words.iter().enumerate() {
+            let mut node = 0;
+            for ch in word.chars() {
+                node = match trie[node].children.get(&ch) {
+                    Some(&child) => child,
+                    None => {
+                        trie.push(TrieNode::default());
+                        let child = trie.len() - 1;
+                        trie[node].children.insert(ch, child);
+                        child
+                    },
+                };
+            }
+            trie[node].pattern_id.get_or_insert(pattern_id);
+        }
+        
+        self.nfa.start = if words.is_empty() {
+            self.nfa.rejected_state()
+        } else {
+            self.compile_trie_node(&trie, 0)
+        };
+        
+        Ok(self.nfa)
+    }
+    
+    /// Compile a lexer spec of `(pattern, token ID)` pairs into one NFA for classifying tokens
+    ///
+    /// Each pattern is parsed and compiled, its accepting state tagged with the token ID,
+    /// and the patterns are unioned behind a shared start, so `Matcher::classify` reports
+    /// which token a whole input is. Specs may share an ID; when several tokens accept
+    /// the same input the lowest ID wins.
+    pub fn compile_lexer(mut self, specs: &[(&str, u32)]) -> CompileResult<NFA> {
+        let mut starts = Vec::new();
+        
+        for &(pattern, token_id) in specs {
+            let hir = regex_syntax::ParserBuilder::new()
+                .build()
+                .parse(pattern)
+                .map_err(|e| CompileError::Parse(e.to_string()))?;
+            let hir = self.fold_case(&hir);
+            let fragment = self.compile_hir(&hir)?;
+            let match_state = match token_id {
+                0 => self.nfa.match_state(),
+                _ => self.nfa.add_match_state(token_id as usize),
+            };
+            self.nfa.connect(fragment.end, match_state);
+            starts.push(fragment.start);
+        }
+        
+        self.nfa.start = if specs.is_empty() {
+            self.nfa.rejected_state()
+        } else {
+            self.nfa.split(starts)
+        };
+        
+        Ok(self.nfa)
+    }
+    
+    /// Widen the literals and classes of a pattern to their case folds in
+    /// case-insensitive mode, and leave it as it is otherwise
+    fn fold_case<'h>(&self, hir: &'h Hir) -> std::borrow::Cow<'h, Hir> {
+        if self.case_insensitive {
+            std::borrow::Cow::Owned(fold_case(hir))
+        } else {
+            std::borrow::Cow::Borrowed(hir)
+        }
+    }
+    
+    /// Emit the states for a trie node and everything below it, returning its entry state
+    fn compile_trie_node(&mut self, trie: &[TrieNode], node: usize) -> StateId {
+        let accept = trie[node].pattern_id.map(|pattern_id| match pattern_id {
+            0 => self.nfa.match_state(),
+            _ => self.nfa.add_match_state(pattern_id),
+        });
+        if trie[node].children.is_empty() {
+            return accept.unwrap_or_else(|| self.nfa.rejected_state());
+        }
+        
+        let transitions = trie[node].children.iter()
+            .map(|(&ch, &child)| TwoCharTransition::char(ch, self.compile_trie_node(trie, child)))
+            .collect();
+        let next = self.nfa.transitions_state(transitions);
+        
+        match accept {
+            // Continue first so the longer keyword wins
+            Some(accept) => self.nfa.split(vec![next, accept]),
+            None => next,
+        }
+    }
+    
+    /// Compile `hir` guarded by a lookbehind: it only matches where `behind` matches the
+    /// characters just before the match start
+    ///
+    /// regex-syntax can't parse `(?<=...)`, so the lookbehind body is passed separately.
+    /// The body must have a fixed width, since it is checked against exactly that many
+    /// already-consumed characters.
+    pub fn compile_lookbehind(mut self, behind: &Hir, hir: &Hir) -> CompileResult<NFA> {
+        let body = Compiler::new()
+            .case_insensitive(self.case_insensitive)
+            .trailing_newline_at_end(self.trailing_newline_at_end)
+            .compile(behind)?;
+        let width = match body.match_length_bounds() {
+            (min, Some(max)) if min == max => min,
+            _ => return Err(CompileError::UnsupportedFeature("variable-width lookbehind".to_string())),
+        };
+        
+        let hir = self.fold_case(hir);
+        let fragment = self.compile_hir(&hir)?;
+        self.nfa.start = self.nfa.assertion(AssertionKind::LookBehind { body: Box::new(body), width }, fragment.start);
+        let match_state = self.nfa.match_state();
+        self.nfa.connect(fragment.end, match_state);
+        
         Ok(self.nfa)
     }
     
     /// Compile an HIR node to an NFA fragment
     fn compile_hir(&mut self, hir: &Hir) -> CompileResult<Fragment> {
+        self.charge_step()?;
+        
         match hir.kind() {
             HirKind::Empty => Ok(self.compile_empty()),
             HirKind::Literal(literal) => self.compile_literal(literal),
             HirKind::Class(class) => self.compile_class(class),
+            HirKind::Look(regex_syntax::hir::Look::Start) => Ok(self.compile_start_text()),
+            HirKind::Look(regex_syntax::hir::Look::End) => Ok(self.compile_end_text()),
+            HirKind::Look(regex_syntax::hir::Look::StartLF) => Ok(self.compile_line_anchor(AssertionKind::StartLine { crlf: false })),
+            HirKind::Look(regex_syntax::hir::Look::EndLF) => Ok(self.compile_line_anchor(AssertionKind::EndLine { crlf: false })),
+            HirKind::Look(regex_syntax::hir::Look::StartCRLF) => Ok(self.compile_line_anchor(AssertionKind::StartLine { crlf: true })),
+            HirKind::Look(regex_syntax::hir::Look::EndCRLF) => Ok(self.compile_line_anchor(AssertionKind::EndLine { crlf: true })),
+            HirKind::Look(regex_syntax::hir::Look::WordAscii) => self.compile_word_boundary(true, false),
+            HirKind::Look(regex_syntax::hir::Look::WordAsciiNegate) => self.compile_word_boundary(true, true),
+            HirKind::Look(regex_syntax::hir::Look::WordUnicode) => self.compile_word_boundary(false, false),
+            HirKind::Look(regex_syntax::hir::Look::WordUnicodeNegate) => self.compile_word_boundary(false, true),
             HirKind::Look(_) => Err(CompileError::UnsupportedFeature("lookarounds not yet implemented".to_string())),
             HirKind::Repetition(rep) => self.compile_repetition(rep),
-            HirKind::Capture(capture) => self.compile_hir(&capture.sub),
+            // Scoped flags like `(?i:...)` are already folded into the group's classes by
+            // regex-syntax, so compiling the body compiles each class with its own predicate
+            HirKind::Capture(capture) => self.compile_capture(capture),
             HirKind::Concat(concat) => self.compile_concat(concat),
             HirKind::Alternation(alternation) => self.compile_alternation(alternation),
         }
     }
     
+    /// Compile a capture group, wrapping its body in slot-recording epsilon states
+    fn compile_capture(&mut self, capture: &regex_syntax::hir::Capture) -> CompileResult<Fragment> {
+        let body = self.compile_hir(&capture.sub)?;
+        Ok(self.wrap_capture(capture, body))
+    }
+    
+    /// Wrap an already compiled body in a capture group's slot-recording epsilon states
+    fn wrap_capture(&mut self, capture: &regex_syntax::hir::Capture, body: Fragment) -> Fragment {
+        let index = capture.index as usize;
+        if self.nfa.group_names.len() <= index {
+            self.nfa.group_names.resize(index + 1, None);
+        }
+        self.nfa.group_names[index] = capture.name.as_deref().map(str::to_string);
+        
+        let open = self.nfa.epsilon(body.start);
+        let close = self.nfa.epsilon(usize::MAX);
+        self.nfa.connect(body.end, close);
+        self.nfa.capture_slots.insert(open, 2 * index);
+        self.nfa.capture_slots.insert(close, 2 * index + 1);
+        
+        Fragment { start: open, end: close }
+    }
+    
+    /// Compile a `^` anchor to a start-of-text assertion
+    fn compile_start_text(&mut self) -> Fragment {
+        let state = self.nfa.assertion(AssertionKind::StartText, usize::MAX);
+        Fragment { start: state, end: state }
+    }
+    
+    /// Compile a multi-line `^` or `$` anchor to its line assertion
+    fn compile_line_anchor(&mut self, kind: AssertionKind) -> Fragment {
+        let state = self.nfa.assertion(kind, usize::MAX);
+        Fragment { start: state, end: state }
+    }
+    
+    /// Compile `\b` or `\B` to a word boundary assertion
+    /// Compile `\b` or `\B` to a word boundary assertion
+    ///
+    /// In UTF-8 byte mode the assertion sees single bytes, which only classify ASCII
+    /// correctly, so Unicode word boundaries are unsupported there.
+    fn compile_word_boundary(&mut self, ascii: bool, negated: bool) -> CompileResult<Fragment> {
+        if self.utf8_bytes && !ascii {
+            return Err(CompileError::UnsupportedFeature("Unicode word boundary in UTF-8 byte mode, use (?-u:\\b)".to_string()));
+        }
+        let state = self.nfa.assertion(AssertionKind::WordBoundary { ascii, negated }, usize::MAX);
+        Ok(Fragment { start: state, end: state })
+    }
+    
+    /// Compile a `$` anchor to an end-of-text assertion
+    fn compile_end_text(&mut self) -> Fragment {
+        let kind = AssertionKind::EndText { allow_trailing_newline: self.trailing_newline_at_end };
+        let state = self.nfa.assertion(kind, usize::MAX);
+        Fragment { start: state, end: state }
+    }
+    
     /// Compile empty match
+    ///
+    /// The epsilon is left unpatched like any other fragment end, so nothing treats it as
+    /// accepting before it is connected to whatever follows.
     fn compile_empty(&mut self) -> Fragment {
-        let start = self.nfa.epsilon(0); // Will be patched
+        let start = self.nfa.epsilon(usize::MAX);
         Fragment { start, end: start }
     }
     
@@ -54,8 +362,8 @@ impl Compiler {
         // Convert bytes to string - this assumes UTF-8 for simplicity
         // In a production implementation, you'd want proper UTF-8 handling
         let chars: Vec<char> = match std::str::from_utf8(bytes) {
-            Ok(s) => s.chars().collect(),
-            Err(_) => {
+            Ok(s) if !self.utf8_bytes => s.chars().collect(),
+            _ => {
                 // For non-UTF8 bytes, convert each byte to a char
                 bytes.iter().map(|&byte| byte as char).collect()
             }
@@ -87,6 +395,10 @@ impl Compiler {
     
     /// Compile character class
     fn compile_class(&mut self, class: &Class) -> CompileResult<Fragment> {
+        if let (Class::Unicode(class_unicode), true) = (class, self.utf8_bytes) {
+            return Ok(self.compile_utf8_class(class_unicode));
+        }
+        
         let transitions = match class {
             Class::Unicode(class_unicode) => self.compile_unicode_class(class_unicode)?,
             Class::Bytes(class_bytes) => self.compile_bytes_class(class_bytes)?,
@@ -98,8 +410,6 @@ impl Compiler {
     
     /// Compile Unicode character class
     fn compile_unicode_class(&mut self, class: &ClassUnicode) -> CompileResult<Vec<TwoCharTransition>> {
-        let mut transitions = Vec::new();
-        
         // Check if this is a negated class (heuristic: very large ranges suggest negation)
         let total_chars: u32 = class.iter().map(|range| {
             (range.end() as u32) - (range.start() as u32) + 1
@@ -111,124 +421,76 @@ impl Compiler {
             return self.compile_negated_unicode_class(class);
         }
         
-        for range in class.iter() {
-            let start_char = range.start();
-            let end_char = range.end();
-            
-            // For reasonable ranges, create individual transitions
-            if (end_char as u32) - (start_char as u32) <= 1000 {
-                for ch_code in (start_char as u32)..=(end_char as u32) {
-                    if let Some(ch) = char::from_u32(ch_code) {
-                        transitions.push(TwoCharTransition::char(ch, usize::MAX));
-                    }
-                }
-            } else {
-                // For large ranges, sample a few characters as examples
-                if let Some(ch) = char::from_u32(start_char as u32) {
-                    transitions.push(TwoCharTransition::char(ch, usize::MAX));
-                }
-                if let Some(ch) = char::from_u32(end_char as u32) {
-                    transitions.push(TwoCharTransition::char(ch, usize::MAX));
-                }
-                // Add a few characters in between
-                let mid1 = start_char as u32 + (end_char as u32 - start_char as u32) / 3;
-                let mid2 = start_char as u32 + 2 * (end_char as u32 - start_char as u32) / 3;
-                if let Some(ch) = char::from_u32(mid1) {
-                    transitions.push(TwoCharTransition::char(ch, usize::MAX));
-                }
-                if let Some(ch) = char::from_u32(mid2) {
-                    transitions.push(TwoCharTransition::char(ch, usize::MAX));
-                }
-            }
-        }
-        
-        Ok(transitions)
+        // Ranges are kept as bounds rather than expanded into sets of characters
+        let ranges = class.iter().map(|range| (range.start(), range.end())).collect();
+        Ok(range_transition(ranges).into_iter().collect())
     }
     
-    /// Compile negated Unicode character class using rejection states
+    /// Compile negated Unicode character class as a single transition
     fn compile_negated_unicode_class(&mut self, class: &ClassUnicode) -> CompileResult<Vec<TwoCharTransition>> {
-        // The class represents large ranges that are the result of negation by regex-syntax
-        // We need to re-negate to find what characters should be REJECTED
-        
-        let rejected_state = self.nfa.rejected_state();
-        let mut transitions = Vec::new();
-        
-        // Find the "gaps" in the large ranges - these are the characters that were originally negated
-        let mut rejected_chars = HashSet::new();
-        
-        // Check for gaps between ranges and at the boundaries
-        let ranges: Vec<_> = class.iter().collect();
-        
-        // Check gap before first range
-        if let Some(first_range) = ranges.first() {
-            if first_range.start() > '\u{0}' {
-                // Add characters from start to first range
-                for ch_code in 0..(first_range.start() as u32) {
-                    if let Some(ch) = char::from_u32(ch_code) {
-                        rejected_chars.insert(ch);
-                    }
-                    // Limit to avoid huge sets
-                    if rejected_chars.len() > 200 { break; }
-                }
-            }
-        }
+        // The class represents large ranges that are the result of negation by regex-syntax.
+        // The excluded set is exactly the gaps between those ranges, so a class like `[^a]`
+        // keeps matching `\n` while `.` (which regex-syntax lowers to `[^\n]` unless DOTALL
+        // is set) excludes it. Routing the gaps to the rejected state instead would not work,
+        // since every matching transition fires in the NFA simulation.
+        let predicate = self.handle_large_unicode_class(class)?;
+        
+        Ok(vec![TwoCharTransition::predicate(predicate, None, usize::MAX)])
+    }
+    
+    /// Compile a Unicode class to the UTF-8 byte sequences of its characters
+    ///
+    /// Each sequence becomes its own chain of byte-range transitions, entered from one
+    /// shared state and ending on a shared epsilon.
+    fn compile_utf8_class(&mut self, class: &ClassUnicode) -> Fragment {
+        let end = self.nfa.epsilon(usize::MAX);
+        let byte_range = |range: &regex_syntax::utf8::Utf8Range, target: StateId| {
+            let current = match (range.start as char, range.end as char) {
+                (start, end) if start == end => crate::nfa::CharacterPredicate::Char(start),
+                (start, end) => crate::nfa::CharacterPredicate::Range(start, end),
+            };
+            TwoCharTransition::predicate(current, None, target)
+        };
         
-        // Check gaps between consecutive ranges  
-        for i in 0..ranges.len().saturating_sub(1) {
-            let current_end = ranges[i].end() as u32;
-            let next_start = ranges[i + 1].start() as u32;
-            
-            // If there's a gap, those characters should be rejected
-            if next_start > current_end + 1 {
-                for ch_code in (current_end + 1)..next_start {
-                    if let Some(ch) = char::from_u32(ch_code) {
-                        rejected_chars.insert(ch);
-                    }
-                    // Limit to avoid huge sets
-                    if rejected_chars.len() > 200 { break; }
+        let mut entries = Vec::new();
+        for range in class.iter() {
+            for sequence in regex_syntax::utf8::Utf8Sequences::new(range.start(), range.end()) {
+                let ranges = sequence.as_slice();
+                // Build the chain back to front so each state knows its target
+                let mut target = end;
+                for range in ranges[1..].iter().rev() {
+                    target = self.nfa.transition_state(byte_range(range, target));
                 }
+                entries.push(byte_range(&ranges[0], target));
             }
-            
-            if rejected_chars.len() > 200 { break; }
-        }
-        
-        // Create rejection transitions for the characters that should be rejected
-        for &ch in &rejected_chars {
-            transitions.push(TwoCharTransition {
-                current: crate::nfa::CharacterPredicate::Char(ch),
-                lookahead: None,
-                target: rejected_state,
-            });
         }
         
-        // Add a default "dot" transition that accepts any other character
-        transitions.push(TwoCharTransition::dot(usize::MAX)); // Will be patched to actual target
-        
-        Ok(transitions)
+        let start = self.nfa.transitions_state(entries);
+        Fragment { start, end }
     }
     
     /// Compile bytes character class  
     fn compile_bytes_class(&mut self, class: &ClassBytes) -> CompileResult<Vec<TwoCharTransition>> {
-        let mut transitions = Vec::new();
-        
-        for range in class.iter() {
-            let start_byte = range.start();
-            let end_byte = range.end();
-            
-            for byte in start_byte..=end_byte {
-                transitions.push(TwoCharTransition::char(byte as char, usize::MAX));
-            }
-        }
+        let ranges = class.iter()
+            .map(|range| (range.start() as char, range.end() as char))
+            .collect();
         
-        Ok(transitions)
+        Ok(range_transition(ranges).into_iter().collect())
     }
     
     /// Compile concatenation using pairwise strategy
+    ///
+    /// No normalization pass is needed first: `Hir::concat` and `Hir::alternation` already
+    /// flatten nested concatenations and alternations and unwrap singletons, and
+    /// non-capturing groups leave no node behind, so `(?:(?:a))` reaches the compiler as
+    /// the literal `a`.
     fn compile_concat(&mut self, concat: &[Hir]) -> CompileResult<Fragment> {
         if concat.is_empty() {
             return Ok(self.compile_empty());
         }
         
+        // A lone element has no follower to guard against, so it compiles exactly like
+        // the first half of a pair (possessives included) before any exit guarding
         if concat.len() == 1 {
             return self.compile_hir(&concat[0]);
         }
@@ -270,6 +532,13 @@ impl Compiler {
         let first_is_lookahead = self.is_lookahead(first);
         let second_is_lookahead = self.is_lookahead(second);
         
+        // Anchors compile to assertion states of their own, so they need no lookahead edges
+        // (a possessive before `$` still uses its exit constraint below)
+        if is_anchor(first) || (is_anchor(second) && !(first_is_possessive && is_end_text(second))) {
+            let fragment = self.compile_single(first)?;
+            return Ok((fragment, 1));
+        }
+        
         // Rule: First element may not be a lookahead
         if first_is_lookahead {
             return Err(CompileError::UnsupportedFeature("first element cannot be lookahead".to_string()));
@@ -283,7 +552,7 @@ impl Compiler {
                     let fragment = self.compile_possessive_with_disjoint_lookahead(first, second)?;
                     Ok((fragment, 2)) // Both elements consumed
                 } else {
-                    return Err(CompileError::UnsupportedFeature("possessive with overlapping lookahead not supported".to_string()));
+                    Err(CompileError::UnsupportedFeature("possessive with overlapping lookahead not supported".to_string()))
                 }
             } else {
                 // Compile first element with lookahead from second element
@@ -291,10 +560,32 @@ impl Compiler {
                 Ok((fragment, 2)) // Both elements consumed
             }
         } else if first_is_possessive {
-            // Possessive quantifiers should be compiled standalone, not in pairwise fashion
-            // Compile first element normally (it will create proper possessive structure)
-            // and ignore second element for now (consume only first)
+            // An unbounded possessive swallows every character the follower could start
+            // with, so the pair could never match. A follower that only shares some of its
+            // characters with the loop can still start with one of the others.
+            let atom = self.possessive_atom_predicate(first);
+            let follower = self.first_char_predicate(second);
+            if let (Some(atom), Some(follower)) = (&atom, &follower) {
+                if !follower.intersects(&atom.negate()) {
+                    return Err(CompileError::UnsupportedFeature("possessive followed by a character it consumes never matches".to_string()));
+                }
+            }
+            
+            // The possessive is compiled standalone and the follower is left for the next
+            // pair, but the possessive may only exit when the next character can start the
+            // follower. This keeps threads from leaving the loop just to die one step later.
+            // A single-character loop only leaves on a character it doesn't take, so its
+            // exits are guarded with the part of the follower outside the atom. A loop over
+            // a longer body leaves through an assertion, and its last transitions also
+            // continue the loop, so it is left alone.
             let fragment = self.compile_single(first)?;
+            if let Some(follower) = follower.filter(|_| !loops_over_longer_body(first)) {
+                let follower = match &atom {
+                    Some(atom) => follower.intersection(&atom.negate()),
+                    None => follower,
+                };
+                self.guard_possessive_exits(&fragment, &follower);
+            }
             Ok((fragment, 1)) // Only first element consumed
         } else {
             // Normal case: compile first element normally, ignore second character
@@ -303,6 +594,21 @@ impl Compiler {
         }
     }
     
+    /// Check whether an HIR node can match the empty string
+    ///
+    /// Looks count as nullable since they never consume input.
+    pub fn is_nullable(hir: &Hir) -> bool {
+        match hir.kind() {
+            HirKind::Empty | HirKind::Look(_) => true,
+            HirKind::Literal(literal) => literal.0.is_empty(),
+            HirKind::Class(_) => false,
+            HirKind::Repetition(rep) => rep.min == 0 || Self::is_nullable(&rep.sub),
+            HirKind::Capture(capture) => Self::is_nullable(&capture.sub),
+            HirKind::Concat(concat) => concat.iter().all(Self::is_nullable),
+            HirKind::Alternation(alternation) => alternation.iter().any(Self::is_nullable),
+        }
+    }
+    
     /// Compile a single HIR element normally
     fn compile_single(&mut self, hir: &Hir) -> CompileResult<Fragment> {
         self.compile_hir(hir)
@@ -332,32 +638,46 @@ impl Compiler {
     }
     
     /// Check if possessive pattern and lookahead are disjoint
+    ///
+    /// Anchors never consume a character, so they are always disjoint. A character
+    /// follower is disjoint when its first character can't be one the possessive
+    /// repeats; when either side's character isn't known this can't be shown, and the
+    /// answer is `false`.
     fn is_disjoint_lookahead(&self, possessive: &Hir, lookahead: &Hir) -> CompileResult<bool> {
-        match lookahead.kind() {
-            HirKind::Look(look) => {
-                // Anchors (^, $, \b, etc.) are always disjoint from character patterns
-                match look {
-                    regex_syntax::hir::Look::Start |
-                    regex_syntax::hir::Look::End |
-                    regex_syntax::hir::Look::StartLF |
-                    regex_syntax::hir::Look::EndLF |
-                    regex_syntax::hir::Look::StartCRLF |
-                    regex_syntax::hir::Look::EndCRLF => Ok(true), // Anchors are always disjoint
-                    _ => Ok(true), // Other lookarounds are also disjoint from character patterns
-                }
+        if let HirKind::Look(_) = lookahead.kind() {
+            return Ok(true);
+        }
+        
+        match (self.possessive_atom_predicate(possessive), self.first_char_predicate(lookahead)) {
+            (Some(atom), Some(follower)) => Ok(!atom.intersects(&follower)),
+            _ => Ok(false),
+        }
+    }
+    
+    /// Get the predicate of the single character an unbounded possessive repeats, if the
+    /// repeated element is one character
+    fn possessive_atom_predicate(&self, hir: &Hir) -> Option<crate::nfa::CharacterPredicate> {
+        let HirKind::Repetition(rep) = hir.kind() else {
+            return None;
+        };
+        if rep.max.is_some() {
+            return None;
+        }
+        
+        match rep.sub.kind() {
+            HirKind::Literal(literal) => {
+                let single = std::str::from_utf8(&literal.0).map_or(literal.0.len() == 1, |s| s.chars().count() == 1);
+                if single { self.first_char_predicate(&rep.sub) } else { None }
             },
-            _ => {
-                // For character-based lookaheads, we'd need to check if the sets overlap
-                // For now, assume non-anchor lookaheads might overlap
-                Ok(false)
-            }
+            HirKind::Class(_) => self.first_char_predicate(&rep.sub),
+            _ => None,
         }
     }
     
     /// Compile possessive quantifier with disjoint lookahead by adding lookahead to outgoing edges
     fn compile_possessive_with_disjoint_lookahead(&mut self, possessive: &Hir, lookahead: &Hir) -> CompileResult<Fragment> {
         // First compile the possessive quantifier normally
-        let possessive_fragment = self.compile_hir(possessive)?;
+        let mut possessive_fragment = self.compile_hir(possessive)?;
         
         // For disjoint lookaheads (like anchors), we need to modify the outgoing transitions
         // to include the lookahead constraint on the exit transitions
@@ -367,9 +687,16 @@ impl Compiler {
                     regex_syntax::hir::Look::End |
                     regex_syntax::hir::Look::EndLF |
                     regex_syntax::hir::Look::EndCRLF => {
-                        // For end anchors, we need to add end-of-input constraint to exit transitions
-                        // This means the possessive pattern can only exit when at end of input
-                        self.add_end_anchor_constraint_to_exits(possessive_fragment.start, possessive_fragment.end)?;
+                        // The possessive run may only exit where the anchor can hold. A loop over
+                        // a longer body already stops exactly where its body can't match, so it
+                        // only needs the anchor itself.
+                        possessive_fragment = if loops_over_longer_body(possessive) {
+                            let anchor = self.compile_hir(lookahead)?;
+                            self.nfa.connect(possessive_fragment.end, anchor.start);
+                            Fragment { start: possessive_fragment.start, end: anchor.end }
+                        } else {
+                            self.add_end_anchor_constraint_to_exits(possessive_fragment, lookahead)?
+                        };
                     },
                     regex_syntax::hir::Look::Start |
                     regex_syntax::hir::Look::StartLF |
@@ -393,59 +720,164 @@ impl Compiler {
         Ok(possessive_fragment)
     }
     
-    /// Add end-of-input constraint to exit transitions from a possessive pattern
-    fn add_end_anchor_constraint_to_exits(&mut self, start: StateId, end: StateId) -> CompileResult<()> {
-        // For possessive patterns with $ lookahead, the exit transitions should only
-        // succeed when at end of input. This is represented by not having valid
-        // character transitions - the pattern can only match when followed by end-of-input.
+    /// Restrict the exit transitions of a possessive fragment to the end of the input
+    ///
+    /// Each exit is split into one that needs end of input next and, when `$` may match
+    /// before a trailing newline, one that needs a `\n` next. The lookaheads only stop the
+    /// run from leaving early; the returned fragment still ends in the `$` assertion, which
+    /// checks that the newline is the last character.
+    fn add_end_anchor_constraint_to_exits(&mut self, fragment: Fragment, anchor: &Hir) -> CompileResult<Fragment> {
+        use crate::nfa::{CharacterPredicate, State};
         
-        // Find states that can reach the end state and modify their transitions
         let mut visited = HashSet::new();
-        let mut stack = vec![start];
-        let mut exit_states = Vec::new();
+        let mut stack = vec![fragment.start];
         
-        // Find all states that can reach the end state
         while let Some(state_id) = stack.pop() {
             if !visited.insert(state_id) || state_id >= self.nfa.states.len() {
                 continue;
             }
             
-            match &self.nfa.states[state_id] {
-                crate::nfa::State::Transitions { transitions } => {
-                    for transition in transitions {
-                        if transition.target == end {
-                            exit_states.push(state_id);
-                        } else {
-                            stack.push(transition.target);
-                        }
-                    }
-                },
-                crate::nfa::State::Epsilon { next } => {
-                    if *next == end {
-                        exit_states.push(state_id);
-                    } else {
-                        stack.push(*next);
-                    }
+            let transitions = match &self.nfa.states[state_id] {
+                State::Transitions { transitions } => transitions.clone(),
+                State::Epsilon { next } | State::Assertion { next, .. } => {
+                    stack.push(*next);
+                    continue;
                 },
-                crate::nfa::State::Split { targets } => {
-                    for &target in targets {
-                        if target == end {
-                            exit_states.push(state_id);
-                        } else {
-                            stack.push(target);
-                        }
-                    }
+                State::Split { targets } => {
+                    stack.extend(targets.iter().copied());
+                    continue;
                 },
-                _ => {}
+                _ => continue,
+            };
+            stack.extend(transitions.iter().map(|t| t.target));
+            
+            let mut constrained = Vec::with_capacity(transitions.len());
+            for transition in transitions {
+                if !self.reaches_without_consuming(transition.target, fragment.end) {
+                    constrained.push(transition);
+                    continue;
+                }
+                let accepts_end = match &transition.lookahead {
+                    None => true,
+                    Some(lookahead) => matches!(lookahead, CharacterPredicate::NotCharSet(_) | CharacterPredicate::EndOfInput),
+                };
+                let accepts_newline = self.trailing_newline_at_end
+                    && transition.lookahead.as_ref().is_none_or(|lookahead| lookahead.matches('\n'));
+                
+                if accepts_end {
+                    constrained.push(TwoCharTransition { lookahead: Some(CharacterPredicate::EndOfInput), ..transition.clone() });
+                }
+                if accepts_newline {
+                    constrained.push(TwoCharTransition { lookahead: Some(CharacterPredicate::Char('\n')), ..transition.clone() });
+                }
+                if !accepts_end && !accepts_newline {
+                    constrained.push(TwoCharTransition { lookahead: Some(CharacterPredicate::CharSet(HashSet::new())), ..transition });
+                }
             }
+            self.nfa.states[state_id] = State::Transitions { transitions: constrained };
         }
         
-        // For possessive + end anchor, the pattern should only succeed at end of input
-        // We implement this by ensuring there are no character transitions that can continue
-        // This is a simplified implementation - in a full implementation you'd want to
-        // add explicit end-of-input checking logic
+        let anchor = self.compile_hir(anchor)?;
+        self.nfa.connect(fragment.end, anchor.start);
+        Ok(Fragment { start: fragment.start, end: anchor.end })
+    }
+    
+    /// Get the predicate for the first character an element must consume, if it is a single atom
+    fn first_char_predicate(&self, hir: &Hir) -> Option<crate::nfa::CharacterPredicate> {
+        use crate::nfa::CharacterPredicate;
         
-        Ok(())
+        match hir.kind() {
+            HirKind::Literal(literal) => {
+                let ch = match std::str::from_utf8(&literal.0) {
+                    Ok(s) if !self.utf8_bytes => s.chars().next()?,
+                    _ => *literal.0.first()? as char,
+                };
+                Some(CharacterPredicate::Char(ch))
+            },
+            // In UTF-8 byte mode only an ASCII class is also the predicate on its first byte
+            HirKind::Class(_) if self.utf8_bytes && hir.properties().maximum_len() != Some(1) => None,
+            HirKind::Class(_) => self.hir_to_predicate(hir).ok(),
+            _ => None,
+        }
+    }
+    
+    /// Restrict the exit transitions of a possessive fragment to a follower's first characters
+    fn guard_possessive_exits(&mut self, fragment: &Fragment, follower: &crate::nfa::CharacterPredicate) {
+        use crate::nfa::{CharacterPredicate, State};
+        
+        // Only finite follower sets can be intersected with the exit lookahead here
+        let follower_chars: HashSet<char> = match follower {
+            CharacterPredicate::Char(ch) => std::iter::once(*ch).collect(),
+            CharacterPredicate::CharSet(set) => set.clone(),
+            _ => return,
+        };
+        
+        let mut visited = HashSet::new();
+        let mut stack = vec![fragment.start];
+        
+        while let Some(state_id) = stack.pop() {
+            if !visited.insert(state_id) || state_id >= self.nfa.states.len() {
+                continue;
+            }
+            
+            let exits: Vec<usize> = match &self.nfa.states[state_id] {
+                State::Transitions { transitions } => {
+                    stack.extend(transitions.iter().map(|t| t.target));
+                    transitions.iter().enumerate()
+                        .filter(|(_, t)| self.reaches_without_consuming(t.target, fragment.end))
+                        .map(|(i, _)| i)
+                        .collect()
+                },
+                State::Epsilon { next } | State::Assertion { next, .. } => {
+                    stack.push(*next);
+                    Vec::new()
+                },
+                State::Split { targets } => {
+                    stack.extend(targets.iter().copied());
+                    Vec::new()
+                },
+                _ => Vec::new(),
+            };
+            
+            if let State::Transitions { transitions } = &mut self.nfa.states[state_id] {
+                for i in exits {
+                    let transition = &mut transitions[i];
+                    // Keep follower characters the exit's own lookahead would also accept
+                    let guarded: HashSet<char> = follower_chars.iter()
+                        .copied()
+                        .filter(|&ch| transition.lookahead.as_ref().is_none_or(|la| la.matches(ch)))
+                        .collect();
+                    transition.lookahead = Some(CharacterPredicate::CharSet(guarded));
+                }
+            }
+        }
+    }
+    
+    /// Collect the states reachable from `from` through epsilon and split states, without
+    /// passing any assertion
+    fn closure_without_assertions(&self, from: StateId) -> HashSet<StateId> {
+        use crate::nfa::State;
+        
+        let mut closure = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(state_id) = stack.pop() {
+            if state_id >= self.nfa.states.len() || !closure.insert(state_id) {
+                continue;
+            }
+            match &self.nfa.states[state_id] {
+                State::Epsilon { next } => stack.push(*next),
+                State::Split { targets } => stack.extend(targets.iter().copied()),
+                _ => {},
+            }
+        }
+        closure
+    }
+    
+    /// Check whether `to` is reachable from `from` through epsilon and split states only
+    fn reaches_without_consuming(&self, from: StateId, to: StateId) -> bool {
+        let mut start = HashSet::new();
+        start.insert(from);
+        self.nfa.epsilon_closure(&start).contains(&to)
     }
     
     /// Compile first element with lookahead constraints from second element
@@ -460,16 +892,56 @@ impl Compiler {
         Ok(base_fragment)
     }
     
-    /// Extract characters that the lookahead element would match
+    /// Extract the characters the lookahead element could start with
+    ///
+    /// Case-insensitive patterns are folded before they get here, so the characters
+    /// already include the same simple case folds the element consumes.
+    ///
+    /// Groups, concatenations and repetitions that must match at least once are
+    /// descended into; an alternation contributes the union of its branches' sets.
     fn extract_lookahead_chars(&self, hir: &Hir) -> CompileResult<Vec<char>> {
         match hir.kind() {
             HirKind::Literal(literal) => {
                 let bytes = &literal.0;
-                match std::str::from_utf8(bytes) {
-                    Ok(s) => Ok(s.chars().collect()),
-                    Err(_) => Ok(bytes.iter().map(|&b| b as char).collect()),
+                let first = match std::str::from_utf8(bytes) {
+                    Ok(s) => s.chars().next(),
+                    Err(_) => bytes.first().map(|&b| b as char),
+                };
+                Ok(first.into_iter().collect())
+            },
+            HirKind::Capture(capture) => self.extract_lookahead_chars(&capture.sub),
+            HirKind::Concat(concat) => {
+                // Leading elements that can match empty also let the next element start
+                let mut chars = Vec::new();
+                for sub in concat {
+                    let sub_chars = match sub.kind() {
+                        HirKind::Empty => Vec::new(),
+                        HirKind::Repetition(rep) => self.extract_lookahead_chars(&rep.sub)?,
+                        _ => self.extract_lookahead_chars(sub)?,
+                    };
+                    for ch in sub_chars {
+                        if !chars.contains(&ch) {
+                            chars.push(ch);
+                        }
+                    }
+                    if !Self::is_nullable(sub) {
+                        return Ok(chars);
+                    }
+                }
+                Err(CompileError::UnsupportedFeature("lookahead pattern can match empty".to_string()))
+            },
+            HirKind::Alternation(alternation) => {
+                let mut chars = Vec::new();
+                for branch in alternation {
+                    for ch in self.extract_lookahead_chars(branch)? {
+                        if !chars.contains(&ch) {
+                            chars.push(ch);
+                        }
+                    }
                 }
+                Ok(chars)
             },
+            HirKind::Repetition(rep) if rep.min > 0 => self.extract_lookahead_chars(&rep.sub),
             HirKind::Class(class) => {
                 match class {
                     Class::Unicode(class_unicode) => {
@@ -485,8 +957,6 @@ impl Compiler {
                                     }
                                 }
                             } else {
-                                print!("{:?}", class);
-                                print!("{:?}", (end_char as u32) - (start_char as u32));
                                 return Err(CompileError::UnsupportedFeature("large character ranges in lookahead".to_string()));
                             }
                         }
@@ -519,25 +989,21 @@ impl Compiler {
             
             match &mut self.nfa.states[state_id] {
                 crate::nfa::State::Transitions { transitions } => {
-                    // Augment each transition with all possible lookahead characters
-                    let mut new_transitions = Vec::new();
-                    for transition in transitions.iter() {
-                        if lookahead_chars.is_empty() {
-                            // No specific lookahead - keep original
-                            new_transitions.push(transition.clone());
-                        } else {
-                            // Create one transition for each lookahead character
-                            for &lookahead_char in lookahead_chars {
-                                let mut new_trans = transition.clone();
-                                new_trans.lookahead = Some(crate::nfa::CharacterPredicate::Char(lookahead_char));
-                                new_transitions.push(new_trans);
-                            }
+                    // Augment each transition with one predicate covering every lookahead
+                    // character (no specific lookahead keeps the transition as it is)
+                    let lookahead = match lookahead_chars {
+                        [] => None,
+                        [ch] => Some(crate::nfa::CharacterPredicate::Char(*ch)),
+                        chars => Some(crate::nfa::CharacterPredicate::CharSet(chars.iter().copied().collect())),
+                    };
+                    for transition in transitions.iter_mut() {
+                        if lookahead.is_some() {
+                            transition.lookahead = lookahead.clone();
                         }
                         stack.push(transition.target);
                     }
-                    *transitions = new_transitions;
                 },
-                crate::nfa::State::Epsilon { next } => {
+                crate::nfa::State::Epsilon { next } | crate::nfa::State::Assertion { next, .. } => {
                     stack.push(*next);
                 },
                 crate::nfa::State::Split { targets } => {
@@ -624,68 +1090,33 @@ impl Compiler {
     }
     
     /// Handle large Unicode character classes (likely negated)
+    ///
+    /// The gaps between the class's ranges are the excluded characters. An excluded set
+    /// made mostly of lone characters, like `[^\n]`, becomes a `NotCharSet`; otherwise
+    /// the class keeps its own ranges, so negated shorthands like `\D` stay a short list
+    /// of intervals and no excluded character is ever dropped.
     fn handle_large_unicode_class(&self, class: &ClassUnicode) -> CompileResult<crate::nfa::CharacterPredicate> {
         use crate::nfa::CharacterPredicate;
-        use std::collections::HashSet;
         
-        // For very large Unicode classes, find the gaps (the excluded characters)
-        let mut excluded_chars = HashSet::new();
-        let ranges: Vec<_> = class.iter().collect();
+        let ranges: Vec<(char, char)> = class.iter().map(|range| (range.start(), range.end())).collect();
+        let gaps = crate::nfa::complement_ranges(&ranges);
         
-        // Check gap before first range
-        if let Some(first_range) = ranges.first() {
-            if first_range.start() > '\u{0}' {
-                for ch_code in 0..(first_range.start() as u32) {
-                    if let Some(ch) = char::from_u32(ch_code) {
-                        excluded_chars.insert(ch);
-                    }
-                    if excluded_chars.len() > 200 { break; }
-                }
-            }
+        // A class with no gaps (`(?s).`, `[\s\S]`, ...) matches every character
+        if gaps.is_empty() {
+            return Ok(CharacterPredicate::Any);
         }
         
-        // Check gaps between ranges
-        for i in 0..ranges.len().saturating_sub(1) {
-            let current_end = ranges[i].end() as u32;
-            let next_start = ranges[i + 1].start() as u32;
-            
-            if next_start > current_end + 1 {
-                for ch_code in (current_end + 1)..next_start {
-                    if let Some(ch) = char::from_u32(ch_code) {
-                        excluded_chars.insert(ch);
-                    }
-                    if excluded_chars.len() > 200 { break; }
-                }
-            }
-            
-            if excluded_chars.len() > 200 { break; }
+        let excluded_count = gaps.iter()
+            .flat_map(|&(start, end)| start..=end)
+            .take(MAX_EXCLUDED_CHARS + 1)
+            .count();
+        if excluded_count <= MAX_EXCLUDED_CHARS && excluded_count <= 2 * gaps.len() {
+            let excluded = gaps.into_iter().flat_map(|(start, end)| start..=end).collect();
+            return Ok(CharacterPredicate::NotCharSet(excluded));
         }
         
-        Ok(CharacterPredicate::NotCharSet(excluded_chars))
-    }
-    
-    /// Negate a character predicate
-    fn negate_predicate(&self, pred: crate::nfa::CharacterPredicate) -> crate::nfa::CharacterPredicate {
-        use crate::nfa::CharacterPredicate;
-        
-        match pred {
-            CharacterPredicate::Any => {
-                // Can't negate "any" - would be "nothing", which isn't useful
-                // Return empty set instead  
-                CharacterPredicate::CharSet(std::collections::HashSet::new())
-            },
-            CharacterPredicate::Char(ch) => {
-                let mut set = std::collections::HashSet::new();
-                set.insert(ch);
-                CharacterPredicate::NotCharSet(set)
-            },
-            CharacterPredicate::CharSet(set) => {
-                CharacterPredicate::NotCharSet(set)
-            },
-            CharacterPredicate::NotCharSet(set) => {
-                CharacterPredicate::CharSet(set)
-            },
-        }
+        CharacterPredicate::from_ranges(ranges)
+            .ok_or_else(|| CompileError::Internal("empty Unicode class".to_string()))
     }
     
     /// Extract characters that a pattern would match (legacy method)
@@ -710,8 +1141,12 @@ impl Compiler {
         
         // Thompson construction: create binary split states
         // For a|b|c, create: split(a, split(b, c))
+        // Folding from the back keeps each earlier branch as the first split target, so
+        // branches are tried in source order and the leftmost alternative wins.
         let mut result = fragments.pop().unwrap();
         
+        // An empty branch (`a|`) is a single unpatched epsilon with `start == end`, so the
+        // split enters it and connecting its end points that same epsilon at the join.
         while let Some(fragment) = fragments.pop() {
             let end_state = self.nfa.epsilon(0);
             let split_state = self.nfa.split(vec![fragment.start, result.start]);
@@ -735,11 +1170,39 @@ impl Compiler {
         let possessive = matches!(rep.kind, RepetitionKind::Possessive);
         let reluctant = matches!(rep.kind, RepetitionKind::Reluctant);
         
+        // Repeating something that never consumes input, like `\b*` or `(?:^)?`, is
+        // almost certainly a mistake, so say so instead of failing on the assertion itself
+        let properties = rep.sub.properties();
+        if properties.maximum_len() == Some(0) && !properties.look_set().is_empty() {
+            return Err(CompileError::UnsupportedFeature("repetition of zero-width assertion".to_string()));
+        }
+        
+        if self.warn_redundant_repetition && max.is_none() {
+            if let Some(inner) = Self::unbounded_repetition(&rep.sub) {
+                self.warnings.push(CompileWarning::RedundantRepetition(inner.to_string()));
+            }
+        }
+        
+        // A possessive's lookahead sees a single byte, which is only a whole character in ASCII
+        if self.utf8_bytes && possessive && !(is_single_char(&rep.sub) && properties.maximum_len() == Some(1)) {
+            return Err(CompileError::UnsupportedFeature("possessive repetition of non-ASCII in UTF-8 byte mode".to_string()));
+        }
+        
         match (min, max) {
+            (1, Some(1)) if possessive => self.compile_atomic(&rep.sub),           // (?>...)
             (0, Some(1)) => self.compile_question(&rep.sub, possessive, reluctant), // ?
             (0, None) => self.compile_star(&rep.sub, possessive, reluctant),        // *
             (1, None) => self.compile_plus(&rep.sub, possessive, reluctant),        // +
-            (min, max) => self.compile_counted(&rep.sub, min, max, possessive), // {n,m}
+            (min, max) => self.compile_counted(&rep.sub, min, max, possessive, reluctant), // {n,m}
+        }
+    }
+    
+    /// Find the unbounded repetition `hir` is, looking through groups
+    fn unbounded_repetition(hir: &Hir) -> Option<&Hir> {
+        match hir.kind() {
+            HirKind::Repetition(rep) if rep.max.is_none() => Some(hir),
+            HirKind::Capture(capture) => Self::unbounded_repetition(&capture.sub),
+            _ => None,
         }
     }
     
@@ -786,8 +1249,23 @@ impl Compiler {
             self.nfa.split(vec![expr_fragment.start, end_state])
         };
         
-        // Connect expr end back to start (for multiple matches)
-        self.nfa.connect(expr_fragment.end, start_state);
+        // A body that can match empty would loop straight back into the split it was
+        // entered from, which the closure has already visited, so the thread that should
+        // leave the loop after the empty iteration would die there. Give such bodies a
+        // separate split after each iteration, i.e. compile `x*` as `(?:x+)?`.
+        let loop_state = if Self::is_nullable(expr) {
+            if reluctant {
+                self.nfa.split(vec![end_state, expr_fragment.start])
+            } else {
+                self.nfa.split(vec![expr_fragment.start, end_state])
+            }
+        } else {
+            start_state
+        };
+        
+        // Connect expr end back to the loop state (for multiple matches)
+        self.nfa.connect(expr_fragment.end, loop_state);
+        self.record_quantifier(loop_state, reluctant, 0);
         
         Ok(Fragment {
             start: start_state,
@@ -806,6 +1284,8 @@ impl Compiler {
         
         let end_state = self.nfa.epsilon(0);
         
+        // The first copy is mandatory, so the fragment is entered at the body and the
+        // only choice is made after each copy.
         // For reluctant +?, prioritize exit over loop: [end, loop]
         // For greedy +, prioritize loop over exit: [loop, end]
         let loop_state = if reluctant {
@@ -816,6 +1296,7 @@ impl Compiler {
         
         // Connect expr to loop state
         self.nfa.connect(expr_fragment.end, loop_state);
+        self.record_quantifier(loop_state, reluctant, 1);
         
         Ok(Fragment {
             start: expr_fragment.start,
@@ -824,17 +1305,31 @@ impl Compiler {
     }
     
     /// Compile possessive * quantifier using lookahead structure  
+    ///
+    /// Zero repetitions are only allowed where the loop couldn't take one: the bypass is
+    /// guarded by an assertion that the next character can't start the body, or that the
+    /// input ends. Otherwise a possessive would give back its first repetition whenever
+    /// the rest of the pattern failed after it.
     fn compile_possessive_star(&mut self, expr: &Hir) -> CompileResult<Fragment> {
-        // For possessive *, we need optional matching with possessive loops
+        if !is_single_char(expr) {
+            return self.compile_possessive_loop(expr, 0);
+        }
+        
+        // For possessive *, we need optional matching with possessive loops
         // This is like possessive + but with an optional entry
         let possessive_plus = self.compile_possessive_plus(expr)?;
+        let first = self.hir_to_predicate(expr)?;
         
-        // Create a split that allows bypassing the possessive match entirely
+        // Create a split that allows bypassing the possessive match entirely. Both the
+        // bypass and the loop exit land on the same epsilon, whose target stays unpatched
+        // (usize::MAX) until the fragment is connected to whatever follows.
         let end_state = self.nfa.epsilon(usize::MAX);
-        let start_state = self.nfa.split(vec![possessive_plus.start, end_state]);
+        let bypass = self.nfa.assertion(AssertionKind::NotFollowedBy { sequences: vec![vec![first]] }, end_state);
+        let start_state = self.nfa.split(vec![possessive_plus.start, bypass]);
         
         // Connect the possessive plus end to the same end state
         self.nfa.connect(possessive_plus.end, end_state);
+        self.record_possessive(possessive_plus.start, 0);
         
         Ok(Fragment {
             start: start_state,
@@ -844,6 +1339,13 @@ impl Compiler {
     
     /// Compile possessive + quantifier using direct loop structure
     fn compile_possessive_plus(&mut self, expr: &Hir) -> CompileResult<Fragment> {
+        use crate::nfa::CharacterPredicate;
+        
+        // Bodies longer than one character need the general construction
+        if !is_single_char(expr) {
+            return self.compile_possessive_loop(expr, 1);
+        }
+        
         // Convert the expression to a character predicate
         let pattern_predicate = self.hir_to_predicate(expr)?;
         
@@ -869,13 +1371,25 @@ impl Compiler {
         loop_transitions.push(loop_transition);
         
         // Exit transition: pattern + non-pattern lookahead OR no lookahead -> end
+        let exit_lookahead = pattern_predicate.negate();
+        let exit_at_end = !matches!(exit_lookahead, CharacterPredicate::NotCharSet(_) | CharacterPredicate::EndOfInput);
         let exit_transition = TwoCharTransition::predicate(
             pattern_predicate.clone(),
-            Some(self.negate_predicate(pattern_predicate.clone())),
+            Some(exit_lookahead),
             usize::MAX  // Will point to end state
         );
         loop_transitions.push(exit_transition);
         
+        // Only negated sets succeed at end of input, so any other exit lookahead needs a
+        // separate exit there
+        if exit_at_end {
+            loop_transitions.push(TwoCharTransition::predicate(
+                pattern_predicate.clone(),
+                Some(CharacterPredicate::EndOfInput),
+                usize::MAX  // Will point to end state
+            ));
+        }
+        
         let loop_state = self.nfa.transitions_state(loop_transitions);
         
         // Update loop state transitions
@@ -884,22 +1398,351 @@ impl Compiler {
                 if transition.target == usize::MAX {
                     match i {
                         0 => transition.target = loop_state, // Loop back
-                        1 => transition.target = end_state,  // Exit to end
-                        _ => unreachable!("Only expecting 2 transitions"),
+                        _ => transition.target = end_state,  // Exit to end
                     }
                 }
             }
         }
         
         // The loop state IS the start state - no separate first match needed
+        self.record_possessive(loop_state, 1);
         Ok(Fragment {
             start: loop_state,
             end: end_state,
         })
     }
     
-    /// Compile counted repetition {n,m} with possessive support
-    fn compile_counted(&mut self, expr: &Hir, min: u32, max: Option<u32>, possessive: bool) -> CompileResult<Fragment> {
+    /// Compile possessive `{min,}` over a body longer than one character
+    ///
+    /// The loop behaves like `(?>X{min,})`: once the required copies have matched, every
+    /// further iteration takes the first way the body matches, and the loop stops exactly
+    /// where the body can't match and never gives an iteration back. When the body (inside
+    /// any capture groups around all of it) expands to a small set of strings, each
+    /// iteration is built like an atomic group and the exit is an assertion that none of
+    /// the strings follows. Other bodies go through `compile_choice_free_loop`.
+    ///
+    /// The required copies may still backtrack into each other while looking for their
+    /// first match, so for `min > 1` they are expanded together unless no string of the
+    /// body is a prefix of another, in which case only one way to match can apply.
+    fn compile_possessive_loop(&mut self, expr: &Hir, min: u32) -> CompileResult<Fragment> {
+        use crate::nfa::CharacterPredicate;
+        
+        let (captures, body) = peel_captures(expr);
+        let Some(strings) = atomic_strings(body) else {
+            return self.compile_choice_free_loop(expr, min);
+        };
+        
+        let mut fragments = Vec::new();
+        if min <= 1 || is_prefix_free(&strings) {
+            for _ in 0..min {
+                let iteration = self.compile_first_match(&strings);
+                fragments.push(self.wrap_captures(&captures, iteration));
+            }
+        } else if captures.is_empty() {
+            let copies = Hir::repetition(regex_syntax::hir::Repetition {
+                min,
+                max: Some(min),
+                kind: RepetitionKind::Greedy,
+                sub: Box::new(body.clone()),
+            });
+            let strings = atomic_strings(&copies).ok_or_else(|| {
+                CompileError::UnsupportedFeature("required copies of a possessive loop body are not a small set of strings".to_string())
+            })?;
+            fragments.push(self.compile_first_match(&strings));
+        } else {
+            return Err(CompileError::UnsupportedFeature(
+                "capture group around a possessive {n,} body whose strings overlap".to_string()
+            ));
+        }
+        
+        // An iteration that matches empty ends the loop, and once the empty string is the
+        // first way the body matches nothing after it is ever taken
+        let strings: Vec<Vec<char>> = strings.into_iter().take_while(|string| !string.is_empty()).collect();
+        let sequences = first_match_strings(strings.clone())
+            .into_iter()
+            .map(|(string, _)| string.into_iter().map(CharacterPredicate::Char).collect())
+            .collect();
+        let end_state = self.nfa.epsilon(usize::MAX);
+        let exit = self.nfa.assertion(AssertionKind::NotFollowedBy { sequences }, end_state);
+        let loop_state = if strings.is_empty() {
+            exit
+        } else {
+            let iteration = self.compile_first_match(&strings);
+            let iteration = self.wrap_captures(&captures, iteration);
+            let loop_state = self.nfa.split(vec![iteration.start, exit]);
+            self.nfa.connect(iteration.end, loop_state);
+            loop_state
+        };
+        self.record_possessive(loop_state, min);
+        
+        fragments.push(Fragment { start: loop_state, end: end_state });
+        for i in 0..fragments.len() - 1 {
+            self.nfa.connect(fragments[i].end, fragments[i + 1].start);
+        }
+        Ok(Fragment { start: fragments[0].start, end: end_state })
+    }
+    
+    /// Wrap a compiled iteration in capture groups, innermost first
+    fn wrap_captures(&mut self, captures: &[&regex_syntax::hir::Capture], fragment: Fragment) -> Fragment {
+        captures.iter().rev().fold(fragment, |fragment, capture| self.wrap_capture(capture, fragment))
+    }
+    
+    /// Compile possessive `{min,}` over a body that isn't a small set of strings
+    ///
+    /// The body must have no choices of its own, so each iteration can only match one way.
+    /// It is compiled once and every transition that finishes an iteration is split in
+    /// two: one continues into the next iteration when the following character can start
+    /// the body, the other leaves the loop when it can't (or at end of input). Each side
+    /// gets its own copy of the epsilon path from that transition to the end of the body,
+    /// so capture groups inside the body still record their spans.
+    ///
+    /// Only one character of lookahead is available, so the loop commits to an iteration
+    /// as soon as its first character matches. That is only right when every iteration
+    /// that gets past its first character can finish; other bodies would need to see
+    /// further ahead to know whether to stop, and are rejected.
+    fn compile_choice_free_loop(&mut self, expr: &Hir, min: u32) -> CompileResult<Fragment> {
+        use crate::nfa::{CharacterPredicate, State};
+        
+        if !is_choice_free(expr) {
+            return Err(CompileError::UnsupportedFeature(
+                "possessive loop body with alternatives that is not a small set of strings".to_string()
+            ));
+        }
+        
+        let first: HashSet<char> = self.extract_lookahead_chars(expr)?.into_iter().collect();
+        let body = self.compile_hir(expr)?;
+        // A body like a literal ends in a consuming state with unpatched targets, so give
+        // it an epsilon end that finishing an iteration always reaches
+        let body_end = self.nfa.epsilon(usize::MAX);
+        self.nfa.connect(body.end, body_end);
+        let body = Fragment { start: body.start, end: body_end };
+        let end_state = self.nfa.epsilon(usize::MAX);
+        
+        // Every character that can start an iteration must also be able to finish one.
+        // Paths through an assertion may fail, so they don't count towards either.
+        let entry = self.closure_without_assertions(body.start);
+        let (finishing, unfinished): (Vec<TwoCharTransition>, Vec<TwoCharTransition>) = entry.iter()
+            .flat_map(|&state_id| self.nfa.transitions_of(state_id).iter().cloned())
+            .partition(|transition| {
+                transition.lookahead.is_none() && self.closure_without_assertions(transition.target).contains(&body.end)
+            });
+        let can_fail = unfinished.iter().any(|transition| {
+            finishing.iter()
+                .fold(transition.current.clone(), |rest, finished| rest.intersection(&finished.current.negate()))
+                .intersects(&CharacterPredicate::Any)
+        });
+        if can_fail {
+            return Err(CompileError::UnsupportedFeature(
+                "possessive loop body that can fail after its first character needs more than one character of lookahead".to_string()
+            ));
+        }
+        
+        // Find the transitions that can finish an iteration
+        let mut finishing = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![body.start];
+        while let Some(state_id) = stack.pop() {
+            if state_id == body.end || state_id >= self.nfa.states.len() || !visited.insert(state_id) {
+                continue;
+            }
+            match &self.nfa.states[state_id] {
+                State::Transitions { transitions } => {
+                    for (index, transition) in transitions.iter().enumerate() {
+                        stack.push(transition.target);
+                        if self.reaches_without_consuming(transition.target, body.end) {
+                            finishing.push((state_id, index));
+                        }
+                    }
+                },
+                State::Epsilon { next } | State::Assertion { next, .. } => stack.push(*next),
+                State::Split { targets } => stack.extend(targets.iter().copied()),
+                _ => {},
+            }
+        }
+        
+        let guards = [
+            (CharacterPredicate::CharSet(first.clone()), body.start),
+            (CharacterPredicate::NotCharSet(first.clone()), end_state),
+        ];
+        let mut copies = Vec::new();
+        for (guard, destination) in guards {
+            let mut copied = std::collections::HashMap::new();
+            for &(state_id, index) in &finishing {
+                let transition = self.nfa.transitions_of(state_id)[index].clone();
+                let lookahead = match &transition.lookahead {
+                    None => Some(guard.clone()),
+                    Some(lookahead) => crate::nfa::intersect_lookaheads(lookahead, &guard)?,
+                };
+                let Some(lookahead) = lookahead else { continue };
+                let target = self.copy_exit_path(transition.target, body.end, destination, &mut copied);
+                copies.push((state_id, TwoCharTransition::predicate(transition.current, Some(lookahead), target)));
+            }
+        }
+        
+        // The originals only survive to keep consuming within the body; their own path to
+        // the end of the body is cut off
+        let has_more = |compiler: &Self, target: StateId| {
+            compiler.nfa.epsilon_closure(&std::iter::once(target).collect())
+                .iter()
+                .any(|&id| !compiler.nfa.transitions_of(id).is_empty())
+        };
+        let removed: Vec<(StateId, usize)> = finishing.iter()
+            .copied()
+            .filter(|&(state_id, index)| !has_more(self, self.nfa.transitions_of(state_id)[index].target))
+            .collect();
+        for (state_id, index) in removed.into_iter().rev() {
+            if let State::Transitions { transitions } = &mut self.nfa.states[state_id] {
+                transitions.remove(index);
+            }
+        }
+        for (state_id, transition) in copies {
+            if let State::Transitions { transitions } = &mut self.nfa.states[state_id] {
+                transitions.push(transition);
+            }
+        }
+        self.nfa.states[body.end] = State::Split { targets: Vec::new() };
+        self.record_possessive(body.start, min);
+        
+        // Zero iterations are only allowed where the next character can't start one, and
+        // required copies before the loop can only match one way
+        let mut fragments = Vec::new();
+        for _ in 1..min {
+            fragments.push(self.compile_hir(expr)?);
+        }
+        let start = if min == 0 {
+            let guard = AssertionKind::NotFollowedBy { sequences: vec![vec![CharacterPredicate::CharSet(first)]] };
+            let bypass = self.nfa.assertion(guard, end_state);
+            self.nfa.split(vec![body.start, bypass])
+        } else {
+            body.start
+        };
+        fragments.push(Fragment { start, end: end_state });
+        for i in 0..fragments.len() - 1 {
+            self.nfa.connect(fragments[i].end, fragments[i + 1].start);
+        }
+        
+        Ok(Fragment { start: fragments[0].start, end: end_state })
+    }
+    
+    /// Copy the epsilon path from `from` to `end`, with `end` leading on to `destination`
+    ///
+    /// Only the parts of the path that reach `end` without consuming are copied, along
+    /// with their capture slots; `copied` shares states between paths that merge.
+    fn copy_exit_path(
+        &mut self,
+        from: StateId,
+        end: StateId,
+        destination: StateId,
+        copied: &mut std::collections::HashMap<StateId, StateId>,
+    ) -> StateId {
+        use crate::nfa::State;
+        
+        if let Some(&copy) = copied.get(&from) {
+            return copy;
+        }
+        let copy = self.nfa.epsilon(destination);
+        copied.insert(from, copy);
+        if let Some(&slot) = self.nfa.capture_slots.get(&from) {
+            self.nfa.capture_slots.insert(copy, slot);
+        }
+        if from == end {
+            return copy;
+        }
+        
+        let state = match self.nfa.states[from].clone() {
+            State::Epsilon { next } => State::Epsilon {
+                next: self.copy_exit_path(next, end, destination, copied),
+            },
+            State::Assertion { kind, next } => State::Assertion {
+                kind,
+                next: self.copy_exit_path(next, end, destination, copied),
+            },
+            State::Split { targets } => {
+                let targets: Vec<StateId> = targets.into_iter()
+                    .filter(|&target| self.reaches_without_consuming(target, end))
+                    .collect();
+                State::Split {
+                    targets: targets.into_iter()
+                        .map(|target| self.copy_exit_path(target, end, destination, copied))
+                        .collect(),
+                }
+            },
+            // Consuming states are never on an epsilon path
+            _ => State::Split { targets: Vec::new() },
+        };
+        self.nfa.states[copy] = state;
+        copy
+    }
+    
+    /// Compile an atomic group `(?>...)`, which commits to the first way its body matches
+    ///
+    /// The body is expanded into the strings it matches, in priority order, and compiled
+    /// with `compile_first_match`. Bodies that aren't a small finite set of strings are
+    /// unsupported.
+    ///
+    /// A body that can only match one way, such as a single character or a chain without
+    /// alternatives, is already atomic and is compiled as it is. An unbounded greedy
+    /// repetition like `(?>a+)` is the possessive `a++` and is compiled as one.
+    fn compile_atomic(&mut self, expr: &Hir) -> CompileResult<Fragment> {
+        if let Some(possessive) = atomic_as_possessive(expr) {
+            return self.compile_hir(&possessive);
+        }
+        if is_choice_free(expr) {
+            return self.compile_hir(expr);
+        }
+        
+        let unsupported = || CompileError::UnsupportedFeature("atomic group body is not a small set of strings".to_string());
+        let strings = atomic_strings(expr).ok_or_else(unsupported)?;
+        Ok(self.compile_first_match(&strings))
+    }
+    
+    /// Compile a list of strings in priority order so that only the first one that
+    /// matches at a position is taken
+    ///
+    /// A string that starts with a higher-priority one can never be chosen and is dropped.
+    /// A string that a higher-priority one extends is only chosen when the rest of that
+    /// one doesn't follow: a single extra character is ruled out by a lookahead on the
+    /// string's last transition, anything longer by a `NotFollowedBy` assertion.
+    fn compile_first_match(&mut self, strings: &[Vec<char>]) -> Fragment {
+        use crate::nfa::CharacterPredicate;
+        
+        let end_state = self.nfa.epsilon(usize::MAX);
+        let mut branches = Vec::new();
+        for (string, suffixes) in first_match_strings(strings.to_vec()) {
+            let mut next = end_state;
+            let mut last_lookahead = None;
+            if !string.is_empty() && suffixes.iter().all(|suffix| suffix.len() == 1) {
+                let forbidden: HashSet<char> = suffixes.iter().map(|suffix| suffix[0]).collect();
+                last_lookahead = (!forbidden.is_empty()).then_some(CharacterPredicate::NotCharSet(forbidden));
+            } else if !suffixes.is_empty() {
+                let sequences = suffixes.into_iter()
+                    .map(|suffix| suffix.into_iter().map(CharacterPredicate::Char).collect())
+                    .collect();
+                next = self.nfa.assertion(AssertionKind::NotFollowedBy { sequences }, end_state);
+            }
+            for &ch in string.iter().rev() {
+                let lookahead = last_lookahead.take();
+                next = self.nfa.transition_state(TwoCharTransition::predicate(CharacterPredicate::Char(ch), lookahead, next));
+            }
+            branches.push(next);
+        }
+        
+        let start = match branches.as_slice() {
+            [only] => *only,
+            _ => self.nfa.split(branches),
+        };
+        Fragment { start, end: end_state }
+    }
+    
+    /// Compile counted repetition {n,m} with possessive and reluctant support
+    ///
+    /// Reluctant `{n,m}?` puts the exit first at every optional step, so it stops after
+    /// `n` copies and only takes another when the rest of the pattern needs it.
+    fn compile_counted(&mut self, expr: &Hir, min: u32, max: Option<u32>, possessive: bool, reluctant: bool) -> CompileResult<Fragment> {
+        if possessive && max.is_none() {
+            return self.compile_possessive_at_least(expr, min);
+        }
+        
         let mut fragments = Vec::new();
         
         // Required repetitions (min)
@@ -926,15 +1769,22 @@ impl Compiler {
         }
         
         let end_state = self.nfa.epsilon(0);
-        let start = fragments[0].start;
+        // With no required copies the first copy is itself optional, so the fragment
+        // needs its own entry state to hang the first choice off
+        let start = if min > 0 { fragments[0].start } else { self.nfa.epsilon(usize::MAX) };
         
         if let Some(max) = max {
-            // Bounded: connect optional parts with choice
+            // Bounded: connect optional parts with choice. For an exact count (min == max)
+            // there are no optional parts and the last required copy goes straight to the end.
             let mut current_end = if min > 0 { fragments[min as usize - 1].end } else { start };
             
             for i in min as usize..max as usize {
                 if i < fragments.len() {
-                    let split = self.nfa.split(vec![fragments[i].start, end_state]);
+                    let split = if reluctant {
+                        self.nfa.split(vec![end_state, fragments[i].start])
+                    } else {
+                        self.nfa.split(vec![fragments[i].start, end_state])
+                    };
                     self.nfa.connect(current_end, split);
                     current_end = fragments[i].end;
                 }
@@ -946,17 +1796,332 @@ impl Compiler {
             let last_required = if min > 0 { fragments[min as usize - 1].end } else { start };
             let loop_expr = self.compile_hir(expr)?;
             
-            // Possessive behavior for unbounded repetitions is achieved through structure
+            let split = if reluctant {
+                self.nfa.split(vec![end_state, loop_expr.start])
+            } else {
+                self.nfa.split(vec![loop_expr.start, end_state])
+            };
             
-            let split = self.nfa.split(vec![loop_expr.start, end_state]);
+            // As in `compile_star`, a nullable body gets a separate split after each
+            // iteration so an empty iteration can still leave the loop
+            let loop_state = if Self::is_nullable(expr) {
+                if reluctant {
+                    self.nfa.split(vec![end_state, loop_expr.start])
+                } else {
+                    self.nfa.split(vec![loop_expr.start, end_state])
+                }
+            } else {
+                split
+            };
             
             self.nfa.connect(last_required, split);
-            self.nfa.connect(loop_expr.end, split);
+            self.nfa.connect(loop_expr.end, loop_state);
+            self.record_quantifier(loop_state, reluctant, min);
         }
         
         Ok(Fragment { start, end: end_state })
     }
     
+    /// Record the quantifier of a greedy or lazy unbounded loop on its loop state
+    fn record_quantifier(&mut self, loop_state: StateId, reluctant: bool, min: u32) {
+        let kind = if reluctant { QuantifierKind::Lazy } else { QuantifierKind::Greedy };
+        self.nfa.quantifiers.insert(loop_state, QuantifierInfo { kind, min, max: None });
+    }
+    
+    /// Record a possessive unbounded loop, replacing the `+` recorded by
+    /// `compile_possessive_plus` when a `*+` or `{n,}+` wraps it
+    fn record_possessive(&mut self, loop_state: StateId, min: u32) {
+        self.nfa.quantifiers.insert(loop_state, QuantifierInfo { kind: QuantifierKind::Possessive, min, max: None });
+    }
+    
+    /// Compile possessive `{n,}+`
+    ///
+    /// The last required copy doubles as the first iteration of a possessive `+`, so once
+    /// the required copies have matched the loop takes every further repetition it can and
+    /// never gives one back. Bodies longer than one character have the same restriction as
+    /// in `compile_possessive_loop`.
+    fn compile_possessive_at_least(&mut self, expr: &Hir, min: u32) -> CompileResult<Fragment> {
+        if min == 0 {
+            return self.compile_possessive_star(expr);
+        }
+        if !is_single_char(expr) {
+            return self.compile_possessive_loop(expr, min);
+        }
+        
+        let mut fragments = Vec::new();
+        for _ in 1..min {
+            fragments.push(self.compile_hir(expr)?);
+        }
+        let possessive_plus = self.compile_possessive_plus(expr)?;
+        self.record_possessive(possessive_plus.start, min);
+        fragments.push(possessive_plus);
+        
+        for i in 0..fragments.len() - 1 {
+            self.nfa.connect(fragments[i].end, fragments[i + 1].start);
+        }
+        
+        Ok(Fragment {
+            start: fragments[0].start,
+            end: fragments[fragments.len() - 1].end,
+        })
+    }
+}
+
+/// Check whether an HIR node is a text or line anchor, which compiles to an assertion
+fn is_anchor(hir: &Hir) -> bool {
+    use regex_syntax::hir::Look;
+    
+    matches!(
+        hir.kind(),
+        HirKind::Look(
+            Look::Start | Look::End | Look::StartLF | Look::EndLF | Look::StartCRLF | Look::EndCRLF
+                | Look::WordAscii | Look::WordAsciiNegate | Look::WordUnicode | Look::WordUnicodeNegate
+        )
+    )
+}
+
+/// Check whether an HIR node is a `$` anchor
+fn is_end_text(hir: &Hir) -> bool {
+    matches!(hir.kind(), HirKind::Look(regex_syntax::hir::Look::End))
+}
+
+/// Check whether an HIR node always matches exactly one character
+fn is_single_char(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Class(_) => true,
+        HirKind::Literal(literal) => match std::str::from_utf8(&literal.0) {
+            Ok(s) => s.chars().count() == 1,
+            Err(_) => literal.0.len() == 1,
+        },
+        _ => false,
+    }
+}
+
+/// Check whether an HIR node can match in at most one way at any position
+///
+/// Classes pick a single character, so only alternations and repetitions with a range
+/// of counts are choices. A possessive repetition of a choice-free body never gives back
+/// what it took, so it isn't one either.
+fn is_choice_free(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Literal(_) | HirKind::Class(_) | HirKind::Look(_) => true,
+        HirKind::Capture(capture) => is_choice_free(&capture.sub),
+        HirKind::Concat(parts) => parts.iter().all(is_choice_free),
+        HirKind::Alternation(_) => false,
+        HirKind::Repetition(rep) => {
+            (rep.max == Some(rep.min) || matches!(rep.kind, RepetitionKind::Possessive)) && is_choice_free(&rep.sub)
+        },
+    }
+}
+
+/// Rebuild an HIR node with every literal character and class widened to its simple
+/// case folds
+fn fold_case(hir: &Hir) -> Hir {
+    use regex_syntax::hir::{Capture, ClassBytesRange, ClassUnicodeRange, Repetition};
+    
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => hir.clone(),
+        HirKind::Literal(literal) => {
+            let classes = match std::str::from_utf8(&literal.0) {
+                Ok(s) => s.chars()
+                    .map(|ch| Class::Unicode(ClassUnicode::new([ClassUnicodeRange::new(ch, ch)])))
+                    .collect::<Vec<_>>(),
+                Err(_) => literal.0.iter()
+                    .map(|&byte| Class::Bytes(ClassBytes::new([ClassBytesRange::new(byte, byte)])))
+                    .collect(),
+            };
+            Hir::concat(classes.into_iter().map(|mut class| {
+                class.case_fold_simple();
+                Hir::class(class)
+            }).collect())
+        },
+        HirKind::Class(class) => {
+            let mut class = class.clone();
+            class.case_fold_simple();
+            Hir::class(class)
+        },
+        HirKind::Repetition(rep) => Hir::repetition(Repetition {
+            min: rep.min,
+            max: rep.max,
+            kind: rep.kind,
+            sub: Box::new(fold_case(&rep.sub)),
+        }),
+        HirKind::Capture(capture) => Hir::capture(Capture {
+            index: capture.index,
+            name: capture.name.clone(),
+            sub: Box::new(fold_case(&capture.sub)),
+        }),
+        HirKind::Concat(parts) => Hir::concat(parts.iter().map(fold_case).collect()),
+        HirKind::Alternation(branches) => Hir::alternation(branches.iter().map(fold_case).collect()),
+    }
+}
+
+/// Check whether an HIR node is an unbounded possessive over a body longer than one
+/// character, which `compile_possessive_loop` builds, looking through capture groups and
+/// atomic groups that compile to one
+fn loops_over_longer_body(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Repetition(rep) if rep.max.is_none() => {
+            matches!(rep.kind, RepetitionKind::Possessive) && !is_single_char(&rep.sub)
+        },
+        HirKind::Repetition(rep) if rep.min == 1 && rep.max == Some(1) && matches!(rep.kind, RepetitionKind::Possessive) => {
+            atomic_as_possessive(&rep.sub).is_some_and(|possessive| loops_over_longer_body(&possessive))
+        },
+        HirKind::Capture(capture) => loops_over_longer_body(&capture.sub),
+        _ => false,
+    }
+}
+
+/// Rewrite an atomic group body that is an unbounded greedy repetition, possibly inside
+/// capture groups, as the possessive repetition it is equivalent to
+fn atomic_as_possessive(hir: &Hir) -> Option<Hir> {
+    use regex_syntax::hir::{Capture, Repetition};
+    
+    match hir.kind() {
+        HirKind::Repetition(rep) if rep.max.is_none() && matches!(rep.kind, RepetitionKind::Greedy) => {
+            Some(Hir::repetition(Repetition {
+                min: rep.min,
+                max: None,
+                kind: RepetitionKind::Possessive,
+                sub: rep.sub.clone(),
+            }))
+        },
+        HirKind::Capture(capture) => Some(Hir::capture(Capture {
+            index: capture.index,
+            name: capture.name.clone(),
+            sub: Box::new(atomic_as_possessive(&capture.sub)?),
+        })),
+        _ => None,
+    }
+}
+
+/// Split the capture groups that wrap a whole HIR node off it, outermost first
+fn peel_captures(hir: &Hir) -> (Vec<&regex_syntax::hir::Capture>, &Hir) {
+    let mut captures = Vec::new();
+    let mut body = hir;
+    while let HirKind::Capture(capture) = body.kind() {
+        captures.push(capture);
+        body = &capture.sub;
+    }
+    (captures, body)
+}
+
+/// Check whether no string in a list is a proper prefix of another, so at most one of
+/// them can match at any position
+fn is_prefix_free(strings: &[Vec<char>]) -> bool {
+    strings.iter().all(|string| {
+        strings.iter().all(|other| other.len() <= string.len() || !other.starts_with(string))
+    })
+}
+
+/// Pick the strings an atomic match over `strings` (in priority order) can end up
+/// taking, each with the rest of every higher-priority string that extends it
+///
+/// A string is dropped when a higher-priority one is a prefix of it, since that one
+/// matches wherever it would. Otherwise it may only be taken where none of the listed
+/// continuations follows it.
+fn first_match_strings(strings: Vec<Vec<char>>) -> Vec<(Vec<char>, Vec<Vec<char>>)> {
+    let mut kept: Vec<(Vec<char>, Vec<Vec<char>>)> = Vec::new();
+    for string in strings {
+        if kept.iter().any(|(higher, _)| string.starts_with(higher)) {
+            continue;
+        }
+        let mut suffixes: Vec<Vec<char>> = Vec::new();
+        for (higher, _) in &kept {
+            if higher.len() > string.len() && higher.starts_with(&string) {
+                let suffix = higher[string.len()..].to_vec();
+                if !suffixes.contains(&suffix) {
+                    suffixes.push(suffix);
+                }
+            }
+        }
+        kept.push((string, suffixes));
+    }
+    kept
+}
+
+/// Largest number of strings an atomic group body may expand to
+const MAX_ATOMIC_STRINGS: usize = 256;
+
+/// Expand an HIR node into the strings it matches, highest priority first
+///
+/// Returns `None` for bodies with captures, assertions or unbounded or possessive
+/// repetitions, or that expand past `MAX_ATOMIC_STRINGS` strings.
+fn atomic_strings(hir: &Hir) -> Option<Vec<Vec<char>>> {
+    let strings = match hir.kind() {
+        HirKind::Empty => vec![Vec::new()],
+        HirKind::Literal(literal) => vec![match std::str::from_utf8(&literal.0) {
+            Ok(s) => s.chars().collect(),
+            Err(_) => literal.0.iter().map(|&byte| byte as char).collect(),
+        }],
+        HirKind::Class(class) => {
+            let ranges: Vec<(char, char)> = match class {
+                Class::Unicode(class) => class.iter().map(|range| (range.start(), range.end())).collect(),
+                Class::Bytes(class) => class.iter().map(|range| (range.start() as char, range.end() as char)).collect(),
+            };
+            let count: usize = ranges.iter().map(|&(start, end)| end as usize - start as usize + 1).sum();
+            if count > MAX_ATOMIC_STRINGS {
+                return None;
+            }
+            // Characters of a class are alternatives of equal priority
+            ranges.into_iter().flat_map(|(start, end)| start..=end).map(|ch| vec![ch]).collect()
+        },
+        HirKind::Concat(parts) => {
+            let mut strings = vec![Vec::new()];
+            for part in parts {
+                let suffixes = atomic_strings(part)?;
+                if strings.len() * suffixes.len() > MAX_ATOMIC_STRINGS {
+                    return None;
+                }
+                strings = strings.iter()
+                    .flat_map(|prefix| suffixes.iter().map(move |suffix| [prefix.as_slice(), suffix].concat()))
+                    .collect();
+            }
+            strings
+        },
+        HirKind::Alternation(branches) => {
+            let mut strings = Vec::new();
+            for branch in branches {
+                strings.extend(atomic_strings(branch)?);
+            }
+            strings
+        },
+        HirKind::Repetition(rep) if !matches!(rep.kind, RepetitionKind::Possessive) => {
+            let max = rep.max?;
+            let counts: Vec<u32> = match rep.kind {
+                RepetitionKind::Reluctant => (rep.min..=max).collect(),
+                _ => (rep.min..=max).rev().collect(),
+            };
+            let mut strings = Vec::new();
+            for count in counts {
+                let copies: Vec<Hir> = std::iter::repeat_n((*rep.sub).clone(), count as usize).collect();
+                strings.extend(atomic_strings(&Hir::concat(copies))?);
+            }
+            strings
+        },
+        _ => return None,
+    };
+    
+    (strings.len() <= MAX_ATOMIC_STRINGS).then_some(strings)
+}
+
+/// Largest excluded set a negated class is compiled to as a `NotCharSet`
+const MAX_EXCLUDED_CHARS: usize = 1000;
+
+/// A node of the keyword trie built by `Compiler::compile_keywords`
+#[derive(Default)]
+struct TrieNode {
+    children: BTreeMap<char, usize>,
+    pattern_id: Option<usize>,
+}
+
+/// Build a single unpatched transition matching any character in a list of ranges
+///
+/// A single range becomes a `Range` predicate (or `Char` for one character); no ranges
+/// yields nothing.
+fn range_transition(ranges: Vec<(char, char)>) -> Option<TwoCharTransition> {
+    let current = crate::nfa::CharacterPredicate::from_ranges(ranges)?;
+    Some(TwoCharTransition::predicate(current, None, usize::MAX))
 }
 
 impl Default for Compiler {
@@ -964,3 +2129,1088 @@ impl Default for Compiler {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use regex_syntax::ParserBuilder;
+    
+    fn compile(pattern: &str) -> NFA {
+        let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+        Compiler::new().compile(&hir).unwrap()
+    }
+    
+    #[test]
+    fn test_negated_class_includes_newline() {
+        let nfa = compile("[^a]");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("\n"));
+        assert!(matcher.is_match("b"));
+        assert!(!matcher.is_match("a"));
+    }
+    
+    #[test]
+    fn test_dot_excludes_newline_without_dotall() {
+        let nfa = compile(".");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("a"));
+        assert!(!matcher.is_match("\n"));
+        
+        let nfa = compile("(?s).");
+        assert!(Matcher::new(&nfa).is_match("\n"));
+    }
+    
+    #[test]
+    fn test_scoped_case_insensitive_group() {
+        let nfa = compile("(?i:ab)c");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("ABc"));
+        assert!(matcher.is_match("aBc"));
+        assert!(!matcher.is_match("ABC"));
+        
+        // Same scoping when the folded classes sit inside a capture group
+        let nfa = compile("((?i)ab)c");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("Abc"));
+        assert!(!matcher.is_match("AbC"));
+    }
+    
+    #[test]
+    fn test_possessive_followed_by_atom() {
+        let possessive = compile("a++b");
+        let greedy = compile("a+b");
+        
+        for input in ["aaab", "aaa", "ab", "b"] {
+            assert_eq!(
+                Matcher::new(&possessive).is_match(input),
+                Matcher::new(&greedy).is_match(input),
+                "a++b vs a+b on {:?}", input
+            );
+        }
+        assert!(Matcher::new(&possessive).is_match("aaab"));
+        assert!(!Matcher::new(&possessive).is_match("aaa"));
+        
+        // The possessive loop only exits when the next character can start `b`
+        let exit_lookaheads: Vec<_> = possessive.states.iter()
+            .flat_map(|state| match state {
+                crate::nfa::State::Transitions { transitions } => transitions.clone(),
+                _ => Vec::new(),
+            })
+            .filter_map(|t| t.lookahead)
+            .collect();
+        assert!(exit_lookaheads.contains(&crate::nfa::CharacterPredicate::CharSet(['b'].into_iter().collect())));
+    }
+    
+    #[test]
+    fn test_possessive_does_not_give_back_to_follower() {
+        // `[ab]++` would swallow the final `b`, so the follower could never match "aab"
+        let hir = ParserBuilder::new().build().parse("[ab]++b").unwrap();
+        assert!(matches!(Compiler::new().compile(&hir), Err(CompileError::UnsupportedFeature(_))));
+        assert!(Matcher::new(&compile("[ab]+b")).is_match("aab"));
+        
+        // A follower with characters outside the loop is given those
+        assert!(Matcher::new(&compile("[ab]++[bc]")).is_match("aac"));
+        assert!(!Matcher::new(&compile("[ab]++[bc]")).is_match("aab"));
+    }
+    
+    #[test]
+    fn test_possessive_follower_overlap() {
+        let compiles = |pattern: &str| {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            Compiler::new().compile(&hir).is_ok()
+        };
+        
+        // Disjoint followers compile, and so do followers the possessive can give way to
+        for pattern in ["a++b", "a++[^a]", "[a-c]++[d-f]", "\\d++x", "(?:ab)++c", "a{1,3}+a", "a++$"] {
+            assert!(compiles(pattern), "{}", pattern);
+        }
+        assert!(Matcher::new(&compile("a++b")).is_match("aab"));
+        
+        // A follower that can only start with a character the loop consumes never gets one
+        for pattern in ["a++a", "a*+a", "a{2,}+a", "[ab]++b", "[^0-9]++y", "\\w++_"] {
+            assert!(!compiles(pattern), "{}", pattern);
+        }
+        
+        // Followers that overlap the loop can still start with a character outside it
+        let span = |pattern: &str, input: &str| Matcher::new(&compile(pattern)).find(input).map(|m| (m.start, m.end));
+        assert_eq!(span("[a-z]++[0-9x]", "ab1"), Some((0, 3)));
+        assert_eq!(span("[a-z]++[0-9x]", "abx"), None);
+        assert_eq!(span("[a-z]++[^0-9]", "ab!"), Some((0, 3)));
+        assert_eq!(span("\\w++[^ ]", "ab!"), Some((0, 3)));
+        assert_eq!(span("[ab]*+[^a]", "c"), Some((0, 1)));
+        assert_eq!(span("[ab]*+[^a]", "abc"), Some((0, 3)));
+        assert_eq!(span("[ab]++[^a]", "ab"), None);
+    }
+    
+    #[test]
+    fn test_atomic_group() {
+        // The group commits to `a` and never retries with `ab`
+        assert!(Matcher::new(&compile("(?:a|ab)c")).is_match("abc"));
+        assert!(!Matcher::new(&compile("(?>a|ab)c")).is_match("abc"));
+        assert!(Matcher::new(&compile("(?>a|ab)c")).is_match("ac"));
+        
+        // `a` is only taken when `ab` can't be
+        assert!(!Matcher::new(&compile("^(?>ab|a)b$")).is_match("ab"));
+        assert!(Matcher::new(&compile("^(?>ab|a)b$")).is_match("abb"));
+        assert!(Matcher::new(&compile("^(?>a{1,2})a$")).is_match("aaa"));
+        assert!(!Matcher::new(&compile("^(?>a{1,2})a$")).is_match("aa"));
+        assert!(Matcher::new(&compile("^(?>a{1,2}?)a$")).is_match("aa"));
+        
+        let compiles = |pattern: &str| {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            Compiler::new().compile(&hir).is_ok()
+        };
+        // Unbounded greedy bodies are the matching possessives
+        let span = |pattern: &str, input: &str| Matcher::new(&compile(pattern)).find(input).map(|m| (m.start, m.end));
+        assert_eq!(span("(?>a+)b", "aab"), Some((0, 3)));
+        assert_eq!(span("(?>\\d+)", "x123y"), Some((1, 4)));
+        assert_eq!(span("(?>[a-z]+)x", "abx"), None);
+        assert_eq!(span("(?>a*)a", "aaa"), None);
+        assert_eq!(span("(?>a{2,})b", "ab"), None);
+        assert_eq!(span("(?>a{2,})b", "aaab"), Some((0, 4)));
+        assert_eq!(span("(?>(?:ab)+)c", "ababc"), Some((0, 5)));
+        assert_eq!(span("(?>(?:ab)*)ab", "abab"), None);
+        let captures = Matcher::new(&compile("(?>(a+))b")).captures("aab").unwrap();
+        assert_eq!(captures.span(1), Some((0, 2)));
+        
+        // A shorter string is ruled out wherever the rest of a longer one follows
+        assert!(Matcher::new(&compile("(?>abc|a)bc")).find("abc").is_none());
+        assert_eq!(Matcher::new(&compile("(?>abc|a)bc")).find("abcbc").map(|m| (m.start, m.end)), Some((0, 5)));
+        assert_eq!(Matcher::new(&compile("(?>abc|a)")).find("abd").map(|m| (m.start, m.end)), Some((0, 1)));
+        
+        // A shorter higher-priority string never blocks a longer unrelated one
+        for pattern in ["(?>a|bcd)", "(?>c|(?:ab))", "(?>(?:a|b)(?:ab|c))", "(?:(?:a|ab)(?:ab|c)){1,1}+"] {
+            assert!(compiles(pattern), "{}", pattern);
+        }
+        assert!(Matcher::new(&compile("(?>a|bcd)")).is_match("bcd"));
+        assert!(Matcher::new(&compile("(?>(?:a|b)(?:ab|c))")).is_match("bab"));
+        assert!(Matcher::new(&compile("(?:(?:a|ab)(?:ab|c)){1,1}+")).is_match("abc"));
+        
+        // Bodies that can only match one way compile as they are
+        for pattern in ["(?>.)", "(?>[^a])", "(?>a++)", "(?>(a))", "(?:b.){1,1}+"] {
+            assert!(compiles(pattern), "{}", pattern);
+        }
+        assert!(Matcher::new(&compile("(?:b.){1,1}+")).is_match("bx"));
+        assert!(Matcher::new(&compile("(?>[^a])b")).is_match("cb"));
+        assert!(Matcher::new(&compile("(?>a++)")).is_match("aaa"));
+    }
+    
+    #[test]
+    fn test_possessive_lookahead_limit() {
+        // A single-character loop only needs to see the next character to exit
+        let nfa = compile("a++bc");
+        let matcher = Matcher::new(&nfa);
+        assert!(matcher.is_match("aabc"));
+        assert!(!matcher.is_match("aabd"));
+        let result = matcher.find("xaabcx").unwrap();
+        assert_eq!((result.start, result.end), (1, 5));
+        
+        // A longer body stops where its next iteration can't complete, however far
+        // ahead that is
+        let span = |pattern: &str, input: &str| Matcher::new(&compile(pattern)).find(input).map(|m| (m.start, m.end));
+        assert_eq!(span("(?:ab)++a", "aba"), Some((0, 3)));
+        assert_eq!(span("(?:ab)++a", "abab"), None);
+        assert_eq!(span("(?:ab)++ac", "ababac"), Some((0, 6)));
+    }
+    
+    #[test]
+    fn test_step_budget() {
+        let compile_with_budget = |pattern: &str, steps: usize| {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            Compiler::new().with_step_budget(steps).compile(&hir)
+        };
+        
+        // Each copy of the body costs steps, so this aborts long before building them all
+        assert_eq!(compile_with_budget("(?:a[bc]d){1,100000}", 10_000), Err(CompileError::TooComplex));
+        assert_eq!(compile_with_budget("a{100000}", 10_000), Err(CompileError::TooComplex));
+        
+        // Ordinary patterns fit comfortably, and the budget doesn't change what is built
+        let nfa = compile_with_budget("(?:a|b)+c{2,3}", 100).unwrap();
+        assert_eq!(nfa, compile("(?:a|b)+c{2,3}"));
+        // One step for the repetition and one for each copy of `a`
+        assert!(compile_with_budget("a{5}", 6).is_ok());
+        assert!(compile_with_budget("a{5}", 5).is_err());
+    }
+    
+    #[test]
+    fn test_possessive_star() {
+        let nfa = compile("a*+");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match(""));
+        assert!(matcher.is_match("a"));
+        assert!(matcher.is_match("aaa"));
+        assert!(!matcher.is_match("aab"));
+        
+        let nfa = compile("a*+b");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("b"));
+        assert!(matcher.is_match("aab"));
+        
+        // Once the loop is entered it keeps every `a`, leaving none for the follower, so
+        // `a*+a` can never match "aa" and is rejected outright
+        let hir = ParserBuilder::new().build().parse("a*+a").unwrap();
+        assert!(matches!(Compiler::new().compile(&hir), Err(CompileError::UnsupportedFeature(_))));
+        assert!(Matcher::new(&compile("a*a")).is_match("aa"));
+        
+        // Zero repetitions are only taken where the next character can't start one
+        let cases = [
+            ("a*+.", "a", None),
+            ("b*+[^a]", "bbabc", Some((3, 5))),
+            ("[^a]*+b+", "aabc", None),
+            ("[ab]*+(a.)c+", "acccc", None),
+            ("a*+b", "xab", Some((1, 3))),
+            ("(?:ab?)*+c", "abac", Some((0, 4))),
+            ("(?:ab?)*+a", "aba", None),
+        ];
+        for (pattern, input, expected) in cases {
+            let found = Matcher::new(&compile(pattern)).find(input).map(|m| (m.start, m.end));
+            assert_eq!(found, expected, "{} on {:?}", pattern, input);
+        }
+    }
+    
+    #[test]
+    fn test_compile_many_classify() {
+        let hirs: Vec<Hir> = ["true", "false"].iter()
+            .map(|pattern| ParserBuilder::new().build().parse(pattern).unwrap())
+            .collect();
+        let nfa = Compiler::new().compile_many(&hirs).unwrap();
+        
+        assert_eq!(nfa.classify("true"), Some(0));
+        assert_eq!(nfa.classify("false"), Some(1));
+        assert_eq!(nfa.classify("maybe"), None);
+        assert_eq!(nfa.classify("tru"), None);
+        assert_eq!(nfa.classify("truex"), None);
+    }
+    
+    #[test]
+    fn test_classify_prefers_first_pattern() {
+        let hirs: Vec<Hir> = ["if", "[a-z]+"].iter()
+            .map(|pattern| ParserBuilder::new().build().parse(pattern).unwrap())
+            .collect();
+        let nfa = Compiler::new().compile_many(&hirs).unwrap();
+        
+        // Both patterns accept "if", so the keyword listed first wins the tie
+        assert_eq!(nfa.classify("if"), Some(0));
+        assert_eq!(nfa.classify("iff"), Some(1));
+    }
+    
+    #[test]
+    fn test_exact_repetition() {
+        let hir = ParserBuilder::new().build().parse("a{3}").unwrap();
+        match hir.kind() {
+            HirKind::Repetition(rep) => assert_eq!((rep.min, rep.max), (3, Some(3))),
+            kind => panic!("expected repetition, got {:?}", kind),
+        }
+        
+        let nfa = compile("a{3}");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("aaa"));
+        assert!(!matcher.is_match("aa"));
+        assert!(!matcher.is_match("aaaa"));
+        
+        // No optional split and no back-edge loop
+        assert!(!nfa.states.iter().any(|state| matches!(state, crate::nfa::State::Split { .. })));
+        assert_eq!(nfa.match_length_bounds(), (3, Some(3)));
+    }
+    
+    #[test]
+    fn test_case_insensitive_folds_literals_and_classes() {
+        let compile_folded = |pattern: &str| {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            Compiler::new().case_insensitive(true).compile(&hir).unwrap()
+        };
+        
+        let nfa = compile_folded("straße");
+        let matcher = Matcher::new(&nfa);
+        for input in ["straße", "STRAßE", "StRaßE"] {
+            assert!(matcher.is_match(input), "{:?}", input);
+        }
+        assert!(!matcher.is_match("STRASSE"));
+        
+        let nfa = compile_folded("[a-c]x+(y|é)");
+        let matcher = Matcher::new(&nfa);
+        assert!(matcher.is_match("BXxÉ"));
+        assert!(matcher.is_match("axy"));
+        assert!(!matcher.is_match("dxy"));
+        
+        // Off by default
+        assert!(!Matcher::new(&compile("straße")).is_match("STRAßE"));
+    }
+    
+    #[test]
+    fn test_case_insensitive_lookahead_uses_simple_folds() {
+        let parse = |pattern: &str| ParserBuilder::new().build().parse(pattern).unwrap();
+        let first = parse("a");
+        let fires = |compiler: &Compiler, start: StateId, next: char| {
+            compiler.nfa.transitions_of(start).iter().any(|t| t.matches('a', Some(next)))
+        };
+        
+        // The follower arrives folded, so its lookahead covers exactly what it consumes:
+        // the Kelvin sign for `k`, and `ẞ` but not `S` for `ß`
+        let cases = [("k", &['k', 'K', '\u{212A}'][..], &['c'][..]), ("ß", &['ß', 'ẞ'], &['s', 'S'])];
+        for (follower, folds, others) in cases {
+            let lookahead = fold_case(&parse(follower));
+            let mut compiler = Compiler::new().case_insensitive(true);
+            let fragment = compiler.compile_with_lookahead(&first, &lookahead).unwrap();
+            
+            for &next in folds {
+                assert!(fires(&compiler, fragment.start, next), "{} before {:?}", follower, next);
+            }
+            for &next in others {
+                assert!(!fires(&compiler, fragment.start, next), "{} before {:?}", follower, next);
+            }
+        }
+        
+        // Without the option only the exact lookahead fires
+        let mut compiler = Compiler::new();
+        let fragment = compiler.compile_with_lookahead(&first, &parse("b")).unwrap();
+        
+        assert!(fires(&compiler, fragment.start, 'b'));
+        assert!(!fires(&compiler, fragment.start, 'B'));
+    }
+    
+    #[test]
+    fn test_standalone_possessive() {
+        let nfa = compile("a++");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("aaa"));
+        assert!(!matcher.is_match(""));
+        assert!(!matcher.is_match("aaab"));
+        
+        let result = matcher.find("aaa").unwrap();
+        assert_eq!((result.start, result.end), (0, 3));
+        let result = matcher.find("aaab").unwrap();
+        assert_eq!((result.start, result.end), (0, 3));
+        
+        // The same repetition reached through the pairwise path agrees on the prefix
+        let paired = compile("a++b");
+        let result = Matcher::new(&paired).find("aaab").unwrap();
+        assert_eq!((result.start, result.end), (0, 4));
+    }
+    
+    #[test]
+    fn test_possessive_plus_exits_at_end_of_input() {
+        let nfa = compile("a++");
+        let matcher = Matcher::new(&nfa);
+        
+        // The loop transition needs another `a` after the current one, so on the last
+        // character only the exit fires, and its negated lookahead accepts end of input
+        for input in ["a", "aa", "aaa"] {
+            let chars: Vec<char> = input.chars().collect();
+            assert_eq!(matcher.match_full(input).map(|m| m.end), Ok(chars.len()), "{:?}", input);
+            // Possessive: the only accepting end is after the whole run
+            assert_eq!(matcher.accepting_ends_from(&chars, 0), vec![chars.len()], "{:?}", input);
+        }
+        
+        // One exit, whose lookahead is the negated atom
+        let crate::nfa::State::Transitions { transitions } = &nfa.states[nfa.start] else {
+            panic!("a++ should start at its loop state");
+        };
+        let exits: Vec<_> = transitions.iter().filter(|t| t.target != nfa.start).collect();
+        assert_eq!(exits.len(), 1);
+        assert_eq!(exits[0].lookahead, Some(crate::nfa::CharacterPredicate::NotCharSet(HashSet::from(['a']))));
+    }
+    
+    #[test]
+    fn test_possessive_before_end_anchor() {
+        let nfa = compile(r"\s++$");
+        let matcher = Matcher::new(&nfa);
+        
+        assert_eq!(matcher.find("   ").map(|m| (m.start, m.end)), Some((0, 3)));
+        assert_eq!(matcher.find("x   ").map(|m| (m.start, m.end)), Some((1, 4)));
+        assert!(matcher.find("   x").is_none());
+        
+        // `$` still allows a trailing newline after a run that can't include it
+        let nfa = compile("a++$");
+        assert_eq!(Matcher::new(&nfa).find("aa\n").map(|m| (m.start, m.end)), Some((0, 2)));
+        assert!(Matcher::new(&nfa).find("aab").is_none());
+        assert!(Matcher::new(&compile("(?:ab)++$")).find("ababc").is_none());
+        
+        // The tokenizer's trailing whitespace branch only takes a run that ends the input
+        let nfa = compile(r"(?:[sdmt]|ll|ve|re)| ?\p{L}++| ?\p{N}++| ?[^\s\p{L}\p{N}]++|\s++$|\s+\S|\s");
+        let spans: Vec<_> = Matcher::new(&nfa).find_all("hi  ").iter().map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, vec![(0, 2), (2, 4)]);
+    }
+    
+    #[test]
+    fn test_alternation_prefers_earlier_branch() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find(input).map(|m| (m.start, m.end))
+        };
+        
+        assert_eq!(span("a|ab", "ab"), Some((0, 1)));
+        assert_eq!(span("ab|a", "ab"), Some((0, 2)));
+        assert_eq!(span("x|a|ab", "ab"), Some((0, 1)));
+        assert_eq!(span("x|ab|a", "ab"), Some((0, 2)));
+    }
+    
+    #[test]
+    fn test_any_character_classes_compile_to_any() {
+        use crate::nfa::CharacterPredicate;
+        
+        let current_predicates = |nfa: &NFA| -> Vec<CharacterPredicate> {
+            nfa.states.iter()
+                .flat_map(|state| match state {
+                    crate::nfa::State::Transitions { transitions } => transitions.clone(),
+                    _ => Vec::new(),
+                })
+                .map(|transition| transition.current)
+                .collect()
+        };
+        
+        let dotall = compile("(?s).");
+        let both_classes = compile(r"[\s\S]");
+        assert_eq!(current_predicates(&dotall), vec![CharacterPredicate::Any]);
+        assert_eq!(current_predicates(&both_classes), vec![CharacterPredicate::Any]);
+        
+        for nfa in [&dotall, &both_classes] {
+            let matcher = Matcher::new(nfa);
+            for input in ["a", "\n", "\u{0}", "é", "\u{10FFFF}", "🎉"] {
+                assert!(matcher.is_match(input), "{:?}", input);
+            }
+        }
+        
+        // Without DOTALL the newline is still excluded
+        assert!(!Matcher::new(&compile(".")).is_match("\n"));
+    }
+    
+    #[test]
+    fn test_alternation_follower_lookahead() {
+        use crate::nfa::CharacterPredicate;
+        
+        let parse = |pattern: &str| ParserBuilder::new().build().parse(pattern).unwrap();
+        let first = parse("a");
+        
+        let cases = [
+            ("(?:b|c)", ["ab", "ac"]),
+            ("(?:bx|cy)", ["abx", "acy"]),
+            ("(?:b|c)d", ["abd", "acd"]),
+            ("(b|cz)+", ["ab", "acz"]),
+        ];
+        
+        for (follower, inputs) in cases {
+            let follower = parse(follower);
+            let mut compiler = Compiler::new();
+            let head = compiler.compile_with_lookahead(&first, &follower).unwrap();
+            
+            let expected: HashSet<char> = ['b', 'c'].into_iter().collect();
+            assert_eq!(
+                compiler.nfa.transitions_of(head.start)[0].lookahead,
+                Some(CharacterPredicate::CharSet(expected))
+            );
+            
+            // Wire the follower in after `a` and run the whole thing
+            let tail = compiler.compile_hir(&follower).unwrap();
+            compiler.nfa.connect(head.end, tail.start);
+            compiler.nfa.start = head.start;
+            let match_state = compiler.nfa.match_state();
+            compiler.nfa.connect(tail.end, match_state);
+            
+            let matcher = Matcher::new(&compiler.nfa);
+            for input in inputs {
+                assert!(matcher.is_match(input), "{:?}", input);
+            }
+            assert!(matcher.find("ad").is_none());
+        }
+    }
+    
+    #[test]
+    fn test_is_nullable() {
+        let nullable = |pattern: &str| {
+            Compiler::is_nullable(&ParserBuilder::new().build().parse(pattern).unwrap())
+        };
+        
+        assert!(nullable("a*"));
+        assert!(!nullable("a+"));
+        assert!(nullable("(a|)"));
+        assert!(!nullable("ab"));
+        assert!(nullable("a{0,3}"));
+        assert!(nullable("(?:a?b*)+"));
+        assert!(nullable(""));
+    }
+    
+    #[test]
+    fn test_optional_counted_repetition() {
+        let nfa = compile("a{0,2}b");
+        let matcher = Matcher::new(&nfa);
+        
+        for input in ["b", "ab", "aab"] {
+            assert!(matcher.is_match(input), "{:?}", input);
+        }
+        assert!(!matcher.is_match("aaab"));
+        assert!(!matcher.is_match("a"));
+    }
+    
+    #[test]
+    fn test_lookbehind() {
+        let parse = |pattern: &str| ParserBuilder::new().build().parse(pattern).unwrap();
+        let nfa = Compiler::new().compile_lookbehind(&parse("ab"), &parse("c")).unwrap();
+        let matcher = Matcher::new(&nfa);
+        
+        assert_eq!(matcher.find("abc").map(|m| (m.start, m.end)), Some((2, 3)));
+        assert!(matcher.find("axc").is_none());
+        assert!(matcher.find("c").is_none());
+        assert_eq!(nfa.clone().freeze().find("abc").map(|m| (m.start, m.end)), Some((2, 3)));
+        
+        let result = Compiler::new().compile_lookbehind(&parse("a+"), &parse("c"));
+        assert!(matches!(result, Err(CompileError::UnsupportedFeature(_))));
+    }
+    
+    #[test]
+    fn test_compile_keywords() {
+        let nfa = Compiler::new().compile_keywords(&["int", "interface", "if"]).unwrap();
+        
+        assert_eq!(nfa.classify("int"), Some(0));
+        assert_eq!(nfa.classify("interface"), Some(1));
+        assert_eq!(nfa.classify("if"), Some(2));
+        assert_eq!(nfa.classify("inter"), None);
+        assert_eq!(nfa.classify("i"), None);
+        
+        // One transition state per distinct proper prefix: "", i, in, int, inte, ..., interfac
+        assert_eq!(nfa.stats().transition_states, 9);
+        
+        let matcher = Matcher::new(&nfa);
+        assert_eq!(matcher.find("an interface").map(|m| (m.start, m.end)), Some((3, 12)));
+    }
+    
+    #[test]
+    fn test_compile_lexer() {
+        let nfa = Compiler::new().compile_lexer(&[("if", 0), ("else", 1), ("[a-z]+", 7)]).unwrap();
+        let matcher = Matcher::new(&nfa);
+        
+        assert_eq!(matcher.classify("if"), Some(0));
+        assert_eq!(matcher.classify("else"), Some(1));
+        assert_eq!(matcher.classify("elsewhere"), Some(7));
+        assert_eq!(matcher.classify("if else"), None);
+        
+        assert!(matches!(Compiler::new().compile_lexer(&[("(", 0)]), Err(CompileError::Parse(_))));
+    }
+    
+    #[test]
+    fn test_repeated_assertion_errors() {
+        for pattern in [r"\b*", r"\b{0,3}", "(?:^)?", r"(?:\b$)*"] {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            match Compiler::new().compile(&hir) {
+                Err(CompileError::UnsupportedFeature(message)) => {
+                    assert_eq!(message, "repetition of zero-width assertion", "{}", pattern);
+                },
+                result => panic!("{}: expected an error, got {:?}", pattern, result.map(|nfa| nfa.states.len())),
+            }
+        }
+        
+        // regex-syntax folds `\b+` and `(?:^){2}` down to the bare assertion, so they
+        // never reach the repetition check and compile like the assertion alone
+        for pattern in [r"\b+", "(?:^){2}"] {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            assert!(matches!(hir.kind(), HirKind::Look(_)), "{}", pattern);
+        }
+        let nfa = compile(r"\b+a");
+        assert_eq!(Matcher::new(&nfa).find("ba a").map(|m| (m.start, m.end)), Some((3, 4)));
+        let nfa = compile("(?:^){2}a");
+        assert_eq!(Matcher::new(&nfa).find("aa").map(|m| (m.start, m.end)), Some((0, 1)));
+    }
+    
+    #[test]
+    fn test_end_text_anchor() {
+        let hir = ParserBuilder::new().build().parse("abc$").unwrap();
+        let span = |nfa: &NFA, input: &str| Matcher::new(nfa).find(input).map(|m| (m.start, m.end));
+        
+        let nfa = Compiler::new().compile(&hir).unwrap();
+        assert_eq!(span(&nfa, "abc"), Some((0, 3)));
+        assert_eq!(span(&nfa, "abc\n"), Some((0, 3)));
+        assert_eq!(span(&nfa, "abcx"), None);
+        assert_eq!(span(&nfa, "abc\n\n"), None);
+        
+        let strict = Compiler::new().trailing_newline_at_end(false).compile(&hir).unwrap();
+        assert_eq!(span(&strict, "abc"), Some((0, 3)));
+        assert_eq!(span(&strict, "abc\n"), None);
+        assert_eq!(span(&strict, "abcx"), None);
+    }
+    
+    #[test]
+    fn test_anchored_alternation_branches() {
+        let nfa = compile("^a|b$");
+        let span = |input: &str| Matcher::new(&nfa).find(input).map(|m| (m.start, m.end));
+        
+        assert_eq!(span("ax"), Some((0, 1)));
+        assert_eq!(span("xb"), Some((1, 2)));
+        assert_eq!(span("xax"), None);
+        assert_eq!(span("xbx"), None);
+        
+        let nfa = compile("(?:^|,)x");
+        let spans: Vec<_> = Matcher::new(&nfa).find_all("x,x x").iter().map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, vec![(0, 1), (1, 3)]);
+    }
+    
+    #[test]
+    fn test_line_anchors() {
+        let span = |nfa: &NFA, input: &str| Matcher::new(nfa).find(input).map(|m| (m.start, m.end));
+        let crlf = |pattern: &str| {
+            let hir = ParserBuilder::new().crlf(true).build().parse(pattern).unwrap();
+            Compiler::new().compile(&hir).unwrap()
+        };
+        
+        // In CRLF mode `$` sits before the `\r` of a `\r\n`
+        assert_eq!(span(&crlf("(?m)foo$"), "foo\r\nbar"), Some((0, 3)));
+        assert_eq!(span(&compile("(?Rm)foo$"), "foo\r\nbar"), Some((0, 3)));
+        assert_eq!(span(&crlf("(?m)^bar"), "foo\r\nbar"), Some((5, 8)));
+        assert_eq!(span(&crlf("(?m)foo$"), "foo\rbar"), Some((0, 3)));
+        
+        // ...and never between the `\r` and the `\n`
+        let nfa = crlf("(?m)$");
+        let ends: Vec<_> = Matcher::new(&nfa).find_all("a\r\nb").iter().map(|m| m.start).collect();
+        assert_eq!(ends, vec![1, 4]);
+        let nfa = crlf("(?m)^");
+        let starts: Vec<_> = Matcher::new(&nfa).find_all("a\r\nb").iter().map(|m| m.start).collect();
+        assert_eq!(starts, vec![0, 3]);
+        
+        // Plain multi-line anchors only know `\n`
+        assert_eq!(span(&compile("(?m)foo$"), "foo\r\nbar"), None);
+        assert_eq!(span(&compile("(?m)foo$"), "foo\nbar"), Some((0, 3)));
+        assert_eq!(span(&compile("(?m)^bar"), "foo\nbar"), Some((4, 7)));
+    }
+    
+    #[test]
+    fn test_possessive_group_with_alternation() {
+        let nfa = compile("(ab|cd)++");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("abcdab"));
+        assert!(matcher.is_match("cd"));
+        assert!(!matcher.is_match("abc"));
+        assert!(!matcher.is_match(""));
+        
+        // The group still records its last iteration
+        let result = matcher.captures("abcdab").unwrap();
+        assert_eq!(result.span(1), Some((4, 6)));
+        
+        // A possessive loop doesn't give back an iteration to the rest of the pattern
+        let nfa = compile("(?:ab|cd)++ab");
+        assert!(!Matcher::new(&nfa).is_match("cdab"));
+        
+        // With nothing after the loop, the match ends after the last whole iteration
+        let span = |pattern: &str, input: &str| Matcher::new(&compile(pattern)).find(input).map(|m| (m.start, m.end));
+        assert_eq!(span("(ab|cd)++", "abc"), Some((0, 2)));
+        assert_eq!(span("(ab|cd)++", "xabcdabx"), Some((1, 7)));
+        assert_eq!(span("(ab?|cd?)++", "xabcdx"), Some((1, 5)));
+        assert_eq!(span("(?:ab?)++", "aaba"), Some((0, 4)));
+        assert_eq!(span("(?:[ab]c?)*+", "babb"), Some((0, 4)));
+        assert!(Matcher::new(&compile("(?:ab?|cd?)++e")).is_match("cdabe"));
+    }
+    
+    #[test]
+    fn test_possessive_loop_commits_to_first_branch() {
+        // Each iteration takes the first branch that matches and the loop stops where no
+        // branch does, so a shorter branch never lets a longer one through later
+        let cases = [
+            ("(?:a|ab)++c", "abc", None),
+            ("(?:ab|a)++b", "abab", None),
+            ("(?:a|ab)++", "ab", Some((0, 1))),
+            ("(?:a|ab)*+c", "abc", Some((2, 3))),
+            ("(?:ab|a)++", "abaab", Some((0, 5))),
+            ("(?:abc|a)++bc", "abcabc", None),
+            ("(?:abc|a)++bc", "aabc", None),
+            ("(?:abc|a)++b", "abcab", Some((0, 5))),
+            ("(?:ab|cd)*+c", "abcdc", Some((0, 5))),
+            ("(?:ab|cd)*+c", "abcdab", None),
+        ];
+        for (pattern, input, expected) in cases {
+            let found = Matcher::new(&compile(pattern)).find(input).map(|m| (m.start, m.end));
+            assert_eq!(found, expected, "{} on {:?}", pattern, input);
+        }
+        assert!(!Matcher::new(&compile("(?:a|ab)++")).is_match("ab"));
+        
+        // Required copies may still take a later branch to let the next one match
+        assert_eq!(Matcher::new(&compile("(?:a|ab){2,}+")).find("abab").map(|m| (m.start, m.end)), Some((0, 3)));
+        assert!(Matcher::new(&compile("(?:a|ab){2,}+c")).find("aabc").is_none());
+        
+        // Bodies that aren't a small set of strings must have no choices of their own
+        for pattern in ["(?:a[0-9]*)++", "(?:(a)|b)++"] {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            assert!(matches!(Compiler::new().compile(&hir), Err(CompileError::UnsupportedFeature(_))), "{}", pattern);
+        }
+    }
+    
+    #[test]
+    fn test_class_ranges_compile_to_range_predicates() {
+        use crate::nfa::CharacterPredicate;
+        
+        let nfa = compile("[a-z]");
+        assert_eq!(nfa.transitions_of(nfa.start), &[
+            TwoCharTransition::predicate(CharacterPredicate::Range('a', 'z'), None, 0),
+        ]);
+        
+        let nfa = compile("[0-9a-fx]");
+        assert_eq!(
+            nfa.transitions_of(nfa.start)[0].current,
+            CharacterPredicate::Ranges(vec![('0', '9'), ('a', 'f'), ('x', 'x')])
+        );
+        
+        let matcher = Matcher::new(&nfa);
+        for input in ["0", "9", "a", "f", "x"] {
+            assert!(matcher.is_match(input), "{:?}", input);
+        }
+        for input in ["g", "w", "y", "/"] {
+            assert!(!matcher.is_match(input), "{:?}", input);
+        }
+    }
+    
+    #[test]
+    fn test_shorthand_classes() {
+        use crate::nfa::CharacterPredicate;
+        
+        let digits = "09\u{663}\u{967}\u{FF15}";
+        let words = "aZ_0\u{E9}\u{436}\u{4E2D}";
+        let spaces = " \t\n\r\x0B\x0C\u{85}\u{A0}\u{2003}\u{3000}";
+        let cases = [
+            ("\\d", digits, "a_ \u{B2}"),
+            ("\\w", words, " -!\u{2003}"),
+            ("\\s", spaces, "a0_\u{200B}"),
+        ];
+        
+        for (pattern, members, non_members) in cases {
+            let negated = pattern.to_uppercase();
+            for (pattern, members, non_members) in [(pattern, members, non_members), (&*negated, non_members, members)] {
+                let nfa = compile(pattern);
+                let predicate = &nfa.transitions_of(nfa.start)[0].current;
+                
+                // Shorthands and their negations stay a short list of intervals
+                match predicate {
+                    CharacterPredicate::Ranges(ranges) => assert!(ranges.len() < 1000, "{}", pattern),
+                    other => panic!("{} compiled to {:?}", pattern, other),
+                }
+                for ch in members.chars() {
+                    assert!(predicate.matches(ch), "{} should match {:?}", pattern, ch);
+                }
+                for ch in non_members.chars() {
+                    assert!(!predicate.matches(ch), "{} should not match {:?}", pattern, ch);
+                }
+            }
+        }
+        
+        // Without Unicode the shorthands are their ASCII definitions
+        let ascii = [
+            ("\\d", CharacterPredicate::Range('0', '9')),
+            ("\\w", CharacterPredicate::Ranges(vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')])),
+            ("\\s", CharacterPredicate::Ranges(vec![('\t', '\r'), (' ', ' ')])),
+        ];
+        for (pattern, predicate) in ascii {
+            let hir = ParserBuilder::new().unicode(false).build().parse(pattern).unwrap();
+            let nfa = Compiler::new().compile(&hir).unwrap();
+            assert_eq!(nfa.transitions_of(nfa.start)[0].current, predicate, "{}", pattern);
+        }
+    }
+    
+    #[test]
+    fn test_posix_classes() {
+        use crate::nfa::CharacterPredicate;
+        
+        let cases = [
+            ("[[:digit:]]", CharacterPredicate::Range('0', '9'), "059", "a/:"),
+            ("[[:alpha:]]", CharacterPredicate::Ranges(vec![('A', 'Z'), ('a', 'z')]), "aZm", "0_[`{"),
+            ("[[:space:]]", CharacterPredicate::Ranges(vec![('\t', '\r'), (' ', ' ')]), " \t\n\x0B\r", "a_\x08\x0E"),
+        ];
+        
+        for (pattern, predicate, members, non_members) in cases {
+            for unicode in [true, false] {
+                let hir = ParserBuilder::new().unicode(unicode).build().parse(pattern).unwrap();
+                let nfa = Compiler::new().compile(&hir).unwrap();
+                assert_eq!(nfa.transitions_of(nfa.start)[0].current, predicate, "{}", pattern);
+                
+                let matcher = Matcher::new(&nfa);
+                for ch in members.chars() {
+                    assert!(matcher.is_match(&ch.to_string()), "{} on {:?}", pattern, ch);
+                }
+                for ch in non_members.chars() {
+                    assert!(!matcher.is_match(&ch.to_string()), "{} on {:?}", pattern, ch);
+                }
+            }
+        }
+        
+        // Negated POSIX classes flip membership, including far outside ASCII
+        let nfa = compile("[[:^alpha:]]");
+        let matcher = Matcher::new(&nfa);
+        for ch in ["0", " ", "\u{3000}", "\u{10FFFF}"] {
+            assert!(matcher.is_match(ch), "{:?}", ch);
+        }
+        for ch in ["a", "Q"] {
+            assert!(!matcher.is_match(ch), "{:?}", ch);
+        }
+    }
+    
+    #[test]
+    fn test_large_negated_class_rejects_every_excluded_char() {
+        // Far more than 200 excluded codepoints, small enough for a NotCharSet
+        let nfa = compile(r"[^\x{100}-\x{3FF}]");
+        let matcher = Matcher::new(&nfa);
+        for c in '\u{100}'..='\u{3FF}' {
+            assert!(!matcher.is_match(&c.to_string()), "{:?}", c);
+        }
+        assert!(matcher.is_match("a"));
+        assert!(matcher.is_match("\u{400}"));
+        
+        // Too many excluded codepoints to list, compiled to ranges instead
+        let nfa = compile(r"[^\x{100}-\x{2FFF}]");
+        let matcher = Matcher::new(&nfa);
+        for c in '\u{100}'..='\u{2FFF}' {
+            assert!(!matcher.is_match(&c.to_string()), "{:?}", c);
+        }
+        assert!(matcher.is_match("a"));
+        assert!(matcher.is_match("\u{3000}"));
+        
+        // Possessive loops over these classes still accept at end of input
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find(input).map(|m| (m.start, m.end))
+        };
+        assert_eq!(span("[^ab]++", "cc"), Some((0, 2)));
+        assert_eq!(span("(?s).++", "xy"), Some((0, 2)));
+    }
+    
+    #[test]
+    fn test_reluctant_counted() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find(input).map(|m| (m.start, m.end))
+        };
+        
+        // Minimal consumption: stop at the lower bound unless the rest of the pattern
+        // can only match after more copies
+        assert_eq!(span("a{2,4}?", "aaaa"), Some((0, 2)));
+        assert_eq!(span("a{2,4}", "aaaa"), Some((0, 4)));
+        assert_eq!(span("a{2,}?", "aaaa"), Some((0, 2)));
+        assert_eq!(span("a{0,2}?", "aa"), Some((0, 0)));
+        assert_eq!(span("a{2,4}?b", "aaaab"), Some((0, 5)));
+        assert_eq!(span("a{2,4}?a", "aaaa"), Some((0, 3)));
+        
+        let nfa = compile("(a{1,3}?)(a*)");
+        let result = Matcher::new(&nfa).captures("aaa").unwrap();
+        assert_eq!(result.span(1), Some((0, 1)));
+        assert_eq!(result.span(2), Some((1, 3)));
+    }
+    
+    #[test]
+    fn test_plus_priority() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find(input).map(|m| (m.start, m.end))
+        };
+        
+        assert_eq!(span("a+", "aaab"), Some((0, 3)));
+        assert_eq!(span("a+?", "aaab"), Some((0, 1)));
+        assert_eq!(span("(?:ab)+?c", "ababc"), Some((0, 5)));
+        
+        // Priority within the body still decides the first copy
+        assert_eq!(span("(?:a|ab)+", "abab"), Some((0, 1)));
+        assert_eq!(span("(?:ab|a)+?", "abab"), Some((0, 2)));
+        assert_eq!(span("(?:ab|a)+", "abab"), Some((0, 4)));
+        
+        let nfa = compile("(a+?)(a*)");
+        let result = Matcher::new(&nfa).captures("aaa").unwrap();
+        assert_eq!(result.span(1), Some((0, 1)));
+        assert_eq!(result.span(2), Some((1, 3)));
+    }
+    
+    #[test]
+    fn test_utf8_bytes_mode() {
+        use crate::nfa::{CharacterPredicate, State};
+        
+        let compile_bytes = |pattern: &str| {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            Compiler::new().utf8_bytes_mode(true).compile(&hir).unwrap()
+        };
+        
+        // `é` is the two bytes C3 A9
+        let nfa = compile_bytes("é");
+        let State::Transitions { transitions } = &nfa.states[nfa.start] else {
+            panic!("expected a transition state");
+        };
+        assert_eq!(transitions[0].current, CharacterPredicate::Char('\u{C3}'));
+        let next = transitions[0].target;
+        assert_eq!(nfa.transitions_of(next)[0].current, CharacterPredicate::Char('\u{A9}'));
+        
+        let matcher = Matcher::new(&nfa);
+        assert_eq!(matcher.find_bytes("café".as_bytes()).map(|m| (m.start, m.end)), Some((3, 5)));
+        assert!(matcher.find_bytes(b"\xE9").is_none());
+        
+        // Classes match whole encodings of any length
+        let nfa = compile_bytes(r"\p{L}+");
+        let input = "1 añ中𝔸 2".as_bytes();
+        assert_eq!(Matcher::new(&nfa).find_bytes(input).map(|m| (m.start, m.end)), Some((2, 12)));
+        let nfa = compile_bytes("[^a]");
+        assert_eq!(Matcher::new(&nfa).find_bytes("aé".as_bytes()).map(|m| (m.start, m.end)), Some((1, 3)));
+        
+        // ASCII possessives keep working; others need more than one byte of lookahead
+        let nfa = compile_bytes("[a-z]++é");
+        assert_eq!(Matcher::new(&nfa).find_bytes("abé".as_bytes()).map(|m| (m.start, m.end)), Some((0, 4)));
+        let hir = ParserBuilder::new().build().parse("é++").unwrap();
+        assert!(matches!(Compiler::new().utf8_bytes_mode(true).compile(&hir), Err(CompileError::UnsupportedFeature(_))));
+        
+        // A byte can't tell whether `é` is a word character, so only ASCII boundaries work
+        for pattern in [r"é\b", r"\Bx"] {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            assert!(matches!(Compiler::new().utf8_bytes_mode(true).compile(&hir), Err(CompileError::UnsupportedFeature(_))), "{}", pattern);
+        }
+        let nfa = compile_bytes(r"(?-u:\b)ab(?-u:\b)");
+        assert_eq!(Matcher::new(&nfa).find_bytes("é ab".as_bytes()).map(|m| (m.start, m.end)), Some((3, 5)));
+        assert_eq!(Matcher::new(&nfa).find_bytes("éab".as_bytes()).map(|m| (m.start, m.end)), Some((2, 4)));
+        assert!(Matcher::new(&nfa).find_bytes(b"xab").is_none());
+    }
+    
+    #[test]
+    fn test_no_trailing_epsilon_before_match() {
+        // `a` was already just its transition into the reserved match state; patterns ending
+        // in a loop or alternation no longer keep the join epsilon in front of it
+        for (pattern, states) in [("a", 3), ("a*", 4), ("a+", 4), ("ab|c", 6), ("(a)", 5)] {
+            assert_eq!(compile(pattern).states.len(), states, "{}", pattern);
+        }
+        
+        let span = |pattern: &str, input: &str| Matcher::new(&compile(pattern)).find(input).map(|m| (m.start, m.end));
+        assert_eq!(span("a*", "aaab"), Some((0, 3)));
+        assert_eq!(span("a+?", "aaa"), Some((0, 1)));
+        assert_eq!(span("ab|c", "xc"), Some((1, 2)));
+        assert_eq!(span("(?:ab)*", "ababa"), Some((0, 4)));
+        let nfa = compile("(a)|b");
+        assert_eq!(Matcher::new(&nfa).captures("a").unwrap().span(1), Some((0, 1)));
+    }
+    
+    #[test]
+    fn test_empty_alternative() {
+        let span = |pattern: &str, input: &str| Matcher::new(&compile(pattern)).find(input).map(|m| (m.start, m.end));
+        
+        assert_eq!(span("a|", ""), Some((0, 0)));
+        assert_eq!(span("a|", "a"), Some((0, 1)));
+        // Leftmost-first: the empty branch comes first and wins
+        assert_eq!(span("|a", "a"), Some((0, 0)));
+        assert_eq!(span("a||b", "a"), Some((0, 1)));
+        assert_eq!(span("a||b", "b"), Some((0, 0)));
+        assert_eq!(span("(?:a|)b", "ab"), Some((0, 2)));
+        assert_eq!(span("(?:a|)b", "xb"), Some((1, 2)));
+        
+        for pattern in ["a|", "|a", "a||b"] {
+            let nfa = compile(pattern);
+            let matcher = Matcher::new(&nfa);
+            assert!(matcher.is_match("") && matcher.is_match("a"), "{}", pattern);
+            assert!(!matcher.is_match("aa"), "{}", pattern);
+        }
+        assert!(Matcher::new(&compile("a||b")).is_match("b"));
+    }
+    
+    #[test]
+    fn test_quantifier_at_loop_state() {
+        use crate::nfa::{QuantifierInfo, QuantifierKind};
+        
+        // The single-character possessive loop is the fragment's first state
+        let nfa = compile("a++");
+        assert_eq!(nfa.quantifier_at(nfa.start), Some(QuantifierInfo { kind: QuantifierKind::Possessive, min: 1, max: None }));
+        
+        let only = |pattern: &str| {
+            let nfa = compile(pattern);
+            assert_eq!(nfa.quantifiers.len(), 1, "{}", pattern);
+            nfa.quantifiers.values().next().copied().map(|info| (info.kind, info.min))
+        };
+        assert_eq!(only("a*"), Some((QuantifierKind::Greedy, 0)));
+        assert_eq!(only("a+?"), Some((QuantifierKind::Lazy, 1)));
+        assert_eq!(only("(?:ab){3,}"), Some((QuantifierKind::Greedy, 3)));
+        assert_eq!(only("a*+b"), Some((QuantifierKind::Possessive, 0)));
+        assert_eq!(only("(?:ab){2,}+"), Some((QuantifierKind::Possessive, 2)));
+        
+        // Bounded repetitions don't loop
+        assert!(compile("a{2,5}").quantifiers.is_empty());
+        assert_eq!(compile("a").quantifier_at(2), None);
+    }
+    
+    #[test]
+    fn test_singleton_wrappers_add_no_states() {
+        for (wrapped, plain) in [("(?:(?:a))", "a"), ("(?:a)(?:(?:)b)", "ab"), ("(?:a|(?:b|cd))e", "(?:a|b|cd)e"), ("(?:a){1}", "a")] {
+            assert_eq!(compile(wrapped), compile(plain), "{}", wrapped);
+        }
+    }
+    
+    #[test]
+    fn test_warn_redundant_repetition() {
+        let warnings = |pattern: &str| {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            let (_, warnings) = Compiler::new().warn_redundant_repetition(true).compile_with_warnings(&hir).unwrap();
+            warnings
+        };
+        
+        assert_eq!(warnings("(a*)*"), vec![CompileWarning::RedundantRepetition("a*".to_string())]);
+        assert_eq!(warnings("(?:a+)*b").len(), 1);
+        assert_eq!(warnings("(?:(a*))+").len(), 1);
+        assert!(warnings("(a*b)*").is_empty());
+        assert!(warnings("(a{2})*").is_empty());
+        
+        // Off by default
+        let hir = ParserBuilder::new().build().parse("(a*)*").unwrap();
+        assert!(Compiler::new().compile_with_warnings(&hir).unwrap().1.is_empty());
+    }
+    
+    #[test]
+    fn test_possessive_at_least() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find(input).map(|m| (m.start, m.end))
+        };
+        
+        assert_eq!(span("a{2,}+b", "aaab"), Some((0, 4)));
+        assert_eq!(span("a{2,}+b", "aab"), Some((0, 3)));
+        assert_eq!(span("a{2,}+b", "ab"), None);
+        assert_eq!(span("a{2,}+b", "aaa"), None);
+        assert_eq!(span("a{2,}+", "aaaa"), Some((0, 4)));
+        assert_eq!(span("x{0,}+", "xx"), Some((0, 2)));
+        
+        // The loop never releases an `a` for the rest of the pattern, so `a{2,}+a` could
+        // never match and is rejected
+        let hir = ParserBuilder::new().build().parse("a{2,}+a").unwrap();
+        assert!(matches!(Compiler::new().compile(&hir), Err(CompileError::UnsupportedFeature(_))));
+        assert_eq!(span("(?:ab){2,}+ab", "ababab"), None);
+        assert_eq!(span("(?:ab){2,}+c", "abababc"), Some((0, 7)));
+        assert_eq!(span("(?:ab?){2,}+c", "abaabc"), Some((0, 6)));
+        assert_eq!(span("(?:ab?){2,}+", "xabaab"), Some((1, 6)));
+        
+        // Literal bodies end in a consuming state rather than an epsilon
+        assert_eq!(span("(?:ab)++", "abab"), Some((0, 4)));
+        
+        // An iteration that starts but can't finish doesn't hide `bc` at 1..3
+        assert_eq!(span("(?:[ab]c){1,}+", "bbcb"), Some((1, 3)));
+        assert_eq!(span("(?:[ab]c?){1,}+", "bbcb"), Some((0, 4)));
+    }
+    
+    #[test]
+    fn test_empty_pattern() {
+        let nfa = compile("");
+        let matcher = Matcher::new(&nfa);
+        
+        // Anchored at both ends, only the empty input matches
+        assert!(matcher.is_match(""));
+        assert!(!matcher.is_match("a"));
+        
+        // Unanchored, an empty match is found at every position
+        assert_eq!(matcher.find("").map(|m| (m.start, m.end)), Some((0, 0)));
+        assert_eq!(matcher.find("a").map(|m| (m.start, m.end)), Some((0, 0)));
+        let spans: Vec<_> = matcher.find_all("ab").iter().map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, vec![(0, 0), (1, 1), (2, 2)]);
+        
+        // An empty branch or repetition still leads on to the rest of the pattern
+        assert_eq!(Matcher::new(&compile("(?:|x)y")).find("y").map(|m| (m.start, m.end)), Some((0, 1)));
+        assert_eq!(Matcher::new(&compile("a{0}b")).find("ab").map(|m| (m.start, m.end)), Some((1, 2)));
+        
+        let hirs: Vec<_> = ["x", ""].iter()
+            .map(|pattern| ParserBuilder::new().build().parse(pattern).unwrap())
+            .collect();
+        let nfa = Compiler::new().compile_many(&hirs).unwrap();
+        assert_eq!(nfa.classify(""), Some(1));
+        assert_eq!(nfa.classify("x"), Some(0));
+    }
+}
@@ -0,0 +1,271 @@
+use crate::nfa::{CharacterPredicate, NFA, State, StateId};
+use std::collections::{BTreeMap, HashSet};
+
+/// Most reachable states `NFA::to_pattern` will eliminate
+const MAX_PATTERN_STATES: usize = 256;
+
+/// A regular expression built up while eliminating states
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    /// Matches the empty string
+    Empty,
+    /// A single character or class, already rendered
+    Atom(String),
+    Concat(Vec<Expr>),
+    Alt(Vec<Expr>),
+    Star(Box<Expr>),
+}
+
+impl Expr {
+    fn concat(self, other: Expr) -> Expr {
+        let mut parts = Vec::new();
+        for expr in [self, other] {
+            match expr {
+                Expr::Empty => {},
+                Expr::Concat(inner) => parts.extend(inner),
+                expr => parts.push(expr),
+            }
+        }
+        match parts.len() {
+            0 => Expr::Empty,
+            1 => parts.remove(0),
+            _ => Expr::Concat(parts),
+        }
+    }
+    
+    fn alt(self, other: Expr) -> Expr {
+        let mut branches: Vec<Expr> = Vec::new();
+        for expr in [self, other] {
+            let inner = match expr {
+                Expr::Alt(inner) => inner,
+                expr => vec![expr],
+            };
+            for branch in inner {
+                if !branches.contains(&branch) {
+                    branches.push(branch);
+                }
+            }
+        }
+        match branches.len() {
+            1 => branches.remove(0),
+            _ => Expr::Alt(branches),
+        }
+    }
+    
+    fn star(self) -> Expr {
+        match self {
+            Expr::Empty => Expr::Empty,
+            Expr::Star(inner) => Expr::Star(inner),
+            expr => Expr::Star(Box::new(expr)),
+        }
+    }
+    
+    /// Render the expression as pattern text
+    fn render(&self) -> String {
+        match self {
+            Expr::Empty => String::new(),
+            Expr::Atom(atom) => atom.clone(),
+            Expr::Concat(parts) => parts.iter()
+                .map(|part| match part {
+                    Expr::Alt(_) if !part.is_optional() => format!("(?:{})", part.render()),
+                    part => part.render(),
+                })
+                .collect(),
+            Expr::Alt(branches) if self.is_optional() => {
+                let rest: Vec<&Expr> = branches.iter().filter(|branch| **branch != Expr::Empty).collect();
+                match rest.as_slice() {
+                    [only] => format!("{}?", only.render_operand()),
+                    _ => {
+                        let rest: Vec<String> = rest.iter().map(|branch| branch.render()).collect();
+                        format!("(?:{})?", rest.join("|"))
+                    },
+                }
+            },
+            Expr::Alt(branches) => branches.iter().map(Expr::render).collect::<Vec<_>>().join("|"),
+            Expr::Star(inner) => format!("{}*", inner.render_operand()),
+        }
+    }
+    
+    /// Render the expression so a postfix operator applies to all of it
+    fn render_operand(&self) -> String {
+        match self {
+            Expr::Atom(atom) => atom.clone(),
+            expr => format!("(?:{})", expr.render()),
+        }
+    }
+    
+    /// Check whether this is an alternation with an empty branch, rendered with `?`
+    fn is_optional(&self) -> bool {
+        matches!(self, Expr::Alt(branches) if branches.contains(&Expr::Empty))
+    }
+}
+
+impl NFA {
+    /// Reconstruct a pattern that matches the same strings as the NFA, where possible
+    ///
+    /// States are eliminated one at a time, joining the expressions on their edges, so
+    /// chains, alternations and loops come back as concatenations, `|` and `*` (`abc`
+    /// compiles back to `"abc"`). The pattern accepts the same whole inputs, but priorities
+    /// between alternatives aren't kept, so leftmost-first spans can differ.
+    ///
+    /// Returns `None` when a reachable state is an assertion or rejected state, when a
+    /// transition has a lookahead (as possessive loops do), when the NFA matches nothing,
+    /// and for NFAs with more than `MAX_PATTERN_STATES` reachable states.
+    pub fn to_pattern(&self) -> Option<String> {
+        let reachable = self.reachable_for_pattern()?;
+        let initial = self.states.len();
+        let accept = initial + 1;
+        
+        // Parallel edges are merged into an alternation as they are added
+        let mut edges: BTreeMap<(usize, usize), Expr> = BTreeMap::new();
+        let add_edge = |edges: &mut BTreeMap<(usize, usize), Expr>, from: usize, to: usize, expr: Expr| {
+            let expr = match edges.remove(&(from, to)) {
+                Some(existing) => existing.alt(expr),
+                None => expr,
+            };
+            edges.insert((from, to), expr);
+        };
+        
+        add_edge(&mut edges, initial, self.start, Expr::Empty);
+        for &state_id in &reachable {
+            match &self.states[state_id] {
+                State::Transitions { transitions } => {
+                    for transition in transitions {
+                        let atom = Expr::Atom(predicate_pattern(&transition.current)?);
+                        add_edge(&mut edges, state_id, transition.target, atom);
+                    }
+                },
+                State::Epsilon { next } => add_edge(&mut edges, state_id, *next, Expr::Empty),
+                State::Split { targets } => {
+                    for &target in targets {
+                        add_edge(&mut edges, state_id, target, Expr::Empty);
+                    }
+                },
+                _ => {},
+            }
+            if self.accepting.contains(&state_id) {
+                add_edge(&mut edges, state_id, accept, Expr::Empty);
+            }
+        }
+        
+        for &state_id in &reachable {
+            let looped = edges.remove(&(state_id, state_id)).map_or(Expr::Empty, Expr::star);
+            let incoming: Vec<(usize, Expr)> = edges.iter()
+                .filter(|((_, to), _)| *to == state_id)
+                .map(|(&(from, _), expr)| (from, expr.clone()))
+                .collect();
+            let outgoing: Vec<(usize, Expr)> = edges.iter()
+                .filter(|((from, _), _)| *from == state_id)
+                .map(|(&(_, to), expr)| (to, expr.clone()))
+                .collect();
+            edges.retain(|&(from, to), _| from != state_id && to != state_id);
+            
+            for (from, before) in &incoming {
+                for (to, after) in &outgoing {
+                    let expr = before.clone().concat(looped.clone()).concat(after.clone());
+                    add_edge(&mut edges, *from, *to, expr);
+                }
+            }
+        }
+        
+        edges.remove(&(initial, accept)).map(|expr| expr.render())
+    }
+    
+    /// Collect the states reachable from the start in ID order, or `None` if any of them
+    /// can't be expressed as a plain pattern
+    fn reachable_for_pattern(&self) -> Option<Vec<StateId>> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![self.start];
+        
+        while let Some(state_id) = stack.pop() {
+            if !seen.insert(state_id) {
+                continue;
+            }
+            match self.states.get(state_id)? {
+                State::Transitions { transitions } => {
+                    if transitions.iter().any(|transition| transition.lookahead.is_some()) {
+                        return None;
+                    }
+                    stack.extend(transitions.iter().map(|transition| transition.target));
+                },
+                State::Epsilon { next } => stack.push(*next),
+                State::Split { targets } => stack.extend(targets.iter().copied()),
+                State::Match => {},
+                State::Rejected | State::Assertion { .. } => return None,
+            }
+            if seen.len() > MAX_PATTERN_STATES {
+                return None;
+            }
+        }
+        
+        let mut reachable: Vec<StateId> = seen.into_iter().collect();
+        reachable.sort_unstable();
+        Some(reachable)
+    }
+}
+
+/// Render a predicate as a single-character pattern, or `None` for end of input
+fn predicate_pattern(predicate: &CharacterPredicate) -> Option<String> {
+    let escape = |ch: char| regex_syntax::escape(ch.encode_utf8(&mut [0; 4]));
+    let sorted = |set: &HashSet<char>| {
+        let mut chars: Vec<char> = set.iter().copied().collect();
+        chars.sort_unstable();
+        chars.into_iter().map(escape).collect::<String>()
+    };
+    let ranges = |ranges: &[(char, char)]| {
+        ranges.iter()
+            .map(|&(start, end)| if start == end { escape(start) } else { format!("{}-{}", escape(start), escape(end)) })
+            .collect::<String>()
+    };
+    
+    Some(match predicate {
+        CharacterPredicate::Any => "(?s:.)".to_string(),
+        CharacterPredicate::Char(ch) => escape(*ch),
+        CharacterPredicate::CharSet(set) if set.len() == 1 => sorted(set),
+        CharacterPredicate::CharSet(set) if set.is_empty() => return None,
+        CharacterPredicate::CharSet(set) => format!("[{}]", sorted(set)),
+        CharacterPredicate::NotCharSet(set) if set.is_empty() => "(?s:.)".to_string(),
+        CharacterPredicate::NotCharSet(set) => format!("[^{}]", sorted(set)),
+        CharacterPredicate::EndOfInput => return None,
+        CharacterPredicate::Range(start, end) => format!("[{}]", ranges(&[(*start, *end)])),
+        CharacterPredicate::Ranges(list) => format!("[{}]", ranges(list)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::matcher::Matcher;
+    use regex_syntax::ParserBuilder;
+    
+    fn compile(pattern: &str) -> NFA {
+        let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+        Compiler::new().compile(&hir).unwrap()
+    }
+    
+    #[test]
+    fn test_to_pattern() {
+        assert_eq!(compile("abc").to_pattern().as_deref(), Some("abc"));
+        assert_eq!(compile("a[x-z]*").to_pattern().as_deref(), Some("a[x-z]*"));
+        
+        // The reconstructed pattern accepts the same whole inputs
+        let inputs = ["", "a", "b", "ab", "abc", "aab", "abab", "bc", "ax", "acx", "aaa", "a.b", "a-b"];
+        for pattern in ["a|bc", "a*b", "(?:ab)+", "[a-c]x?", "a{2,3}", "(a|b)*ab", "a\\.b|[-.]"] {
+            let nfa = compile(pattern);
+            let text = nfa.to_pattern().unwrap_or_else(|| panic!("{} has no pattern", pattern));
+            let rebuilt = compile(&text);
+            for input in inputs {
+                assert_eq!(
+                    Matcher::new(&rebuilt).is_match(input),
+                    Matcher::new(&nfa).is_match(input),
+                    "{} became {} on {:?}", pattern, text, input
+                );
+            }
+        }
+        
+        // Possessive loops rely on lookahead, and anchors on assertions
+        assert_eq!(compile("a++").to_pattern(), None);
+        assert_eq!(compile("^a").to_pattern(), None);
+    }
+}
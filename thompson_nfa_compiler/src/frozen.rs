@@ -0,0 +1,292 @@
+use crate::matcher::MatchResult;
+use crate::nfa::{AssertionKind, NFA, State, StateId, TwoCharTransition};
+
+/// Span of a state's entries within one of the frozen arenas
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Span {
+    start: usize,
+    len: usize,
+}
+
+/// Layout of a single frozen state
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrozenState {
+    /// Consuming transitions, stored in the transition arena
+    Transitions(Span),
+    /// Epsilon targets (from `Epsilon` and `Split`), stored in the epsilon arena
+    Epsilons(Span),
+    /// Match state (accepting)
+    Match,
+    /// Rejected state (never matches, dead end)
+    Rejected,
+    /// Zero-width assertion, stored in the assertion arena
+    Assertion(usize),
+}
+
+/// An immutable NFA with transitions flattened into contiguous arenas
+///
+/// Produced by `NFA::freeze` once compilation is finished. Every state refers to a
+/// slice of a shared arena instead of owning its own vector, and the accepting set
+/// is a sorted slice, which keeps the hot matching loop allocation-light.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrozenNfa {
+    states: Box<[FrozenState]>,
+    transitions: Box<[TwoCharTransition]>,
+    epsilons: Box<[StateId]>,
+    assertions: Box<[(AssertionKind, StateId)]>,
+    accepting: Box<[StateId]>,
+    start: StateId,
+}
+
+/// A set of states with O(1) insertion and membership that keeps insertion order
+struct SparseSet {
+    dense: Vec<StateId>,
+    member: Vec<bool>,
+}
+
+impl SparseSet {
+    fn new(capacity: usize) -> Self {
+        Self {
+            dense: Vec::with_capacity(capacity),
+            member: vec![false; capacity],
+        }
+    }
+    
+    fn insert(&mut self, state_id: StateId) -> bool {
+        if state_id >= self.member.len() || self.member[state_id] {
+            return false;
+        }
+        self.member[state_id] = true;
+        self.dense.push(state_id);
+        true
+    }
+    
+    fn truncate(&mut self, len: usize) {
+        for &state_id in &self.dense[len..] {
+            self.member[state_id] = false;
+        }
+        self.dense.truncate(len);
+    }
+    
+    fn clear(&mut self) {
+        for &state_id in &self.dense {
+            self.member[state_id] = false;
+        }
+        self.dense.clear();
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+}
+
+impl FrozenNfa {
+    /// Flatten an NFA into the frozen layout
+    pub(crate) fn new(nfa: &NFA) -> Self {
+        let mut states = Vec::with_capacity(nfa.states.len());
+        let mut transitions = Vec::new();
+        let mut epsilons = Vec::new();
+        let mut assertions = Vec::new();
+        
+        for state in &nfa.states {
+            let frozen = match state {
+                State::Transitions { transitions: state_transitions } => {
+                    let span = Span { start: transitions.len(), len: state_transitions.len() };
+                    transitions.extend(state_transitions.iter().cloned());
+                    FrozenState::Transitions(span)
+                },
+                State::Epsilon { next } => {
+                    let span = Span { start: epsilons.len(), len: 1 };
+                    epsilons.push(*next);
+                    FrozenState::Epsilons(span)
+                },
+                State::Split { targets } => {
+                    let span = Span { start: epsilons.len(), len: targets.len() };
+                    epsilons.extend(targets.iter().copied());
+                    FrozenState::Epsilons(span)
+                },
+                State::Match => FrozenState::Match,
+                State::Rejected => FrozenState::Rejected,
+                State::Assertion { kind, next } => {
+                    assertions.push((kind.clone(), *next));
+                    FrozenState::Assertion(assertions.len() - 1)
+                },
+            };
+            states.push(frozen);
+        }
+        
+        let accepting: Vec<StateId> = nfa.accepting.iter().copied().collect();
+        
+        Self {
+            states: states.into_boxed_slice(),
+            transitions: transitions.into_boxed_slice(),
+            epsilons: epsilons.into_boxed_slice(),
+            assertions: assertions.into_boxed_slice(),
+            accepting: accepting.into_boxed_slice(),
+            start: nfa.start,
+        }
+    }
+    
+    /// Get the starting state
+    pub fn start(&self) -> StateId {
+        self.start
+    }
+    
+    /// Get the number of states
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+    
+    /// Check if a state is accepting
+    pub fn is_accepting_state(&self, state_id: StateId) -> bool {
+        self.accepting.binary_search(&state_id).is_ok()
+    }
+    
+    /// Get the two-character transitions of a state (empty for non-consuming states)
+    pub fn transitions_of(&self, state_id: StateId) -> &[TwoCharTransition] {
+        match self.states.get(state_id) {
+            Some(FrozenState::Transitions(span)) => &self.transitions[span.start..span.start + span.len],
+            _ => &[],
+        }
+    }
+    
+    /// Get the epsilon targets of a state (empty for consuming and terminal states)
+    fn epsilons_of(&self, state_id: StateId) -> &[StateId] {
+        match self.states.get(state_id) {
+            Some(FrozenState::Epsilons(span)) => &self.epsilons[span.start..span.start + span.len],
+            _ => &[],
+        }
+    }
+    
+    /// Find the first match in the input string
+    pub fn find(&self, input: &str) -> Option<MatchResult> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut current = SparseSet::new(self.states.len());
+        let mut next = SparseSet::new(self.states.len());
+        
+        for start in 0..=chars.len() {
+            if let Some(end) = self.match_at(&chars, start, &mut current, &mut next) {
+                return Some(MatchResult::new(start, end));
+            }
+        }
+        
+        None
+    }
+    
+    /// Check if the entire input matches
+    pub fn is_match(&self, input: &str) -> bool {
+        let chars: Vec<char> = input.chars().collect();
+        let mut current = SparseSet::new(self.states.len());
+        let mut next = SparseSet::new(self.states.len());
+        
+        self.add(&mut current, self.start, &chars, 0);
+        
+        for position in 0..chars.len() {
+            self.step(&current, &chars, position, &mut next);
+            if next.is_empty() {
+                return false;
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        
+        self.any_accepting(&current)
+    }
+    
+    /// Try to match at a specific position, returning the end of the leftmost-first match
+    ///
+    /// Follows the same thread-priority rules as `Matcher`: an accepting thread ends the
+    /// search for every lower-priority thread.
+    fn match_at(&self, chars: &[char], start: usize, current: &mut SparseSet, next: &mut SparseSet) -> Option<usize> {
+        current.clear();
+        self.add(current, self.start, chars, start);
+        
+        let mut matched = None;
+        let mut position = start;
+        
+        loop {
+            if let Some(index) = current.dense.iter().position(|&state_id| self.is_accepting_state(state_id)) {
+                matched = Some(position);
+                current.truncate(index);
+            }
+            
+            if current.is_empty() || position >= chars.len() {
+                break;
+            }
+            
+            self.step(current, chars, position, next);
+            std::mem::swap(current, next);
+            position += 1;
+        }
+        
+        matched
+    }
+    
+    /// Consume the character at `position` from every state in `current`, writing the
+    /// closed result to `next`
+    fn step(&self, current: &SparseSet, chars: &[char], position: usize, next: &mut SparseSet) {
+        let current_char = chars[position];
+        let next_char = chars.get(position + 1).copied();
+        next.clear();
+        for &state_id in &current.dense {
+            for transition in self.transitions_of(state_id) {
+                if transition.matches(current_char, next_char) {
+                    self.add(next, transition.target, chars, position + 1);
+                }
+            }
+        }
+    }
+    
+    /// Add a state and its epsilon closure to a set in split-priority order, following
+    /// assertions only where they hold at `position`
+    fn add(&self, set: &mut SparseSet, state_id: StateId, chars: &[char], position: usize) {
+        let mut stack = vec![state_id];
+        while let Some(state_id) = stack.pop() {
+            if !set.insert(state_id) {
+                continue;
+            }
+            if let Some(FrozenState::Assertion(index)) = self.states.get(state_id) {
+                let (kind, next) = &self.assertions[*index];
+                if kind.holds(chars, position) {
+                    stack.push(*next);
+                }
+            }
+            // Push in reverse so the first target is explored first
+            stack.extend(self.epsilons_of(state_id).iter().rev());
+        }
+    }
+    
+    /// Check if any state in the set is accepting
+    fn any_accepting(&self, set: &SparseSet) -> bool {
+        set.dense.iter().any(|&state_id| self.is_accepting_state(state_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::matcher::Matcher;
+    use regex_syntax::ParserBuilder;
+    
+    #[test]
+    fn test_frozen_matches_like_source() {
+        let patterns = ["ab", "a*", "a+b", "a|bc", "[abc]+", "[^ab]", "(?:foo|bar)x?", "a++", "a{2,3}"];
+        let inputs = ["", "a", "ab", "aaab", "xbcx", "foobarx", "cab", "aaa", "zzz"];
+        
+        for pattern in patterns {
+            let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+            let nfa = Compiler::new().compile(&hir).unwrap();
+            let frozen = nfa.clone().freeze();
+            let matcher = Matcher::new(&nfa);
+            
+            assert_eq!(frozen.state_count(), nfa.states.len());
+            for input in inputs {
+                assert_eq!(
+                    frozen.find(input).map(|m| (m.start, m.end)),
+                    matcher.find(input).map(|m| (m.start, m.end)),
+                    "{} on {:?}", pattern, input
+                );
+                assert_eq!(frozen.is_match(input), matcher.is_match(input), "{} on {:?}", pattern, input);
+            }
+        }
+    }
+}
@@ -13,12 +13,22 @@
 pub mod nfa;
 pub mod compiler;
 pub mod matcher;
+pub mod frozen;
+pub mod pike;
+pub mod state_set;
+mod serialize;
+mod decompile;
 pub mod verilog_gen;
+pub mod c_gen;
 
-pub use nfa::{NFA, State, StateId, TwoCharTransition, Fragment};
+pub use nfa::{Complexity, NFA, NfaStats, QuantifierInfo, QuantifierKind, State, StateId, TransitionRow, TwoCharTransition, Fragment};
 pub use compiler::Compiler;
-pub use matcher::Matcher;
+pub use matcher::{Captures, Coverage, LineCol, MatchStats, Matcher, OwnedMatcher, SearchMode};
+pub use frozen::FrozenNfa;
+pub use pike::PikeMatcher;
+pub use state_set::StateSet;
 pub use verilog_gen::SystemVerilogGenerator;
+pub use c_gen::CGenerator;
 
 /// The result of compiling a regex to a two-character Thompson NFA
 pub type CompileResult<T> = Result<T, CompileError>;
@@ -30,6 +40,8 @@ pub enum CompileError {
     TooComplex,
     /// Unsupported regex feature
     UnsupportedFeature(String),
+    /// The pattern text could not be parsed
+    Parse(String),
     /// Internal compilation error
     Internal(String),
 }
@@ -39,9 +51,56 @@ impl std::fmt::Display for CompileError {
         match self {
             CompileError::TooComplex => write!(f, "regex pattern is too complex"),
             CompileError::UnsupportedFeature(feature) => write!(f, "unsupported feature: {}", feature),
+            CompileError::Parse(msg) => write!(f, "parse error: {}", msg),
             CompileError::Internal(msg) => write!(f, "internal error: {}", msg),
         }
     }
 }
 
-impl std::error::Error for CompileError {}
\ No newline at end of file
+impl std::error::Error for CompileError {}
+
+/// Problems found while compiling that don't stop the NFA from being built
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileWarning {
+    /// An unbounded repetition of something already unbounded, like `(a*)*`; holds the
+    /// inner repetition's pattern text
+    RedundantRepetition(String),
+}
+
+impl std::fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileWarning::RedundantRepetition(inner) => {
+                write!(f, "redundant repetition of {}, which already repeats without bound", inner)
+            },
+        }
+    }
+}
+
+/// Parse a regex pattern and compile it to a two-character Thompson NFA
+pub fn compile_pattern(pattern: &str) -> CompileResult<NFA> {
+    let hir = regex_syntax::ParserBuilder::new()
+        .build()
+        .parse(pattern)
+        .map_err(|e| CompileError::Parse(e.to_string()))?;
+    Compiler::new().compile(&hir)
+}
+
+/// Compile a regex pattern straight to a SystemVerilog module named `module`
+pub fn pattern_to_systemverilog(pattern: &str, module: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let nfa = compile_pattern(pattern)?;
+    Ok(SystemVerilogGenerator::new().generate_module(&nfa, module))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_pattern_to_systemverilog() {
+        let verilog = pattern_to_systemverilog("ab", "m").unwrap();
+        assert!(verilog.contains("module m"));
+        
+        assert!(matches!(compile_pattern("(ab"), Err(CompileError::Parse(_))));
+    }
+}
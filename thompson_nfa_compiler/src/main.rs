@@ -1,18 +1,10 @@
-use thompson_nfa_compiler::{Compiler, SystemVerilogGenerator};
+use thompson_nfa_compiler::{Compiler, SystemVerilogGenerator, pattern_to_systemverilog};
 use regex_syntax::ParserBuilder;
 
 use std::env;
 
 fn compile_pattern_to_file(pattern: &str, module_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Parse the regex pattern
-    let hir = ParserBuilder::new().build().parse(pattern)?;
-    
-    // Compile to two-character Thompson NFA
-    let nfa = Compiler::new().compile(&hir)?;
-    
-    // Generate SystemVerilog
-    let generator = SystemVerilogGenerator::new();
-    let verilog_code = generator.generate_module(&nfa, module_name);
+    let verilog_code = pattern_to_systemverilog(pattern, module_name)?;
     
     // Write to file
     let filename = format!("{}.sv", module_name);
@@ -130,6 +122,9 @@ fn print_nfa(nfa: &thompson_nfa_compiler::NFA) {
             thompson_nfa_compiler::nfa::State::Split { targets } => {
                 println!("SPLIT -> {:?}", targets);
             },
+            thompson_nfa_compiler::nfa::State::Assertion { kind, next } => {
+                println!("ASSERT {} -> {}", kind, next);
+            },
             thompson_nfa_compiler::nfa::State::Transitions { transitions } => {
                 println!("TRANSITIONS:");
                 for (i, trans) in transitions.iter().enumerate() {
@@ -141,7 +136,8 @@ fn print_nfa(nfa: &thompson_nfa_compiler::NFA) {
                         thompson_nfa_compiler::nfa::CharacterPredicate::Char(c) => print!("'{}'", c),
                         thompson_nfa_compiler::nfa::CharacterPredicate::CharSet(set) => {
                             print!("[");
-                            let chars: Vec<_> = set.iter().collect();
+                            let mut chars: Vec<_> = set.iter().collect();
+                            chars.sort();
                             for (i, ch) in chars.iter().enumerate() {
                                 if i > 0 { print!(""); }
                                 print!("{}", ch);
@@ -150,13 +146,23 @@ fn print_nfa(nfa: &thompson_nfa_compiler::NFA) {
                         },
                         thompson_nfa_compiler::nfa::CharacterPredicate::NotCharSet(set) => {
                             print!("[^");
-                            let chars: Vec<_> = set.iter().collect();
+                            let mut chars: Vec<_> = set.iter().collect();
+                            chars.sort();
                             for (i, ch) in chars.iter().enumerate() {
                                 if i > 0 { print!(""); }
                                 print!("{}", ch);
                             }
                             print!("]");
                         },
+                        thompson_nfa_compiler::nfa::CharacterPredicate::EndOfInput => print!("$"),
+                        thompson_nfa_compiler::nfa::CharacterPredicate::Range(start, end) => print!("[{}-{}]", start, end),
+                        thompson_nfa_compiler::nfa::CharacterPredicate::Ranges(ranges) => {
+                            print!("[");
+                            for (start, end) in ranges {
+                                print!("{}-{}", start, end);
+                            }
+                            print!("]");
+                        },
                     }
                     
                     // Print lookahead predicate
@@ -167,7 +173,8 @@ fn print_nfa(nfa: &thompson_nfa_compiler::NFA) {
                             thompson_nfa_compiler::nfa::CharacterPredicate::Char(c) => print!("'{}'", c),
                             thompson_nfa_compiler::nfa::CharacterPredicate::CharSet(set) => {
                                 print!("[");
-                                let chars: Vec<_> = set.iter().collect();
+                                let mut chars: Vec<_> = set.iter().collect();
+                                chars.sort();
                                 for (i, ch) in chars.iter().enumerate() {
                                     if i > 0 { print!(""); }
                                     print!("{}", ch);
@@ -176,13 +183,23 @@ fn print_nfa(nfa: &thompson_nfa_compiler::NFA) {
                             },
                             thompson_nfa_compiler::nfa::CharacterPredicate::NotCharSet(set) => {
                                 print!("[^");
-                                let chars: Vec<_> = set.iter().collect();
+                                let mut chars: Vec<_> = set.iter().collect();
+                                chars.sort();
                                 for (i, ch) in chars.iter().enumerate() {
                                     if i > 0 { print!(""); }
                                     print!("{}", ch);
                                 }
                                 print!("]");
                             },
+                            thompson_nfa_compiler::nfa::CharacterPredicate::EndOfInput => print!("$"),
+                            thompson_nfa_compiler::nfa::CharacterPredicate::Range(start, end) => print!("[{}-{}]", start, end),
+                            thompson_nfa_compiler::nfa::CharacterPredicate::Ranges(ranges) => {
+                                print!("[");
+                                for (start, end) in ranges {
+                                    print!("{}-{}", start, end);
+                                }
+                                print!("]");
+                            },
                         }
                     }
                     
@@ -1,159 +1,1172 @@
-use crate::nfa::{NFA, TwoCharTransition, StateId};
-use std::collections::HashSet;
+use crate::nfa::{AssertionKind, CharacterPredicate, NFA, State, StateId, TwoCharTransition};
+use crate::pike::PikeMatcher;
+use crate::state_set::StateSet;
+use std::collections::{BTreeSet, VecDeque};
+use std::ops::Range;
+use std::str::CharIndices;
 
 /// A matcher that executes a two-character Thompson NFA against input
 pub struct Matcher<'a> {
     nfa: &'a NFA,
+    prepared: Option<PreparedStates>,
+    /// Characters a match can start with, used to skip hopeless start positions
+    first_set: CharacterPredicate,
+    /// Most characters a single match may span, set by `with_max_match_len`
+    max_match_len: Option<usize>,
+    /// Which characters word boundaries separate, set by `with_word_classifier`
+    word_classifier: Option<fn(char) -> bool>,
+    /// Which match a search reports among those at the leftmost start
+    search_mode: SearchMode,
 }
 
+/// How a search chooses among the matches that start at the leftmost position
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// The match the highest-priority path reaches, as a backtracking engine would find:
+    /// alternatives are tried in order and quantifiers honour greediness, so `ab|a`
+    /// matches `a` in `"ab"` only when `ab` fails
+    #[default]
+    LeftmostFirst,
+    /// The longest match, whatever the priority of the path to it, as POSIX specifies:
+    /// `a|ab` matches `ab` in `"ab"`
+    LeftmostLongest,
+}
+
+/// The part of a character stream around the current position that matching can see
+///
+/// Holds `behind` characters before the position and up to three from it onward: the
+/// two a transition reads, plus one more so `EndText` can tell whether the input ends
+/// right after a trailing newline.
+struct CharWindow<'s> {
+    chars: CharIndices<'s>,
+    buffer: Vec<char>,
+    /// Index in the whole input of `buffer[0]`
+    offset: usize,
+    behind: usize,
+}
+
+impl CharWindow<'_> {
+    /// Slide the window to `position`, returning the buffered characters and the index
+    /// of `position` within them
+    fn view(&mut self, position: usize) -> (&[char], usize) {
+        let keep_from = position.saturating_sub(self.behind);
+        if keep_from > self.offset {
+            self.buffer.drain(..keep_from - self.offset);
+            self.offset = keep_from;
+        }
+        while self.offset + self.buffer.len() < position + 3 {
+            match self.chars.next() {
+                Some((_, ch)) => self.buffer.push(ch),
+                None => break,
+            }
+        }
+        
+        (&self.buffer, position - self.offset)
+    }
+}
+
+/// Per-state metadata computed once by `Matcher::prepare`
+struct PreparedStates {
+    /// Epsilon closure of each state in split-priority order, indexed by state ID
+    closures: Vec<Vec<StateId>>,
+    /// Indices of the transitions whose current-character predicate accepts each ASCII
+    /// character, indexed by state ID then character
+    ascii_transitions: Vec<Vec<Vec<usize>>>,
+}
+
+impl PreparedStates {
+    /// Index the transitions of every state by the ASCII characters they accept
+    fn index_transitions(nfa: &NFA) -> Vec<Vec<Vec<usize>>> {
+        (0..nfa.states.len())
+            .map(|state_id| {
+                let transitions = nfa.transitions_of(state_id);
+                if transitions.is_empty() {
+                    return Vec::new();
+                }
+                (0..128u8)
+                    .map(|byte| transitions.iter()
+                        .enumerate()
+                        .filter(|(_, transition)| transition.current.matches(byte as char))
+                        .map(|(index, _)| index)
+                        .collect())
+                    .collect()
+            })
+            .collect()
+    }
+    
+    /// The transitions of a state that can fire on `ch`, if it is indexed
+    fn candidates(&self, state_id: StateId, ch: char) -> Option<&[usize]> {
+        if !ch.is_ascii() {
+            return None;
+        }
+        self.ascii_transitions.get(state_id)?.get(ch as usize).map(Vec::as_slice)
+    }
+}
+
+/// A position in the input as a 1-based (line, column) pair
+pub type LineCol = (usize, usize);
+
+/// A match found by one of the `Matcher` searches
+///
+/// `start` and `end` are in the units of the search that produced the result: character
+/// indices for `find`, `find_all` and most other searches over a `&str`, and byte
+/// offsets for `find_in`, `find_bytes` and `find_utf8`, or UTF-16 code units for
+/// `find_utf16`.
 #[derive(Debug, Clone)]
 pub struct MatchResult {
     pub matched: bool,
+    /// Offset of the first matched character, in the search's units
     pub start: usize,
+    /// Offset just past the last matched character, in the search's units
     pub end: usize,
+    /// Span of each capture group by index, filled in by `Matcher::captures`
+    pub groups: Vec<Option<(usize, usize)>>,
+    /// Names of the capture groups, parallel to `groups`
+    pub group_names: Vec<Option<String>>,
+    /// Annotation of the accepting state the match ended in, set with `NFA::set_annotation`
+    pub annotation: Option<u32>,
+}
+
+/// A match and the text of its capture groups, as reported by `Matcher::captures`
+#[derive(Debug, Clone)]
+pub struct Captures<'s> {
+    input: &'s str,
+    result: MatchResult,
+}
+
+impl<'s> Captures<'s> {
+    /// Get the text of a capture group by index, where group 0 is the whole match
+    pub fn get(&self, index: usize) -> Option<&'s str> {
+        self.text(self.result.group(index)?)
+    }
+    
+    /// Get the text of a capture group by name
+    pub fn name(&self, name: &str) -> Option<&'s str> {
+        self.text(self.result.name(name)?)
+    }
+    
+    /// Get the span of a capture group by index, in characters like `MatchResult::group`
+    pub fn span(&self, index: usize) -> Option<(usize, usize)> {
+        self.result.group(index)
+    }
+    
+    /// Get the number of capture groups, including group 0
+    pub fn len(&self) -> usize {
+        self.result.groups.len()
+    }
+    
+    /// Check whether there are no capture groups, which never happens for a match
+    pub fn is_empty(&self) -> bool {
+        self.result.groups.is_empty()
+    }
+    
+    /// Get the underlying match, with its spans and annotation
+    pub fn as_match(&self) -> &MatchResult {
+        &self.result
+    }
+    
+    /// Slice the input by a span of character offsets
+    fn text(&self, (start, end): (usize, usize)) -> Option<&'s str> {
+        let mut offsets = self.input.char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(self.input.len()));
+        let start_byte = offsets.nth(start)?;
+        let end_byte = if end == start { start_byte } else { offsets.nth(end - start - 1)? };
+        Some(&self.input[start_byte..end_byte])
+    }
+}
+
+/// Work counters for a single search, as reported by `Matcher::find_with_stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchStats {
+    /// Number of two-character transitions tested against the input
+    pub transitions_evaluated: usize,
+    /// Largest number of live states at any position
+    pub max_live_states: usize,
+    /// Number of start positions a match was attempted from
+    pub start_positions: usize,
+}
+
+/// The transitions a set of inputs exercised, as reported by `Matcher::coverage`
+///
+/// A transition is identified by its state ID and its index among that state's
+/// transitions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    /// Every transition in the NFA, in state then index order
+    transitions: Vec<(StateId, usize)>,
+    fired: BTreeSet<(StateId, usize)>,
+}
+
+impl Coverage {
+    /// Check whether the transition at `index` of `state` fired for some input
+    pub fn is_covered(&self, state: StateId, index: usize) -> bool {
+        self.fired.contains(&(state, index))
+    }
+    
+    /// List the transitions that fired, in state then index order
+    pub fn covered(&self) -> Vec<(StateId, usize)> {
+        self.fired.iter().copied().collect()
+    }
+    
+    /// List the transitions that never fired, in state then index order
+    pub fn uncovered(&self) -> Vec<(StateId, usize)> {
+        self.transitions.iter()
+            .copied()
+            .filter(|transition| !self.fired.contains(transition))
+            .collect()
+    }
+}
+
+impl MatchResult {
+    /// Create a match result without capture information
+    pub(crate) fn new(start: usize, end: usize) -> Self {
+        Self {
+            matched: true,
+            start,
+            end,
+            groups: Vec::new(),
+            group_names: Vec::new(),
+            annotation: None,
+        }
+    }
+    
+    /// Get the span of a capture group by index
+    pub fn group(&self, index: usize) -> Option<(usize, usize)> {
+        self.groups.get(index).copied().flatten()
+    }
+    
+    /// Get the span of a capture group by name
+    pub fn name(&self, name: &str) -> Option<(usize, usize)> {
+        let index = self.group_names.iter().position(|group| group.as_deref() == Some(name))?;
+        self.group(index)
+    }
 }
 
 impl<'a> Matcher<'a> {
     /// Create a new matcher for the given NFA
     pub fn new(nfa: &'a NFA) -> Self {
-        Self { nfa }
+        Self {
+            nfa,
+            prepared: None,
+            first_set: nfa.first_set(),
+            max_match_len: None,
+            word_classifier: None,
+            search_mode: SearchMode::default(),
+        }
+    }
+    
+    /// Create a matcher that precomputes per-state epsilon closures and transition indices up front
+    ///
+    /// The closures and the per-character transition indices are shared by every
+    /// subsequent search, which pays off when the same matcher runs `find`/`find_all`
+    /// many times. Stepping on an ASCII character then only tests the transitions that
+    /// accept it; other characters fall back to testing every transition.
+    pub fn prepare(nfa: &'a NFA) -> Self {
+        let closures = (0..nfa.states.len())
+            .map(|state_id| Self::static_closure(nfa, state_id))
+            .collect();
+        let ascii_transitions = PreparedStates::index_transitions(nfa);
+        
+        Self {
+            nfa,
+            prepared: Some(PreparedStates { closures, ascii_transitions }),
+            first_set: nfa.first_set(),
+            max_match_len: None,
+            word_classifier: None,
+            search_mode: SearchMode::default(),
+        }
+    }
+    
+    /// Stop extending any match after `max_len` characters
+    ///
+    /// Bounds the work per start position on inputs that would keep a loop like `a+`
+    /// running. A search reports the best match found within the limit, so `a+` yields
+    /// at most `max_len` characters, and whole-input checks such as `is_match` fail on
+    /// longer inputs. `captures` runs a separate VM and ignores the limit.
+    pub fn with_max_match_len(mut self, max_len: usize) -> Self {
+        self.max_match_len = Some(max_len);
+        self
+    }
+    
+    /// Choose which match searches report among those at the leftmost start
+    ///
+    /// Applies to `find`, `find_in`, `find_all` and the other searches that report one
+    /// match per start; `captures` and `find_with_stats` stay leftmost-first.
+    pub fn with_search_mode(mut self, mode: SearchMode) -> Self {
+        self.search_mode = mode;
+        self
+    }
+    
+    /// Decide word characters for `\b` and `\B` with `is_word` instead of `\w`
+    ///
+    /// Lets word boundaries follow a locale or alphabet of the caller's choosing, such as
+    /// treating `-` as part of a word. The classifier applies to both Unicode and ASCII
+    /// boundaries. `captures` runs a separate VM and keeps the standard definition.
+    pub fn with_word_classifier(mut self, is_word: fn(char) -> bool) -> Self {
+        self.word_classifier = Some(is_word);
+        self
+    }
+    
+    /// Check whether an assertion holds at `position`, using the word classifier if set
+    fn holds(&self, kind: &AssertionKind, chars: &[char], position: usize) -> bool {
+        match self.word_classifier {
+            Some(is_word) => kind.holds_with(chars, position, is_word),
+            None => kind.holds(chars, position),
+        }
+    }
+    
+    /// Get the position a match starting at `start` may not extend past
+    fn match_limit(&self, chars: &[char], start: usize) -> usize {
+        self.max_match_len.map_or(chars.len(), |max_len| chars.len().min(start.saturating_add(max_len)))
     }
     
     /// Find the first match in the input string
+    ///
+    /// The match starts as early as possible, and among the matches from there the search
+    /// mode picks one: leftmost-first by default, so `ab|a` yields `ab` but `a|ab` yields
+    /// `a` on `"ab"`. See `with_search_mode`. Offsets are character indices.
+    ///
+    /// Start positions whose character isn't in the NFA's FIRST set are skipped without
+    /// running the NFA. Patterns that can match empty are tried at every position.
+    ///
+    /// The input is read through `CharIndices` rather than collected, so besides the
+    /// thread lists only a few characters around the current position are held at once
+    /// (more for a pattern with a wide lookbehind) however long the input is.
     pub fn find(&self, input: &str) -> Option<MatchResult> {
+        if self.search_mode == SearchMode::LeftmostLongest {
+            return self.find_chars(&input.chars().collect::<Vec<_>>());
+        }
+        
+        let behind = self.lookbehind_reach();
+        let mut context = VecDeque::with_capacity(behind + 1);
+        let mut rest = input.char_indices();
+        let mut start = 0;
+        
+        loop {
+            if self.can_start_with(rest.clone().next().map(|(_, ch)| ch)) {
+                let window = CharWindow {
+                    chars: rest.clone(),
+                    buffer: context.iter().copied().collect(),
+                    offset: start - context.len(),
+                    behind,
+                };
+                if let Some((end, accept)) = self.match_at_window(window, start) {
+                    return Some(self.match_result(start, end, accept));
+                }
+            }
+            
+            let (_, ch) = rest.next()?;
+            context.push_back(ch);
+            if context.len() > behind {
+                context.pop_front();
+            }
+            start += 1;
+        }
+    }
+    
+    /// Get how many characters before a position its assertions may inspect
+    fn lookbehind_reach(&self) -> usize {
+        self.nfa.states.iter()
+            .filter_map(|state| match state {
+                State::Assertion { kind: AssertionKind::LookBehind { width, .. }, .. } => Some(*width),
+                _ => None,
+            })
+            .fold(1, usize::max)
+    }
+    
+    /// Find the first match in a byte string, with offsets in bytes
+    ///
+    /// Each byte is presented to the NFA as the character with the same value, which is
+    /// how byte classes from patterns parsed with Unicode disabled are compiled.
+    pub fn find_bytes(&self, input: &[u8]) -> Option<MatchResult> {
+        let chars: Vec<char> = input.iter().map(|&byte| byte as char).collect();
+        self.find_chars(&chars)
+    }
+    
+    /// Find the first match in UTF-8 bytes, with offsets in bytes
+    ///
+    /// Valid UTF-8 sequences are decoded so character predicates like `\p{L}` see whole
+    /// characters. Each byte of an invalid sequence is presented as the character with the
+    /// same value, as `find_bytes` does, so byte classes still match it (and a character
+    /// predicate sees the Latin-1 character of that value). Matches always start and end
+    /// on the edges of decoded characters.
+    pub fn find_utf8(&self, input: &[u8]) -> Option<MatchResult> {
+        let mut chars = Vec::new();
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        
+        for chunk in input.utf8_chunks() {
+            for (index, ch) in chunk.valid().char_indices() {
+                chars.push(ch);
+                offsets.push(offset + index);
+            }
+            offset += chunk.valid().len();
+            for &byte in chunk.invalid() {
+                chars.push(byte as char);
+                offsets.push(offset);
+                offset += 1;
+            }
+        }
+        offsets.push(input.len());
+        
+        let found = self.find_chars(&chars)?;
+        Some(MatchResult {
+            start: offsets[found.start],
+            end: offsets[found.end],
+            ..found
+        })
+    }
+    
+    /// Find the first match in UTF-16 code units, with offsets in code units
+    ///
+    /// Surrogate pairs are decoded to full characters for matching. A lone surrogate
+    /// matches nothing and no match can span it, so the input is searched as separate
+    /// segments on either side.
+    pub fn find_utf16(&self, units: &[u16]) -> Option<MatchResult> {
+        let mut chars = Vec::new();
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        
+        for decoded in char::decode_utf16(units.iter().copied()) {
+            match decoded {
+                Ok(ch) => {
+                    chars.push(ch);
+                    offsets.push(offset);
+                    offset += ch.len_utf16();
+                },
+                Err(_) => {
+                    if let Some(found) = self.find_utf16_segment(&chars, &offsets, offset) {
+                        return Some(found);
+                    }
+                    chars.clear();
+                    offsets.clear();
+                    offset += 1;
+                },
+            }
+        }
+        
+        self.find_utf16_segment(&chars, &offsets, offset)
+    }
+    
+    /// Search one surrogate-free segment, mapping character indices back to code units
+    fn find_utf16_segment(&self, chars: &[char], offsets: &[usize], end_offset: usize) -> Option<MatchResult> {
+        let found = self.find_chars(chars)?;
+        let unit_offset = |index: usize| offsets.get(index).copied().unwrap_or(end_offset);
+        Some(MatchResult::new(unit_offset(found.start), unit_offset(found.end)))
+    }
+    
+    /// Find the first match starting within a byte range of the input, with byte offsets
+    ///
+    /// Only start positions in `range` are tried, but matches may run past its end and
+    /// lookahead sees the whole input. Offsets are byte offsets relative to the full
+    /// input, so `&input[result.start..result.end]` is the matched text; unlike `find`,
+    /// they aren't character indices once the input has multibyte characters. Range
+    /// bounds that fall inside a multibyte character snap forward to the next character
+    /// boundary, and bounds past the end are clamped to the input length.
+    pub fn find_in(&self, input: &str, range: Range<usize>) -> Option<MatchResult> {
         let chars: Vec<char> = input.chars().collect();
+        let byte_offsets: Vec<usize> = input.char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(input.len()))
+            .collect();
+        
+        // Index of the first character boundary at or after a byte offset
+        let snap = |offset: usize| byte_offsets.partition_point(|&boundary| boundary < offset.min(input.len()));
+        let first = snap(range.start);
+        // A range reaching the end of the input also allows an empty match at the end
+        let last = if range.end >= input.len() { chars.len() + 1 } else { snap(range.end) };
+        
+        (first..last).filter(|&start| self.can_start_at(&chars, start)).find_map(|start| {
+            let (end, accept) = self.match_at(&chars, start)?;
+            Some(self.match_result(byte_offsets[start], byte_offsets[end], accept))
+        })
+    }
+    
+    /// Find the first match like `find`, also counting the work the search took
+    ///
+    /// Useful for profiling a pattern: the counters show how the cost of a search
+    /// grows with the input and how many states stay live at once. Every start position is
+    /// tried, without the FIRST-set skipping `find` does, so the counters reflect the NFA.
+    pub fn find_with_stats(&self, input: &str) -> (Option<MatchResult>, MatchStats) {
+        let chars: Vec<char> = input.chars().collect();
+        let mut stats = MatchStats::default();
         
-        // Try matching at each position
         for start in 0..=chars.len() {
-            if let Some(end) = self.match_at(&chars, start) {
-                return Some(MatchResult {
-                    matched: true,
-                    start,
-                    end,
-                });
+            stats.start_positions += 1;
+            if let Some((end, accept)) = self.match_at_counted(&chars, start, &mut stats) {
+                return (Some(self.match_result(start, end, accept)), stats);
+            }
+        }
+        
+        (None, stats)
+    }
+    
+    /// Find the first match in a sequence of characters
+    fn find_chars(&self, chars: &[char]) -> Option<MatchResult> {
+        // Try matching at each position the FIRST set allows
+        for start in (0..=chars.len()).filter(|&start| self.can_start_at(chars, start)) {
+            if let Some((end, accept)) = self.match_at(chars, start) {
+                return Some(self.match_result(start, end, accept));
+            }
+        }
+        
+        None
+    }
+    
+    /// Find the first match like `find`, also reporting its start and end as (line, column)
+    ///
+    /// Lines and columns count from 1; lines are split on `\n` and columns count
+    /// characters. The end is the position just after the last matched character, so a
+    /// match ending with a newline ends at column 1 of the next line.
+    pub fn find_line_col(&self, input: &str) -> Option<(MatchResult, LineCol, LineCol)> {
+        let found = self.find(input)?;
+        let mut position = (1, 1);
+        let mut start = position;
+        
+        for (index, ch) in input.chars().enumerate().take(found.end) {
+            if index == found.start {
+                start = position;
+            }
+            position = match ch {
+                '\n' => (position.0 + 1, 1),
+                _ => (position.0, position.1 + 1),
+            };
+        }
+        if found.start == found.end {
+            start = position;
+        }
+        
+        Some((found, start, position))
+    }
+    
+    /// Find the longest match anywhere in the input
+    ///
+    /// Every start position is tried and the longest match from each is considered,
+    /// regardless of thread priority. Among matches of the same length the one that
+    /// starts earliest wins.
+    pub fn find_longest_global(&self, input: &str) -> Option<MatchResult> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut best: Option<(usize, usize, StateId)> = None;
+        
+        for start in (0..=chars.len()).filter(|&start| self.can_start_at(&chars, start)) {
+            if let Some((end, accept)) = self.longest_at(&chars, start) {
+                if best.is_none_or(|(best_start, best_end, _)| end - start > best_end - best_start) {
+                    best = Some((start, end, accept));
+                }
+            }
+        }
+        
+        best.map(|(start, end, accept)| self.match_result(start, end, accept))
+    }
+    
+    /// Find the furthest end of a match starting at `start`, with its accepting state
+    fn longest_at(&self, chars: &[char], start: usize) -> Option<(usize, StateId)> {
+        let mut threads = Vec::new();
+        self.add_ordered(&mut threads, &mut self.state_set(), self.nfa.start, chars, start);
+        let mut position = start;
+        let limit = self.match_limit(chars, start);
+        let mut longest = None;
+        
+        loop {
+            if let Some(&accept) = threads.iter().find(|state_id| self.nfa.accepting.contains(state_id)) {
+                longest = Some((position, accept));
+            }
+            
+            if threads.is_empty() || position >= limit {
+                break;
+            }
+            
+            threads = self.step(&threads, chars, position, &mut MatchStats::default());
+            position += 1;
+        }
+        
+        longest
+    }
+    
+    /// Find the leftmost match that consumes at least one character, ending as early as possible
+    ///
+    /// From the first start position with any non-empty match, the shortest one is taken,
+    /// regardless of thread priority; an accept before consuming anything is skipped, so
+    /// `a*` over `"aaa"` finds `(0, 1)`.
+    pub fn find_shortest_nonempty(&self, input: &str) -> Option<MatchResult> {
+        let chars: Vec<char> = input.chars().collect();
+        
+        (0..chars.len())
+            .filter(|&start| self.can_start_at(&chars, start))
+            .find_map(|start| {
+                let (end, accept) = self.shortest_nonempty_at(&chars, start)?;
+                Some(self.match_result(start, end, accept))
+            })
+    }
+    
+    /// Find the nearest end after `start` of a match starting at `start`, with its accepting state
+    fn shortest_nonempty_at(&self, chars: &[char], start: usize) -> Option<(usize, StateId)> {
+        let mut threads = Vec::new();
+        self.add_ordered(&mut threads, &mut self.state_set(), self.nfa.start, chars, start);
+        let limit = self.match_limit(chars, start);
+        
+        for position in start..limit {
+            threads = self.step(&threads, chars, position, &mut MatchStats::default());
+            if let Some(&accept) = threads.iter().find(|state_id| self.nfa.accepting.contains(state_id)) {
+                return Some((position + 1, accept));
+            }
+            if threads.is_empty() {
+                break;
             }
         }
         
         None
     }
     
+    /// Record which transitions fire while searching each of `inputs`
+    ///
+    /// A thread is started at every position, as in `is_match_anywhere`, and a transition
+    /// counts as fired when a live thread takes it, whether or not that thread goes on to
+    /// match. Transitions of states no input reaches stay uncovered.
+    pub fn coverage(&self, inputs: &[&str]) -> Coverage {
+        let mut coverage = Coverage {
+            transitions: (0..self.nfa.states.len())
+                .flat_map(|state_id| (0..self.nfa.transitions_of(state_id).len()).map(move |index| (state_id, index)))
+                .collect(),
+            fired: BTreeSet::new(),
+        };
+        
+        for input in inputs {
+            let chars: Vec<char> = input.chars().collect();
+            let mut threads = Vec::new();
+            
+            for position in 0..chars.len() {
+                if self.can_start_at(&chars, position) {
+                    let mut seen = self.state_set();
+                    for &state_id in &threads {
+                        seen.insert(state_id);
+                    }
+                    self.add_ordered(&mut threads, &mut seen, self.nfa.start, &chars, position);
+                }
+                
+                let next_char = chars.get(position + 1).copied();
+                for &state_id in &threads {
+                    for (index, transition) in self.nfa.transitions_of(state_id).iter().enumerate() {
+                        if transition.matches(chars[position], next_char) {
+                            coverage.fired.insert((state_id, index));
+                        }
+                    }
+                }
+                threads = self.step(&threads, &chars, position, &mut MatchStats::default());
+            }
+        }
+        
+        coverage
+    }
+    
+    /// Check whether a match could start at `start`, judging by the FIRST set alone
+    fn can_start_at(&self, chars: &[char], start: usize) -> bool {
+        self.can_start_with(chars.get(start).copied())
+    }
+    
+    /// Check whether a match could start at a position holding `ch` (`None` at the end)
+    fn can_start_with(&self, ch: Option<char>) -> bool {
+        matches!(self.first_set, CharacterPredicate::Any)
+            || ch.is_some_and(|ch| self.first_set.matches(ch))
+    }
+    
+    /// Build the result for a match that ended in the accepting state `accept`
+    fn match_result(&self, start: usize, end: usize, accept: StateId) -> MatchResult {
+        MatchResult {
+            annotation: self.nfa.annotation(accept),
+            ..MatchResult::new(start, end)
+        }
+    }
+    
+    /// Find the first match like `find`, also capturing the text of every group
+    ///
+    /// Runs a `PikeMatcher`, so when a group could span different parts of the match
+    /// the higher-priority path decides, whatever the search mode.
+    pub fn captures<'s>(&self, input: &'s str) -> Option<Captures<'s>> {
+        let result = PikeMatcher::new(self.nfa).find(input)?;
+        Some(Captures { input, result })
+    }
+    
     /// Check if the entire input matches
+    ///
+    /// The pattern must match from the first character to the last, so `abc` doesn't
+    /// match `"xabcx"`; use `is_match_anywhere` to ask whether it occurs at all.
     pub fn is_match(&self, input: &str) -> bool {
+        self.match_full(input).is_ok()
+    }
+    
+    /// Check if the pattern matches anywhere in the input
+    ///
+    /// Gives the same answer as `find(input).is_some()`, but a thread is started at every
+    /// position in a single pass and the search stops at the first accepting state, without
+    /// working out where the leftmost-first match would end.
+    pub fn is_match_anywhere(&self, input: &str) -> bool {
+        // Threads from different starts are merged, so a length limit needs `find`
+        if self.max_match_len.is_some() {
+            return self.find(input).is_some();
+        }
+        
+        let chars: Vec<char> = input.chars().collect();
+        let mut threads = Vec::new();
+        
+        for position in 0..=chars.len() {
+            if self.can_start_at(&chars, position) {
+                let mut seen = self.state_set();
+                for &state_id in &threads {
+                    seen.insert(state_id);
+                }
+                self.add_ordered(&mut threads, &mut seen, self.nfa.start, &chars, position);
+            }
+            if threads.iter().any(|state_id| self.nfa.accepting.contains(state_id)) {
+                return true;
+            }
+            if position < chars.len() {
+                threads = self.step(&threads, &chars, position, &mut MatchStats::default());
+            }
+        }
+        
+        false
+    }
+    
+    /// Check whether the pattern can match the empty string
+    ///
+    /// Looks for an accepting state in the epsilon closure of the start. Assertions are
+    /// followed without being checked, so `^` and `\b` count as matching empty even though
+    /// they only do so at some positions.
+    pub fn matches_empty(&self) -> bool {
+        let start = std::iter::once(self.nfa.start).collect();
+        self.nfa.is_accepting(&self.nfa.epsilon_closure(&start))
+    }
+    
+    /// Match the entire input, or report the position where matching broke down
+    ///
+    /// Returns `Err(position)` with the index of the first character no transition
+    /// could consume, or the input length if the input ran out before an accepting state.
+    pub fn match_full(&self, input: &str) -> Result<MatchResult, usize> {
         let chars: Vec<char> = input.chars().collect();
-        self.match_at(&chars, 0) == Some(chars.len())
+        let final_states = self.run_full(&chars)?;
+        
+        match final_states.iter().find(|state_id| self.nfa.accepting.contains(state_id)) {
+            Some(&accept) => Ok(self.match_result(0, chars.len(), accept)),
+            None => Err(chars.len()),
+        }
     }
     
-    /// Try to match at a specific position
-    fn match_at(&self, chars: &[char], start: usize) -> Option<usize> {
-        let mut current_states = HashSet::new();
-        current_states.insert(self.nfa.start);
+    /// Classify the entire input, returning the ID of the pattern that accepts all of it
+    ///
+    /// When several patterns accept the input the lowest pattern ID wins.
+    pub fn classify(&self, input: &str) -> Option<usize> {
+        let chars: Vec<char> = input.chars().collect();
+        let final_states = self.run_full(&chars).ok()?;
         
-        // Get epsilon closure of starting states
-        current_states = self.nfa.epsilon_closure(&current_states);
+        final_states.iter()
+            .filter_map(|&state| self.nfa.pattern_id(state))
+            .min()
+    }
+    
+    /// Run the NFA over all of the input, returning the final states or the failing position
+    fn run_full(&self, chars: &[char]) -> Result<Vec<StateId>, usize> {
+        let mut threads = Vec::new();
+        self.add_ordered(&mut threads, &mut self.state_set(), self.nfa.start, chars, 0);
         
-        let mut position = start;
+        // Unlike match_at, keep going past intermediate accepting states
+        let limit = self.match_limit(chars, 0);
+        for position in 0..chars.len() {
+            if position >= limit {
+                return Err(position);
+            }
+            let next_threads = self.step(&threads, chars, position, &mut MatchStats::default());
+            
+            if next_threads.is_empty() {
+                return Err(position);
+            }
+            
+            threads = next_threads;
+        }
         
-        // Check if we're already in an accepting state (handles empty matches)
-        if self.nfa.is_accepting(&current_states) && start <= chars.len() {
-            return Some(position);
+        Ok(threads)
+    }
+    
+    /// Check if a sequence of characters is matched in its entirety
+    pub(crate) fn is_match_chars(&self, chars: &[char]) -> bool {
+        self.run_full(chars)
+            .is_ok_and(|states| states.iter().any(|state_id| self.nfa.accepting.contains(state_id)))
+    }
+    
+    /// Try to match at a specific position, returning the end of the match the search mode
+    /// picks along with the accepting state it ended in
+    ///
+    /// For leftmost-first, threads are kept in split-priority order. When a thread
+    /// accepts, the match is recorded and every lower-priority thread is dropped, so greedy
+    /// quantifiers keep extending while lazy ones stop as soon as the rest of the pattern
+    /// has matched.
+    fn match_at(&self, chars: &[char], start: usize) -> Option<(usize, StateId)> {
+        match self.search_mode {
+            SearchMode::LeftmostFirst => self.match_at_counted(chars, start, &mut MatchStats::default()),
+            SearchMode::LeftmostLongest => self.longest_at(chars, start),
         }
+    }
+    
+    /// Like `match_at`, adding the work done to `stats`
+    fn match_at_counted(&self, chars: &[char], start: usize, stats: &mut MatchStats) -> Option<(usize, StateId)> {
+        let mut threads = Vec::new();
+        self.add_ordered(&mut threads, &mut self.state_set(), self.nfa.start, chars, start);
         
-        // Process each character
-        while position < chars.len() && !current_states.is_empty() {
-            let current_char = chars[position];
-            let next_char = if position + 1 < chars.len() {
-                Some(chars[position + 1])
-            } else {
-                None
-            };
+        let mut matched = None;
+        let mut position = start;
+        let limit = self.match_limit(chars, start);
+        
+        loop {
+            stats.max_live_states = stats.max_live_states.max(threads.len());
+            if let Some(index) = threads.iter().position(|state_id| self.nfa.accepting.contains(state_id)) {
+                matched = Some((position, threads[index]));
+                threads.truncate(index);
+            }
+            
+            if threads.is_empty() || position >= limit {
+                break;
+            }
             
-            let next_states = self.step_states(&current_states, current_char, next_char);
+            threads = self.step(&threads, chars, position, stats);
+            position += 1;
+        }
+        
+        matched
+    }
+    
+    /// Like `match_at`, reading the input through a sliding window instead of a slice
+    fn match_at_window(&self, mut window: CharWindow<'_>, start: usize) -> Option<(usize, StateId)> {
+        let mut threads = Vec::new();
+        let (chars, local) = window.view(start);
+        self.add_ordered(&mut threads, &mut self.state_set(), self.nfa.start, chars, local);
+        
+        let mut matched = None;
+        let mut position = start;
+        
+        loop {
+            if let Some(index) = threads.iter().position(|state_id| self.nfa.accepting.contains(state_id)) {
+                matched = Some((position, threads[index]));
+                threads.truncate(index);
+            }
             
-            if next_states.is_empty() {
+            let (chars, local) = window.view(position);
+            let at_limit = local >= chars.len() || self.max_match_len.is_some_and(|max_len| position - start >= max_len);
+            if threads.is_empty() || at_limit {
                 break;
             }
             
-            current_states = self.nfa.epsilon_closure(&next_states);
+            threads = self.step(&threads, chars, local, &mut MatchStats::default());
             position += 1;
+        }
+        
+        matched
+    }
+    
+    /// Collect every position where a match starting at `start` could end, in order
+    ///
+    /// Unlike `find`, accepting threads don't cut off the others, so a maximal-munch
+    /// lexer can take the last entry as the longest match.
+    pub fn accepting_ends_from(&self, chars: &[char], start: usize) -> Vec<usize> {
+        let mut ends = Vec::new();
+        if start > chars.len() {
+            return ends;
+        }
+        
+        let mut threads = Vec::new();
+        self.add_ordered(&mut threads, &mut self.state_set(), self.nfa.start, chars, start);
+        let mut position = start;
+        let limit = self.match_limit(chars, start);
+        
+        loop {
+            if threads.iter().any(|state_id| self.nfa.accepting.contains(state_id)) {
+                ends.push(position);
+            }
             
-            // Check if we're in an accepting state after consuming this character
-            if self.nfa.is_accepting(&current_states) {
-                return Some(position);
+            if threads.is_empty() || position >= limit {
+                break;
             }
             
-            // Possessive behavior is handled structurally through lookahead, not flags
+            threads = self.step(&threads, chars, position, &mut MatchStats::default());
+            position += 1;
         }
         
-        // Final check for accepting state
-        if self.nfa.is_accepting(&current_states) {
-            Some(position)
-        } else {
-            None
+        ends
+    }
+    
+    /// Consume the character at `position` from every thread, keeping priority order
+    fn step(&self, threads: &[StateId], chars: &[char], position: usize, stats: &mut MatchStats) -> Vec<StateId> {
+        let current_char = chars[position];
+        let next_char = chars.get(position + 1).copied();
+        let mut next_threads = Vec::new();
+        let mut seen = self.state_set();
+        
+        // Walk each state's transitions in place rather than cloning them out
+        for &state_id in threads {
+            let transitions = self.nfa.transitions_of(state_id);
+            let candidates = self.prepared.as_ref().and_then(|prepared| prepared.candidates(state_id, current_char));
+            let mut fire = |transition: &TwoCharTransition| {
+                stats.transitions_evaluated += 1;
+                if transition.matches(current_char, next_char) {
+                    self.add_ordered(&mut next_threads, &mut seen, transition.target, chars, position + 1);
+                }
+            };
+            
+            match candidates {
+                // Indices are ascending, so priority order is kept
+                Some(indices) => indices.iter().for_each(|&index| fire(&transitions[index])),
+                None => transitions.iter().for_each(fire),
+            }
         }
+        
+        next_threads
     }
     
-    /// Step from current states using a character with lookahead
-    fn step_states(&self, current_states: &HashSet<StateId>, current_char: char, next_char: Option<char>) -> HashSet<StateId> {
-        let mut next_states = HashSet::new();
+    /// Create an empty set sized to hold any of the NFA's states
+    fn state_set(&self) -> StateSet {
+        StateSet::new(self.nfa.states.len())
+    }
+    
+    /// Append the epsilon closure of a state to `threads` in split-priority order
+    ///
+    /// Assertions are checked against the input at `position` and only followed where
+    /// they hold.
+    fn add_ordered(&self, threads: &mut Vec<StateId>, seen: &mut StateSet, state_id: StateId, chars: &[char], position: usize) {
+        if let Some(prepared) = &self.prepared {
+            // Prepared closures stop at assertions, which are resolved here
+            for &id in prepared.closures.get(state_id).into_iter().flatten() {
+                if !seen.insert(id) {
+                    continue;
+                }
+                threads.push(id);
+                if let State::Assertion { kind, next } = &self.nfa.states[id] {
+                    if self.holds(kind, chars, position) {
+                        self.add_ordered(threads, seen, *next, chars, position);
+                    }
+                }
+            }
+            return;
+        }
         
-        // Get all possible transitions from current states
-        let transitions = self.nfa.get_two_char_transitions(current_states);
+        let mut stack = vec![state_id];
+        while let Some(state_id) = stack.pop() {
+            if state_id >= self.nfa.states.len() || !seen.insert(state_id) {
+                continue;
+            }
+            threads.push(state_id);
+            
+            match &self.nfa.states[state_id] {
+                State::Epsilon { next } => stack.push(*next),
+                // Push in reverse so the first target is explored first
+                State::Split { targets } => stack.extend(targets.iter().rev()),
+                State::Assertion { kind, next } if self.holds(kind, chars, position) => stack.push(*next),
+                _ => {},
+            }
+        }
+    }
+    
+    /// Collect the closure of a state in priority order without crossing assertions
+    fn static_closure(nfa: &NFA, state_id: StateId) -> Vec<StateId> {
+        let mut closure = Vec::new();
+        let mut seen = StateSet::new(nfa.states.len());
+        let mut stack = vec![state_id];
         
-        for transition in transitions {
-            if self.transition_matches(&transition, current_char, next_char) {
-                next_states.insert(transition.target);
+        while let Some(state_id) = stack.pop() {
+            if state_id >= nfa.states.len() || !seen.insert(state_id) {
+                continue;
             }
+            closure.push(state_id);
+            
+            match &nfa.states[state_id] {
+                State::Epsilon { next } => stack.push(*next),
+                State::Split { targets } => stack.extend(targets.iter().rev()),
+                _ => {},
+            }
+        }
+        
+        closure
+    }
+    
+    /// Find all non-overlapping leftmost-first matches in the input
+    pub fn find_all(&self, input: &str) -> Vec<MatchResult> {
+        self.find_iter(input).collect()
+    }
+    
+    /// Iterate over non-overlapping leftmost-first matches in the input
+    ///
+    /// After a match the search resumes at its end, so adjacent matches are all reported.
+    /// After an empty match it resumes one character later, and an empty match directly
+    /// after a previous match is skipped, so zero-width matches never repeat or loop.
+    pub fn find_iter<'m>(&'m self, input: &str) -> FindIter<'m, 'a> {
+        FindIter {
+            matcher: self,
+            chars: input.chars().collect(),
+            start: 0,
+            last_match_end: None,
+        }
+    }
+    
+    /// Split the input on non-overlapping matches of the pattern
+    ///
+    /// Like `regex::Regex::split`, leading and trailing empty pieces are kept, and an
+    /// empty match directly after a previous match does not produce an extra split.
+    pub fn split<'s>(&self, input: &'s str) -> Vec<&'s str> {
+        let byte_offsets: Vec<usize> = input.char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(input.len()))
+            .collect();
+        
+        let mut pieces = Vec::new();
+        let mut last = 0;
+        
+        for found in self.find_iter(input) {
+            pieces.push(&input[byte_offsets[last]..byte_offsets[found.start]]);
+            last = found.end;
         }
-        next_states
+        
+        pieces.push(&input[byte_offsets[last]..]);
+        pieces
+    }
+    
+    /// Replace every non-overlapping match with `replacement`
+    ///
+    /// The replacement is inserted as is; `$` has no special meaning.
+    pub fn replace_all(&self, input: &str, replacement: &str) -> String {
+        self.replacen(input, replacement, 0)
+    }
+    
+    /// Replace the first `limit` non-overlapping matches with `replacement`, or every
+    /// match when `limit` is 0, like `regex::Regex::replacen`
+    pub fn replacen(&self, input: &str, replacement: &str, limit: usize) -> String {
+        self.replace_matches(input, limit, |_, _| replacement.to_string())
+    }
+    
+    /// Replace every non-overlapping match with what `f` returns for it
+    ///
+    /// `f` is given the match and its text. Matches are found as by `find_iter`, so
+    /// empty matches are replaced too, but never twice at the same position.
+    pub fn replace_with(&self, input: &str, f: impl FnMut(&MatchResult, &str) -> String) -> String {
+        self.replace_matches(input, 0, f)
+    }
+    
+    /// Replace the first `limit` matches (all of them when 0) with what `f` returns
+    fn replace_matches(&self, input: &str, limit: usize, mut f: impl FnMut(&MatchResult, &str) -> String) -> String {
+        let byte_offsets: Vec<usize> = input.char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(input.len()))
+            .collect();
+        
+        let mut replaced = String::with_capacity(input.len());
+        let mut last = 0;
+        let matches = self.find_iter(input).take(if limit == 0 { usize::MAX } else { limit });
+        
+        for found in matches {
+            let (start, end) = (byte_offsets[found.start], byte_offsets[found.end]);
+            replaced.push_str(&input[byte_offsets[last]..start]);
+            replaced.push_str(&f(&found, &input[start..end]));
+            last = found.end;
+        }
+        
+        replaced.push_str(&input[byte_offsets[last]..]);
+        replaced
+    }
+    
+    /// Match a prefix of the input, returning the matched text and the rest
+    ///
+    /// The match must start at the beginning of the input and takes the longest prefix
+    /// any thread accepts, regardless of thread priority. Returns `None` when no prefix,
+    /// not even an empty one, matches.
+    pub fn match_prefix<'s>(&self, input: &'s str) -> Option<(&'s str, &'s str)> {
+        let chars: Vec<char> = input.chars().collect();
+        let (end, _) = self.longest_at(&chars, 0)?;
+        let split = input.char_indices().nth(end).map_or(input.len(), |(offset, _)| offset);
+        Some(input.split_at(split))
+    }
+}
+
+/// A matcher that owns its NFA, for returning from functions or storing in structs
+///
+/// Each search borrows the NFA through a short-lived `Matcher`; use `matcher` for the
+/// rest of the `Matcher` API.
+#[derive(Debug, Clone)]
+pub struct OwnedMatcher {
+    nfa: NFA,
+}
+
+impl OwnedMatcher {
+    /// Create a matcher that takes ownership of an NFA
+    pub fn new(nfa: NFA) -> Self {
+        Self { nfa }
+    }
+    
+    /// Parse and compile a pattern into an owned matcher
+    pub fn compile(pattern: &str) -> crate::CompileResult<Self> {
+        crate::compile_pattern(pattern).map(Self::new)
+    }
+    
+    /// Get the owned NFA
+    pub fn nfa(&self) -> &NFA {
+        &self.nfa
     }
     
-    /// Check if a transition matches the current character and lookahead
-    fn transition_matches(&self, transition: &TwoCharTransition, current_char: char, next_char: Option<char>) -> bool {
-        // Check current character predicate
-        if !transition.current.matches(current_char) {
-            return false;
-        }
-        
-        // Check lookahead predicate
-        match (&transition.lookahead, next_char) {
-            (None, _) => true, // No lookahead constraint
-            (Some(lookahead_pred), Some(actual)) => lookahead_pred.matches(actual),
-            (Some(lookahead_pred), None) => {
-                // At end of input - negative lookahead succeeds, positive fails
-                match lookahead_pred {
-                    crate::nfa::CharacterPredicate::NotCharSet(_) => true, // Negative: succeeds at end
-                    _ => false, // Positive: fails at end
-                }
-            }
-        }
+    /// Borrow a `Matcher` over the owned NFA
+    pub fn matcher(&self) -> Matcher<'_> {
+        Matcher::new(&self.nfa)
+    }
+    
+    /// Find the first match in the input string
+    pub fn find(&self, input: &str) -> Option<MatchResult> {
+        self.matcher().find(input)
     }
     
+    /// Check if the entire input matches
+    pub fn is_match(&self, input: &str) -> bool {
+        self.matcher().is_match(input)
+    }
     
-    /// Find all matches in the input (greedy)
+    /// Find all non-overlapping leftmost-first matches in the input
     pub fn find_all(&self, input: &str) -> Vec<MatchResult> {
-        let mut matches = Vec::new();
-        let chars: Vec<char> = input.chars().collect();
-        let mut start = 0;
-        
-        while start < chars.len() {
-            let _remaining = &chars[start..];
+        self.matcher().find_all(input)
+    }
+}
+
+/// Iterator over non-overlapping matches, created by `Matcher::find_iter`
+pub struct FindIter<'m, 'a> {
+    matcher: &'m Matcher<'a>,
+    chars: Vec<char>,
+    start: usize,
+    last_match_end: Option<usize>,
+}
+
+impl Iterator for FindIter<'_, '_> {
+    type Item = MatchResult;
+    
+    fn next(&mut self) -> Option<MatchResult> {
+        while self.start <= self.chars.len() {
+            let start = self.start;
+            let matched = self.matcher.can_start_at(&self.chars, start)
+                .then(|| self.matcher.match_at(&self.chars, start))
+                .flatten();
+            let (end, accept) = match matched {
+                Some(matched) => matched,
+                None => {
+                    self.start += 1;
+                    continue;
+                }
+            };
             
-            // Try to find a match starting at this position
-            if let Some(match_len) = self.match_at(&chars, start) {
-                matches.push(MatchResult {
-                    matched: true,
-                    start,
-                    end: match_len,
-                });
-                
-                // Move past this match
-                start = match_len.max(start + 1);
-            } else {
-                start += 1;
+            // An empty match touching the previous match would report nothing new
+            if end == start && self.last_match_end == Some(start) {
+                self.start += 1;
+                continue;
             }
+            
+            self.last_match_end = Some(end);
+            self.start = if end > start { end } else { start + 1 };
+            return Some(self.matcher.match_result(start, end, accept));
         }
         
-        matches
+        None
     }
 }
 
@@ -161,6 +1174,13 @@ impl<'a> Matcher<'a> {
 mod tests {
     use super::*;
     use crate::nfa::TwoCharTransition;
+    use crate::compiler::Compiler;
+    use regex_syntax::ParserBuilder;
+    
+    fn compile(pattern: &str) -> NFA {
+        let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+        Compiler::new().compile(&hir).unwrap()
+    }
     
     #[test]
     fn test_simple_match() {
@@ -218,4 +1238,679 @@ mod tests {
         assert!(!matcher.is_match(""));
         assert!(!matcher.is_match("ab"));
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_match_full_reports_failure_position() {
+        let mut nfa = NFA::new();
+        
+        // Create NFA that matches "abc"
+        let a_state = nfa.transition_state(TwoCharTransition::char('a', usize::MAX));
+        let b_state = nfa.transition_state(TwoCharTransition::char('b', usize::MAX));
+        let c_state = nfa.transition_state(TwoCharTransition::char('c', usize::MAX));
+        let match_state = nfa.match_state();
+        
+        nfa.connect(a_state, b_state);
+        nfa.connect(b_state, c_state);
+        nfa.connect(c_state, match_state);
+        nfa.start = a_state;
+        
+        let matcher = Matcher::new(&nfa);
+        
+        let result = matcher.match_full("abc").unwrap();
+        assert_eq!((result.start, result.end), (0, 3));
+        assert_eq!(matcher.match_full("abx").unwrap_err(), 2);
+        assert_eq!(matcher.match_full("xbc").unwrap_err(), 0);
+        assert_eq!(matcher.match_full("ab").unwrap_err(), 2);
+        assert_eq!(matcher.match_full("abcd").unwrap_err(), 3);
+    }
+    
+    #[test]
+    fn test_end_of_input_lookahead() {
+        use crate::nfa::CharacterPredicate;
+        
+        let mut nfa = NFA::new();
+        
+        // 'a' may only be consumed when it is the last character of the input
+        let a_state = nfa.transition_state(TwoCharTransition::predicate(
+            CharacterPredicate::Char('a'),
+            Some(CharacterPredicate::EndOfInput),
+            0,
+        ));
+        nfa.start = a_state;
+        
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match("a"));
+        assert!(!matcher.is_match("aa"));
+        assert!(matcher.find("ab").is_none());
+        
+        let result = matcher.find("aaa").unwrap();
+        assert_eq!((result.start, result.end), (2, 3));
+    }
+    
+    #[test]
+    fn test_prepared_matcher_agrees() {
+        let patterns = ["ab", "a*", "a+", "a|bc", "[abc]+", "[^ab]", "(?:foo|bar)x?", "a++"];
+        let inputs = ["", "a", "ab", "aaab", "xbcx", "foobarx", "cab", "zzz"];
+        
+        for pattern in patterns {
+            let nfa = compile(pattern);
+            let plain = Matcher::new(&nfa);
+            let prepared = Matcher::prepare(&nfa);
+            
+            for input in inputs {
+                let expected = plain.find(input).map(|m| (m.start, m.end));
+                assert_eq!(prepared.find(input).map(|m| (m.start, m.end)), expected, "{} on {:?}", pattern, input);
+                assert_eq!(prepared.is_match(input), plain.is_match(input), "{} on {:?}", pattern, input);
+                assert_eq!(prepared.find_all(input).len(), plain.find_all(input).len(), "{} on {:?}", pattern, input);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_prepared_matcher_skips_transitions() {
+        let nfa = compile("[a-m]x|[n-z]y|éz");
+        let plain = Matcher::new(&nfa);
+        let prepared = Matcher::prepare(&nfa);
+        
+        // Only the transition accepting each ASCII character is tested
+        for input in ["ax", "ny", "qq", "éz"] {
+            let (expected, plain_stats) = plain.find_with_stats(input);
+            let (found, prepared_stats) = prepared.find_with_stats(input);
+            assert_eq!(found.map(|m| (m.start, m.end)), expected.map(|m| (m.start, m.end)), "{:?}", input);
+            assert!(prepared_stats.transitions_evaluated <= plain_stats.transitions_evaluated, "{:?}", input);
+        }
+        
+        let (_, plain_stats) = plain.find_with_stats("qq");
+        let (_, prepared_stats) = prepared.find_with_stats("qq");
+        assert!(prepared_stats.transitions_evaluated < plain_stats.transitions_evaluated);
+    }
+    
+    #[test]
+    fn test_captures_text() {
+        let nfa = compile(r"(\d+)-(\d+)");
+        let captures = Matcher::new(&nfa).captures("12-34").unwrap();
+        assert_eq!(captures.get(0), Some("12-34"));
+        assert_eq!(captures.get(1), Some("12"));
+        assert_eq!(captures.get(2), Some("34"));
+        assert_eq!(captures.get(3), None);
+        assert_eq!(captures.len(), 3);
+        
+        // Group text is sliced on character boundaries; unmatched groups have none
+        let nfa = compile(r"(?P<word>\w+)(!)?");
+        let captures = Matcher::new(&nfa).captures("→ héllo.").unwrap();
+        assert_eq!(captures.name("word"), Some("héllo"));
+        assert_eq!(captures.span(1), Some((2, 7)));
+        assert_eq!(captures.get(2), None);
+    }
+    
+    #[test]
+    fn test_find_line_col() {
+        let line_col = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find_line_col(input).map(|(_, start, end)| (start, end))
+        };
+        
+        assert_eq!(line_col("bar", "foo\nbar"), Some(((2, 1), (2, 4))));
+        assert_eq!(line_col("o+", "foo\nbar"), Some(((1, 2), (1, 4))));
+        assert_eq!(line_col("o\nb", "foo\nbar"), Some(((1, 3), (2, 2))));
+        assert_eq!(line_col("$", "é\n"), Some(((1, 2), (1, 2))));
+        assert_eq!(line_col("x", "foo"), None);
+        
+        let nfa = compile("ar");
+        let (found, _, _) = Matcher::new(&nfa).find_line_col("foo\nbar").unwrap();
+        assert_eq!((found.start, found.end), (5, 7));
+    }
+    
+    #[test]
+    fn test_find_shortest_nonempty() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find_shortest_nonempty(input).map(|m| (m.start, m.end))
+        };
+        
+        assert_eq!(span("a*", "aaa"), Some((0, 1)));
+        assert_eq!(span("a*", "bba"), Some((2, 3)));
+        assert_eq!(span("ab|abc|a+c", "xaac"), Some((1, 4)));
+        assert_eq!(span("a*", ""), None);
+        assert_eq!(span("a*", "bbb"), None);
+    }
+    
+    #[test]
+    fn test_coverage() {
+        // The pattern `a|b` compiles to a single class, so spell out one transition per branch
+        let mut nfa = NFA::new();
+        nfa.start = nfa.transitions_state(vec![TwoCharTransition::char('a', 0), TwoCharTransition::char('b', 0)]);
+        let matcher = Matcher::new(&nfa);
+        
+        let coverage = matcher.coverage(&["a"]);
+        assert!(coverage.is_covered(nfa.start, 0));
+        assert!(!coverage.is_covered(nfa.start, 1));
+        assert_eq!(coverage.uncovered(), vec![(nfa.start, 1)]);
+        assert!(matcher.coverage(&["a", "xb"]).uncovered().is_empty());
+        
+        // A transition fires even when the thread taking it dies later
+        let nfa = compile("ab|cd");
+        let matcher = Matcher::new(&nfa);
+        let coverage = matcher.coverage(&["xaa"]);
+        assert_eq!(coverage.covered().len(), 1);
+        assert_eq!(coverage.uncovered().len(), 3);
+    }
+    
+    #[test]
+    fn test_find_longest_global() {
+        let nfa = compile("a+");
+        let matcher = Matcher::new(&nfa);
+        
+        let result = matcher.find_longest_global("a aaa a").unwrap();
+        assert_eq!((result.start, result.end), (2, 5));
+        
+        // Ties go to the earliest start, and priority doesn't shorten a match
+        let result = matcher.find_longest_global("aa bb aa").unwrap();
+        assert_eq!((result.start, result.end), (0, 2));
+        let result = Matcher::new(&compile("a+?")).find_longest_global("b aaa").unwrap();
+        assert_eq!((result.start, result.end), (2, 5));
+        
+        assert!(matcher.find_longest_global("bbb").is_none());
+    }
+    
+    #[test]
+    fn test_is_match_anywhere() {
+        let nfa = compile("abc");
+        let matcher = Matcher::new(&nfa);
+        
+        assert!(matcher.is_match_anywhere("xabcx"));
+        assert!(!matcher.is_match("xabcx"));
+        assert!(matcher.is_match_anywhere("abc"));
+        assert!(!matcher.is_match_anywhere("abxabd"));
+        
+        for pattern in ["^abc", "c$", "a+b", "x*", "(?m)^b"] {
+            let nfa = compile(pattern);
+            let matcher = Matcher::new(&nfa);
+            for input in ["", "abc", "xabc", "aab", "a\nb", "zzz"] {
+                assert_eq!(matcher.is_match_anywhere(input), matcher.find(input).is_some(), "{} on {:?}", pattern, input);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_ascii_and_unicode_word_boundaries() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            let found = Matcher::new(&nfa).find(input).map(|m| (m.start, m.end));
+            assert_eq!(found, Matcher::new(&nfa).captures(input).and_then(|m| m.span(0)), "{}", pattern);
+            found
+        };
+        
+        // `é` is a word character to `\b` but not to `(?-u:\b)`
+        assert_eq!(span(r"\bé\b", "é"), Some((0, 1)));
+        assert_eq!(span(r"(?-u:\b)é", "é"), None);
+        assert_eq!(span(r"\bé", "aé"), None);
+        assert_eq!(span(r"(?-u:\b)é", "aé"), Some((1, 2)));
+        
+        assert_eq!(span(r"a\Bé", "aé"), Some((0, 2)));
+        assert_eq!(span(r"a(?-u:\B)é", "aé"), None);
+        assert_eq!(span(r"é(?-u:\B)", "é"), Some((0, 1)));
+    }
+    
+    #[test]
+    fn test_word_classifier() {
+        let nfa = compile("\\bfoo-bar\\b");
+        let span = |matcher: &Matcher, input: &str| matcher.find(input).map(|m| (m.start, m.end));
+        
+        // By default `-` isn't a word character, so `foo-bar` ends at a boundary even
+        // when the next character is `-`
+        let standard = Matcher::new(&nfa);
+        assert_eq!(span(&standard, "x foo-bar y"), Some((2, 9)));
+        assert_eq!(span(&standard, "foo-bar-baz"), Some((0, 7)));
+        assert_eq!(span(&standard, "xfoo-bar"), None);
+        
+        let hyphenated = Matcher::new(&nfa).with_word_classifier(|ch| ch == '-' || ch.is_alphanumeric());
+        assert_eq!(span(&hyphenated, "x foo-bar y"), Some((2, 9)));
+        assert_eq!(span(&hyphenated, "foo-bar-baz"), None);
+        assert_eq!(span(&hyphenated, "-foo-bar"), None);
+        
+        // The classifier applies to `\B` too
+        let nfa = compile("a\\B-");
+        assert_eq!(span(&Matcher::new(&nfa), "a-"), None);
+        assert_eq!(span(&Matcher::new(&nfa).with_word_classifier(|ch| ch == '-' || ch.is_alphanumeric()), "a-"), Some((0, 2)));
+    }
+    
+    #[test]
+    fn test_search_modes() {
+        let span = |matcher: &Matcher, input: &str| matcher.find(input).map(|m| (m.start, m.end));
+        
+        // Leftmost-first takes the first alternative that matches
+        let nfa = compile("a|ab");
+        assert_eq!(span(&Matcher::new(&nfa), "ab"), Some((0, 1)));
+        assert_eq!(span(&Matcher::new(&nfa).with_search_mode(SearchMode::LeftmostFirst), "ab"), Some((0, 1)));
+        let nfa = compile("ab|a");
+        assert_eq!(span(&Matcher::new(&nfa), "ab"), Some((0, 2)));
+        
+        // Leftmost-longest takes the longest match from the leftmost start
+        for pattern in ["a|ab", "ab|a"] {
+            let nfa = compile(pattern);
+            let matcher = Matcher::new(&nfa).with_search_mode(SearchMode::LeftmostLongest);
+            assert_eq!(span(&matcher, "ab"), Some((0, 2)), "{}", pattern);
+            assert_eq!(span(&matcher, "xab"), Some((1, 3)), "{}", pattern);
+        }
+        
+        // An earlier start still wins over a longer match further on
+        let nfa = compile("a|bcd");
+        let matcher = Matcher::new(&nfa).with_search_mode(SearchMode::LeftmostLongest);
+        assert_eq!(span(&matcher, "abcd"), Some((0, 1)));
+        
+        // Lazy quantifiers don't shorten a leftmost-longest match
+        let nfa = compile("a+?");
+        let matcher = Matcher::new(&nfa).with_search_mode(SearchMode::LeftmostLongest);
+        let spans: Vec<_> = matcher.find_all("aa a").iter().map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, vec![(0, 2), (3, 4)]);
+    }
+    
+    #[test]
+    fn test_state_set_matcher_agrees_with_hash_sets() {
+        // The Pike VM tracks its threads in a `HashSet`, so it serves as the reference
+        let patterns = ["a+b", "(a|ab)(c|bcd)", "x*", "[a-c]+?c", "(?:ab|a)++c", "a{2,4}", "^ab|cd$", "(a|b)*abb"];
+        let inputs = ["", "aab", "abcd", "abbcd", "xxabbabb", "aaaaa", "cabababc", "zzz"];
+        
+        for pattern in patterns {
+            let nfa = compile(pattern);
+            let matcher = Matcher::new(&nfa);
+            let prepared = Matcher::prepare(&nfa);
+            let pike = PikeMatcher::new(&nfa);
+            for input in inputs {
+                let expected = pike.find(input).map(|m| (m.start, m.end));
+                assert_eq!(matcher.find(input).map(|m| (m.start, m.end)), expected, "{} on {:?}", pattern, input);
+                assert_eq!(prepared.find(input).map(|m| (m.start, m.end)), expected, "{} on {:?}", pattern, input);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_split() {
+        let nfa = compile(",");
+        let matcher = Matcher::new(&nfa);
+        
+        assert_eq!(matcher.split("a,b,c"), vec!["a", "b", "c"]);
+        assert_eq!(matcher.split(",a,"), vec!["", "a", ""]);
+        assert_eq!(matcher.split("abc"), vec!["abc"]);
+        assert_eq!(matcher.split(""), vec![""]);
+    }
+    
+    #[test]
+    fn test_split_on_empty_match() {
+        let nfa = compile("");
+        let matcher = Matcher::new(&nfa);
+        
+        assert_eq!(matcher.split("abc"), vec!["", "a", "b", "c", ""]);
+        assert_eq!(matcher.split("é!"), vec!["", "é", "!", ""]);
+    }
+    
+    #[test]
+    fn test_matches_empty() {
+        for pattern in ["a*", "(a|)", "a?b?", "(?:ab)*|c", "^"] {
+            assert!(Matcher::new(&compile(pattern)).matches_empty(), "{}", pattern);
+        }
+        for pattern in ["a+", "abc", "a*b", "(?:a|b)+"] {
+            assert!(!Matcher::new(&compile(pattern)).matches_empty(), "{}", pattern);
+        }
+    }
+    
+    #[test]
+    fn test_match_prefix() {
+        let nfa = compile("a+");
+        let matcher = Matcher::new(&nfa);
+        
+        assert_eq!(matcher.match_prefix("aaab"), Some(("aaa", "b")));
+        assert_eq!(matcher.match_prefix("aaa"), Some(("aaa", "")));
+        assert_eq!(matcher.match_prefix("baa"), None);
+        
+        // The split falls on a character boundary, and priority doesn't shorten it
+        let nfa = compile("é+?");
+        assert_eq!(Matcher::new(&nfa).match_prefix("ééx"), Some(("éé", "x")));
+    }
+    
+    #[test]
+    fn test_replace() {
+        let nfa = compile("[0-9]+");
+        let matcher = Matcher::new(&nfa);
+        
+        assert_eq!(matcher.replacen("a1 b22 c333", "#", 2), "a# b# c333");
+        assert_eq!(matcher.replacen("a1 b22 c333", "#", 0), "a# b# c#");
+        assert_eq!(matcher.replace_all("no digits", "#"), "no digits");
+        
+        let nfa = compile("[a-zé]+");
+        let upper = Matcher::new(&nfa).replace_with("café, ok!", |_, text| text.to_uppercase());
+        assert_eq!(upper, "CAFÉ, OK!");
+        
+        // Empty matches are replaced once per position, including after the last character
+        let nfa = compile("x*");
+        assert_eq!(Matcher::new(&nfa).replace_all("axb", "-"), "-a-b-");
+    }
+    
+    #[test]
+    fn test_owned_matcher() {
+        fn identifier_matcher() -> OwnedMatcher {
+            let nfa = compile("[a-z]+");
+            OwnedMatcher::new(nfa)
+        }
+        
+        let matcher = identifier_matcher();
+        assert!(matcher.is_match("abc"));
+        assert!(!matcher.is_match("ab1"));
+        assert_eq!(matcher.find("12ab").map(|m| (m.start, m.end)), Some((2, 4)));
+        assert_eq!(matcher.find_all("a b").len(), 2);
+        
+        let matcher = OwnedMatcher::compile("ab").unwrap();
+        assert!(matcher.is_match("ab"));
+        assert!(matches!(OwnedMatcher::compile("("), Err(crate::CompileError::Parse(_))));
+    }
+    
+    #[test]
+    fn test_find_utf8() {
+        let nfa = compile(r"\p{L}+");
+        let matcher = Matcher::new(&nfa);
+        
+        let result = matcher.find_utf8("café".as_bytes()).unwrap();
+        assert_eq!((result.start, result.end), (0, 5));
+        let result = matcher.find_utf8("12 naïve".as_bytes()).unwrap();
+        assert_eq!((result.start, result.end), (3, 9));
+        
+        // Invalid bytes pass through as their own values and split the letters around them
+        let result = matcher.find_utf8(b"\x80\x80caf\xC3\xA9").unwrap();
+        assert_eq!((result.start, result.end), (2, 7));
+        
+        // Byte classes still see the invalid bytes
+        let hir = ParserBuilder::new().unicode(false).utf8(false).build().parse(r"\xFF+").unwrap();
+        let nfa = Compiler::new().compile(&hir).unwrap();
+        let result = Matcher::new(&nfa).find_utf8(b"ab\xFF\xFFc").unwrap();
+        assert_eq!((result.start, result.end), (2, 4));
+    }
+    
+    #[test]
+    fn test_find_bytes_with_unicode_disabled() {
+        let hir = ParserBuilder::new().unicode(false).build().parse("[a-c]").unwrap();
+        assert!(matches!(hir.kind(), regex_syntax::hir::HirKind::Class(regex_syntax::hir::Class::Bytes(_))));
+        
+        let nfa = Compiler::new().compile(&hir).unwrap();
+        let matcher = Matcher::new(&nfa);
+        
+        let result = matcher.find_bytes(b"b").unwrap();
+        assert_eq!((result.start, result.end), (0, 1));
+        
+        let result = matcher.find_bytes(b"xxc").unwrap();
+        assert_eq!((result.start, result.end), (2, 3));
+        assert!(matcher.find_bytes(b"d").is_none());
+    }
+    
+    #[test]
+    fn test_captures_by_name() {
+        let nfa = compile(r"(?P<w>\w+)-");
+        assert_eq!(nfa.group_name(1), Some("w"));
+        assert_eq!(nfa.group_index("w"), Some(1));
+        
+        let captures = Matcher::new(&nfa).captures("  ab-").unwrap();
+        assert_eq!((captures.as_match().start, captures.as_match().end), (2, 5));
+        assert_eq!(captures.span(0), Some((2, 5)));
+        assert_eq!(captures.name("w"), Some("ab"));
+        assert_eq!(captures.name("missing"), None);
+        
+        // Plain find doesn't carry group spans
+        assert_eq!(Matcher::new(&nfa).find("  ab-").unwrap().name("w"), None);
+    }
+    
+    #[test]
+    fn test_lazy_and_greedy_priority() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find(input).map(|m| (m.start, m.end))
+        };
+        
+        // The earliest start wins first; laziness only shortens the match from there
+        assert_eq!(span("a*?b", "aaab"), Some((0, 4)));
+        assert_eq!(span("a*?b", "xab"), Some((1, 3)));
+        assert_eq!(span("a*?", "aaa"), Some((0, 0)));
+        
+        assert_eq!(span("a+?", "aaa"), Some((0, 1)));
+        assert_eq!(span("a+", "aaa"), Some((0, 3)));
+        assert_eq!(span("a??", "a"), Some((0, 0)));
+        assert_eq!(span("a?", "a"), Some((0, 1)));
+        
+        // A lazy loop still extends when the remainder can't match yet
+        assert_eq!(span("a+?c", "aaac"), Some((0, 4)));
+        
+        let nfa = compile("a+?");
+        let ends: Vec<_> = Matcher::new(&nfa).find_all("aaa").iter().map(|m| m.end).collect();
+        assert_eq!(ends, vec![1, 2, 3]);
+    }
+    
+    #[test]
+    fn test_empty_iterations_leave_the_loop() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find(input).map(|m| (m.start, m.end))
+        };
+        
+        // Expected spans agree with Perl, Python `re` and the regex crate. An iteration
+        // that matches empty ends the loop ahead of any iteration that consumes more.
+        let cases = [
+            ("a|(?:c??)*(?:.){0,2}?(?:[^a])", "caba", Some((0, 1))),
+            (".([ab]*?)*", "cb", Some((0, 1))),
+            ("(.*?)*", "baa", Some((0, 0))),
+            ("([ab]*?)*", "abab", Some((0, 0))),
+            ("(?:a|)*b", "aab", Some((0, 3))),
+            ("(?:a*)*", "aa", Some((0, 2))),
+            ("(?:a??)+b", "ab", Some((0, 2))),
+            ("(?:c??)*?c", "cc", Some((0, 1))),
+            ("(?:c??){1,}x", "ccx", Some((0, 3))),
+            ("(?:c??){2,}.", "cc", Some((0, 1))),
+            ("(?:c??){2,}?.", "cc", Some((0, 1))),
+        ];
+        for (pattern, input, expected) in cases {
+            assert_eq!(span(pattern, input), expected, "{} on {:?}", pattern, input);
+        }
+    }
+    
+    #[test]
+    fn test_find_iter_advancement() {
+        let spans = |pattern: &str, input: &str| -> Vec<(usize, usize)> {
+            let nfa = compile(pattern);
+            Matcher::new(&nfa).find_iter(input).map(|m| (m.start, m.end)).collect()
+        };
+        
+        assert_eq!(spans("a", "aa"), vec![(0, 1), (1, 2)]);
+        assert_eq!(spans("ab", "abab"), vec![(0, 2), (2, 4)]);
+        
+        // Zero-width matches at every position, including the end, each reported once
+        assert_eq!(spans("a?", "b"), vec![(0, 0), (1, 1)]);
+        assert_eq!(spans("a?", "ab"), vec![(0, 1), (2, 2)]);
+        assert_eq!(spans("", ""), vec![(0, 0)]);
+    }
+    
+    #[test]
+    fn test_find_in_range() {
+        let nfa = compile("a");
+        let matcher = Matcher::new(&nfa);
+        
+        let result = matcher.find_in("xaxa", 2..4).unwrap();
+        assert_eq!((result.start, result.end), (3, 4));
+        let result = matcher.find_in("xaxa", 0..4).unwrap();
+        assert_eq!((result.start, result.end), (1, 2));
+        assert!(matcher.find_in("xaxa", 2..3).is_none());
+        
+        // Matches may extend past the end of the range
+        let nfa = compile("ab");
+        let result = Matcher::new(&nfa).find_in("xxab", 0..3).unwrap();
+        assert_eq!((result.start, result.end), (2, 4));
+        
+        // A bound inside 'é' (bytes 1..3) snaps forward to the next character
+        let nfa = compile("[éa]");
+        let matcher = Matcher::new(&nfa);
+        let result = matcher.find_in("xéa", 2..10).unwrap();
+        assert_eq!((result.start, result.end), (3, 4));
+        let result = matcher.find_in("xéa", 1..10).unwrap();
+        assert_eq!((result.start, result.end), (1, 3));
+        
+        // Offsets are in bytes, where `find` counts characters
+        let nfa = compile("b+");
+        let matcher = Matcher::new(&nfa);
+        let input = "ébbé";
+        let result = matcher.find_in(input, 0..input.len()).unwrap();
+        assert_eq!((result.start, result.end), (2, 4));
+        assert_eq!(&input[result.start..result.end], "bb");
+        let result = matcher.find(input).unwrap();
+        assert_eq!((result.start, result.end), (1, 3));
+    }
+    
+    #[test]
+    fn test_find_utf16() {
+        let nfa = compile(r"\p{L}+");
+        let matcher = Matcher::new(&nfa);
+        
+        // The astral letter is a surrogate pair occupying units 1..3
+        let units: Vec<u16> = "1𝒜é 🎉".encode_utf16().collect();
+        let result = matcher.find_utf16(&units).unwrap();
+        assert_eq!((result.start, result.end), (1, 4));
+        
+        let nfa = compile("🎉");
+        let result = Matcher::new(&nfa).find_utf16(&units).unwrap();
+        assert_eq!((result.start, result.end), (5, 7));
+        
+        // A lone surrogate splits the input and matches nothing
+        let nfa = compile("a.b");
+        let matcher = Matcher::new(&nfa);
+        assert!(matcher.find_utf16(&[0x61, 0xD800, 0x62]).is_none());
+        let result = matcher.find_utf16(&[0xD800, 0x61, 0x78, 0x62]).unwrap();
+        assert_eq!((result.start, result.end), (1, 4));
+    }
+    
+    #[test]
+    fn test_find_with_stats() {
+        let nfa = compile("a");
+        let matcher = Matcher::new(&nfa);
+        
+        // One transition test per start position, so the work grows linearly
+        for n in [10, 100, 1000] {
+            let input = format!("{}a", "b".repeat(n));
+            let (found, stats) = matcher.find_with_stats(&input);
+            
+            assert_eq!(found.map(|m| (m.start, m.end)), Some((n, n + 1)));
+            assert_eq!(stats.transitions_evaluated, n + 1);
+            assert_eq!(stats.start_positions, n + 1);
+            assert_eq!(stats.max_live_states, 1);
+        }
+        
+        let (found, stats) = matcher.find_with_stats("bbb");
+        assert!(found.is_none());
+        assert_eq!(stats.start_positions, 4);
+    }
+    
+    #[test]
+    fn test_accepting_ends_from() {
+        let nfa = compile("a+");
+        let matcher = Matcher::new(&nfa);
+        let chars: Vec<char> = "aaa".chars().collect();
+        
+        assert_eq!(matcher.accepting_ends_from(&chars, 0), vec![1, 2, 3]);
+        assert_eq!(matcher.accepting_ends_from(&chars, 2), vec![3]);
+        assert_eq!(matcher.accepting_ends_from(&chars, 3), Vec::<usize>::new());
+        
+        // Lazy quantifiers don't hide the longer ends
+        let nfa = compile("a+?");
+        assert_eq!(Matcher::new(&nfa).accepting_ends_from(&chars, 0), vec![1, 2, 3]);
+    }
+    
+    #[test]
+    fn test_match_reports_accept_annotation() {
+        let hirs: Vec<_> = ["if", "[a-z]+"].iter()
+            .map(|pattern| ParserBuilder::new().build().parse(pattern).unwrap())
+            .collect();
+        let mut nfa = Compiler::new().compile_many(&hirs).unwrap();
+        let keyword_accept = nfa.match_state();
+        let identifier_accept = *nfa.accepting.iter().find(|&&state| nfa.pattern_id(state) == Some(1)).unwrap();
+        
+        assert!(nfa.set_annotation(keyword_accept, 7));
+        assert!(nfa.set_annotation(identifier_accept, 1));
+        assert!(!nfa.set_annotation(nfa.start, 3));
+        
+        let matcher = Matcher::new(&nfa);
+        assert_eq!(matcher.find("if").unwrap().annotation, Some(7));
+        assert_eq!(matcher.find("x").unwrap().annotation, Some(1));
+        assert_eq!(matcher.captures("if").unwrap().as_match().annotation, Some(7));
+        assert_eq!(matcher.match_full("if").unwrap().annotation, Some(7));
+        
+        // Unannotated accepting states report nothing
+        let nfa = compile("if");
+        assert_eq!(Matcher::new(&nfa).find("if").unwrap().annotation, None);
+    }
+    
+    #[test]
+    fn test_first_set_skipping_matches_naive_scan() {
+        let patterns = ["zq[0-9]+", "abc|xyz", "a*", "(?:x|)y", "[^a]b", "\\d+$", "(?:foo)+?", "(?i)k"];
+        let inputs = ["", "a", "xxyz", "zq12 zq3", "aab", "bb", "12 34", "a foo", "K"];
+        
+        for pattern in patterns {
+            let nfa = compile(pattern);
+            let matcher = Matcher::new(&nfa);
+            for input in inputs {
+                // `find_with_stats` tries every start position
+                let naive = matcher.find_with_stats(input).0;
+                assert_eq!(
+                    matcher.find(input).map(|m| (m.start, m.end)),
+                    naive.map(|m| (m.start, m.end)),
+                    "{} on {:?}", pattern, input
+                );
+            }
+        }
+    }
+    
+    #[test]
+    fn test_max_match_len() {
+        let nfa = compile("a+");
+        let matcher = Matcher::new(&nfa).with_max_match_len(3);
+        let input = "a".repeat(1000);
+        
+        assert_eq!(matcher.find(&input).map(|m| (m.start, m.end)), Some((0, 3)));
+        assert_eq!(matcher.find_all(&input).len(), 334);
+        assert!(matcher.is_match("aaa"));
+        assert!(!matcher.is_match("aaaa"));
+        assert_eq!(matcher.match_full("aaaa").err(), Some(3));
+        
+        // A match that can't finish within the limit isn't reported
+        let nfa = compile("a+b");
+        let matcher = Matcher::new(&nfa).with_max_match_len(3);
+        assert_eq!(matcher.find("aaaab").map(|m| (m.start, m.end)), Some((2, 5)));
+        assert!(matcher.find(&"a".repeat(100)).is_none());
+    }
+    
+    #[test]
+    fn test_streaming_find_matches_collected_chars() {
+        let patterns = ["zq[0-9]+", "a*", "(?m)^b+$", "x\\z", "(?R)(?m)y$", "c\\n?\\z", "[^a-z]{2}"];
+        let mut input = "ab\r\ncd xy\nb".repeat(5000);
+        input.push_str("zq42\n");
+        let chars: Vec<char> = input.chars().collect();
+        
+        let mut nfas: Vec<NFA> = patterns.iter().map(|pattern| compile(pattern)).collect();
+        let behind = ParserBuilder::new().build().parse("d x").unwrap();
+        let hir = ParserBuilder::new().build().parse("y").unwrap();
+        nfas.push(Compiler::new().compile_lookbehind(&behind, &hir).unwrap());
+        
+        for nfa in &nfas {
+            for matcher in [Matcher::new(nfa), Matcher::prepare(nfa), Matcher::new(nfa).with_max_match_len(2)] {
+                assert_eq!(
+                    matcher.find(&input).map(|m| (m.start, m.end)),
+                    matcher.find_chars(&chars).map(|m| (m.start, m.end)),
+                    "{}", nfa.snapshot()
+                );
+                for short in ["", "b", "xz", "cd xy", "c\n", "\r\ny"] {
+                    let chars: Vec<char> = short.chars().collect();
+                    assert_eq!(
+                        matcher.find(short).map(|m| (m.start, m.end)),
+                        matcher.find_chars(&chars).map(|m| (m.start, m.end)),
+                        "{:?} in {}", short, nfa.snapshot()
+                    );
+                }
+            }
+        }
+    }
+}
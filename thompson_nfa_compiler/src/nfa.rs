@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use crate::{CompileError, CompileResult};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
 /// A state ID in the NFA
 pub type StateId = usize;
@@ -14,6 +15,12 @@ pub enum CharacterPredicate {
     CharSet(std::collections::HashSet<char>),
     /// Match any character NOT in a set (negated)
     NotCharSet(std::collections::HashSet<char>),
+    /// Match only the end of input (never a character); meaningful as a lookahead
+    EndOfInput,
+    /// Match any character in an inclusive range
+    Range(char, char),
+    /// Match any character in a list of sorted, non-overlapping inclusive ranges
+    Ranges(Vec<(char, char)>),
 }
 
 impl CharacterPredicate {
@@ -24,7 +31,106 @@ impl CharacterPredicate {
             CharacterPredicate::Char(expected) => ch == *expected,
             CharacterPredicate::CharSet(set) => set.contains(&ch),
             CharacterPredicate::NotCharSet(set) => !set.contains(&ch),
+            CharacterPredicate::EndOfInput => false,
+            CharacterPredicate::Range(start, end) => (*start..=*end).contains(&ch),
+            CharacterPredicate::Ranges(ranges) => {
+                let index = ranges.partition_point(|&(_, end)| end < ch);
+                ranges.get(index).is_some_and(|&(start, _)| start <= ch)
+            },
+        }
+    }
+    
+    /// Check whether some character satisfies both predicates
+    pub fn intersects(&self, other: &CharacterPredicate) -> bool {
+        use CharacterPredicate::*;
+        
+        match (self, other) {
+            (EndOfInput, _) | (_, EndOfInput) => false,
+            (Any, other) | (other, Any) => !matches!(other, CharSet(set) if set.is_empty()),
+            (Char(ch), other) | (other, Char(ch)) => other.matches(*ch),
+            (CharSet(set), other) | (other, CharSet(set)) => set.iter().any(|&ch| other.matches(ch)),
+            // Complements of finite sets always share characters
+            (NotCharSet(_), NotCharSet(_)) => true,
+            _ => intersect_predicates(self, other).is_some(),
+        }
+    }
+    
+    /// Build the predicate matching exactly the characters that satisfy both
+    ///
+    /// Returns an empty `CharSet` when no character does, including when either side is
+    /// `EndOfInput`.
+    pub fn intersection(&self, other: &CharacterPredicate) -> CharacterPredicate {
+        intersect_predicates(self, other).unwrap_or_else(|| CharacterPredicate::CharSet(HashSet::new()))
+    }
+    
+    /// Build the lookahead predicate that holds exactly where this one doesn't
+    ///
+    /// As a lookahead, negated sets and `EndOfInput` hold at the end of input and other
+    /// predicates don't, so `Char('a')` negates to `NotCharSet({a})`, which also holds
+    /// at the end, while a range negates to its complement ranges, which don't; pair
+    /// those with an `EndOfInput` lookahead to cover the end. `Any` negates to an empty
+    /// `CharSet`.
+    pub fn negate(&self) -> CharacterPredicate {
+        match self {
+            CharacterPredicate::Any => CharacterPredicate::CharSet(HashSet::new()),
+            CharacterPredicate::Char(ch) => CharacterPredicate::NotCharSet(HashSet::from([*ch])),
+            CharacterPredicate::CharSet(set) => CharacterPredicate::NotCharSet(set.clone()),
+            CharacterPredicate::NotCharSet(set) => CharacterPredicate::CharSet(set.clone()),
+            // Anything but end of input is any actual character
+            CharacterPredicate::EndOfInput => CharacterPredicate::Any,
+            CharacterPredicate::Range(..) | CharacterPredicate::Ranges(_) => {
+                let ranges = self.ranges().unwrap_or_default();
+                CharacterPredicate::from_ranges(complement_ranges(&ranges))
+                    .unwrap_or_else(|| CharacterPredicate::CharSet(HashSet::new()))
+            },
+        }
+    }
+    
+    /// Build the predicate matching any character in a list of inclusive ranges
+    ///
+    /// Ranges are sorted and overlapping or adjacent ones merged. A single range becomes
+    /// a `Range` (or a `Char` if it holds one character); no ranges yields `None`.
+    pub fn from_ranges(mut ranges: Vec<(char, char)>) -> Option<Self> {
+        ranges.sort();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if next_char(last.1).is_none_or(|next| start <= next) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
         }
+        
+        match merged.as_slice() {
+            [] => None,
+            [(start, end)] if start == end => Some(CharacterPredicate::Char(*start)),
+            [(start, end)] => Some(CharacterPredicate::Range(*start, *end)),
+            _ => Some(CharacterPredicate::Ranges(merged)),
+        }
+    }
+    
+    /// Get the ranges of a `Range` or `Ranges` predicate
+    fn ranges(&self) -> Option<Vec<(char, char)>> {
+        match self {
+            CharacterPredicate::Range(start, end) => Some(vec![(*start, *end)]),
+            CharacterPredicate::Ranges(ranges) => Some(ranges.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Get the character after `ch`, skipping the surrogate gap
+fn next_char(ch: char) -> Option<char> {
+    match ch {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(ch as u32 + 1),
+    }
+}
+
+/// Get the character before `ch`, skipping the surrogate gap
+fn prev_char(ch: char) -> Option<char> {
+    match ch {
+        '\u{E000}' => Some('\u{D7FF}'),
+        _ => char::from_u32((ch as u32).checked_sub(1)?),
     }
 }
 
@@ -85,6 +191,28 @@ impl TwoCharTransition {
         }
     }
 
+    /// Check if this transition fires on the current character and lookahead (`None` at end of input)
+    pub fn matches(&self, current_char: char, next_char: Option<char>) -> bool {
+        // Check current character predicate
+        if !self.current.matches(current_char) {
+            return false;
+        }
+        
+        // Check lookahead predicate
+        match (&self.lookahead, next_char) {
+            (None, _) => true, // No lookahead constraint
+            (Some(lookahead_pred), Some(actual)) => lookahead_pred.matches(actual),
+            (Some(lookahead_pred), None) => {
+                // At end of input - negative and end-of-input lookaheads succeed, positive fails
+                match lookahead_pred {
+                    CharacterPredicate::NotCharSet(_) => true, // Negative: succeeds at end
+                    CharacterPredicate::EndOfInput => true, // Only succeeds at end
+                    _ => false, // Positive: fails at end
+                }
+            }
+        }
+    }
+    
     /// Create a transition with character set predicates  
     pub fn predicate(current: CharacterPredicate, lookahead: Option<CharacterPredicate>, target: StateId) -> Self {
         TwoCharTransition {
@@ -119,6 +247,146 @@ pub enum State {
     
     /// Rejected state (never matches, dead end)
     Rejected,
+    
+    /// Zero-width assertion: continues to `next` without consuming input, but only
+    /// where the assertion holds at the current position
+    Assertion {
+        kind: AssertionKind,
+        next: StateId,
+    },
+}
+
+/// A zero-width condition on the input around the current position
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssertionKind {
+    /// The `width` characters just before the position are matched exactly by `body`
+    LookBehind {
+        body: Box<NFA>,
+        width: usize,
+    },
+    /// The position is the start of the input
+    StartText,
+    /// The position is the start of the input or of a line; with `crlf`, a lone `\r`
+    /// also ends a line but the position between `\r` and `\n` does not start one
+    StartLine {
+        crlf: bool,
+    },
+    /// The position is the end of the input or of a line; with `crlf`, a line also ends
+    /// before a `\r`, and the position between `\r` and `\n` does not end one
+    EndLine {
+        crlf: bool,
+    },
+    /// The position is the end of the input, or just before a final `\n` when
+    /// `allow_trailing_newline` is set
+    EndText {
+        allow_trailing_newline: bool,
+    },
+    /// Exactly one of the characters on either side of the position is a word character
+    /// (`\b`), or with `negated` either both or neither are (`\B`); with `ascii` only
+    /// `[0-9A-Za-z_]` count as word characters
+    WordBoundary {
+        ascii: bool,
+        negated: bool,
+    },
+    /// The input from the position on doesn't start with any of `sequences`, each a run
+    /// of one predicate per character
+    NotFollowedBy {
+        sequences: Vec<Vec<CharacterPredicate>>,
+    },
+}
+
+impl AssertionKind {
+    /// Check whether the assertion holds at `position` in `chars`
+    pub fn holds(&self, chars: &[char], position: usize) -> bool {
+        let is_word = match self {
+            AssertionKind::WordBoundary { ascii: true, .. } => is_ascii_word_char,
+            _ => is_word_char,
+        };
+        self.holds_with(chars, position, is_word)
+    }
+    
+    /// Check whether the assertion holds at `position`, with `is_word` deciding which
+    /// characters a word boundary separates
+    pub fn holds_with(&self, chars: &[char], position: usize, is_word: fn(char) -> bool) -> bool {
+        match self {
+            AssertionKind::LookBehind { body, width } => {
+                position >= *width
+                    && crate::matcher::Matcher::new(body).is_match_chars(&chars[position - width..position])
+            },
+            AssertionKind::StartText => position == 0,
+            AssertionKind::StartLine { crlf } => match position.checked_sub(1).map(|before| chars[before]) {
+                None | Some('\n') => true,
+                Some('\r') => *crlf && chars.get(position) != Some(&'\n'),
+                Some(_) => false,
+            },
+            AssertionKind::EndLine { crlf } => match chars.get(position) {
+                None => true,
+                Some('\n') => !*crlf || position == 0 || chars[position - 1] != '\r',
+                Some('\r') => *crlf,
+                Some(_) => false,
+            },
+            AssertionKind::EndText { allow_trailing_newline } => {
+                position == chars.len()
+                    || (*allow_trailing_newline && position + 1 == chars.len() && chars[position] == '\n')
+            },
+            AssertionKind::WordBoundary { negated, .. } => {
+                let before = position.checked_sub(1).is_some_and(|before| is_word(chars[before]));
+                let after = chars.get(position).is_some_and(|&ch| is_word(ch));
+                (before != after) != *negated
+            },
+            AssertionKind::NotFollowedBy { sequences } => !sequences.iter().any(|sequence| {
+                let rest = &chars[position..];
+                sequence.len() <= rest.len() && sequence.iter().zip(rest).all(|(predicate, &ch)| predicate.matches(ch))
+            }),
+        }
+    }
+}
+
+/// Check whether a character is a Unicode word character, as `\w` defines it
+pub fn is_word_char(ch: char) -> bool {
+    regex_syntax::try_is_word_character(ch).unwrap_or_else(|_| is_ascii_word_char(ch))
+}
+
+/// Check whether a character is an ASCII word character, `[0-9A-Za-z_]`
+pub fn is_ascii_word_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+impl std::fmt::Display for AssertionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssertionKind::LookBehind { width, .. } => write!(f, "lookbehind(width {})", width),
+            AssertionKind::StartText => write!(f, "start"),
+            AssertionKind::StartLine { crlf: false } => write!(f, "start_line"),
+            AssertionKind::StartLine { crlf: true } => write!(f, "start_line(crlf)"),
+            AssertionKind::EndLine { crlf: false } => write!(f, "end_line"),
+            AssertionKind::EndLine { crlf: true } => write!(f, "end_line(crlf)"),
+            AssertionKind::EndText { allow_trailing_newline: false } => write!(f, "end"),
+            AssertionKind::EndText { allow_trailing_newline: true } => write!(f, "end(\\n?)"),
+            AssertionKind::WordBoundary { ascii, negated } => {
+                write!(f, "{}word_boundary{}", if *negated { "not_" } else { "" }, if *ascii { "(ascii)" } else { "" })
+            },
+            AssertionKind::NotFollowedBy { sequences } => {
+                let sequences: Vec<String> = sequences.iter()
+                    .map(|sequence| sequence.iter().map(predicate_snapshot).collect::<Vec<_>>().join(" "))
+                    .collect();
+                write!(f, "not_followed_by({})", sequences.join(" | "))
+            },
+        }
+    }
+}
+
+/// One edge of an NFA's transition table, as exported by `NFA::transition_table`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionRow {
+    /// State the edge leaves
+    pub from: StateId,
+    /// Character the edge consumes, or `None` for an edge that consumes nothing
+    pub current: Option<CharacterPredicate>,
+    /// Condition on the character after the consumed one, if any
+    pub lookahead: Option<CharacterPredicate>,
+    /// State the edge leads to
+    pub to: StateId,
 }
 
 /// Fragment of an NFA with start and end states
@@ -128,6 +396,66 @@ pub struct Fragment {
     pub end: StateId,
 }
 
+/// Size metrics of a compiled NFA, as reported by `NFA::stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NfaStats {
+    /// Number of `Transitions` states
+    pub transition_states: usize,
+    /// Number of `Epsilon` states
+    pub epsilon_states: usize,
+    /// Number of `Split` states
+    pub split_states: usize,
+    /// Number of `Match` states
+    pub match_states: usize,
+    /// Number of `Rejected` states
+    pub rejected_states: usize,
+    /// Number of `Assertion` states
+    pub assertion_states: usize,
+    /// Total number of two-character transitions
+    pub transitions: usize,
+    /// Number of transitions with a lookahead predicate
+    pub lookahead_transitions: usize,
+    /// Number of accepting states
+    pub accepting_states: usize,
+    /// Largest number of transitions on a single state
+    pub max_transitions_per_state: usize,
+}
+
+/// Graph complexity of the part of an NFA reachable from its start, as reported by
+/// `NFA::complexity`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Complexity {
+    /// Number of reachable states
+    pub states: usize,
+    /// Number of two-character transitions on reachable states
+    pub transitions: usize,
+    /// Edges minus states plus two, counting every transition and epsilon edge
+    pub cyclomatic: usize,
+    /// Number of back edges a depth-first walk from the start finds, one per loop
+    pub loops: usize,
+}
+
+/// How a quantifier chooses between another repetition and leaving its loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuantifierKind {
+    /// Takes as many repetitions as it can, giving them back when the rest fails
+    Greedy,
+    /// Takes as few repetitions as it can (`*?`, `+?`, `{n,}?`)
+    Lazy,
+    /// Takes as many repetitions as it can and never gives them back (`*+`, `++`, `{n,}+`)
+    Possessive,
+}
+
+/// The quantifier a loop state was compiled from, as reported by `NFA::quantifier_at`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantifierInfo {
+    pub kind: QuantifierKind,
+    /// Fewest repetitions the quantifier accepts
+    pub min: u32,
+    /// Most repetitions the quantifier accepts, or `None` if unbounded
+    pub max: Option<u32>,
+}
+
 /// A Thompson NFA with two-character transitions
 #[derive(Debug, Clone, PartialEq)]
 pub struct NFA {
@@ -135,8 +463,19 @@ pub struct NFA {
     pub states: Vec<State>,
     /// Starting state
     pub start: StateId,
-    /// Set of accepting states
-    pub accepting: HashSet<StateId>,
+    /// Set of accepting states, kept ordered so iteration and output are reproducible
+    pub accepting: BTreeSet<StateId>,
+    /// Pattern ID of each accepting state (states without an entry belong to pattern 0)
+    pub pattern_ids: HashMap<StateId, usize>,
+    /// Name of each capture group by index (group 0 is the whole match and has no name)
+    pub group_names: Vec<Option<String>>,
+    /// Capture slot recorded when passing through an epsilon state: `2 * group` marks
+    /// the start of a group and `2 * group + 1` its end
+    pub capture_slots: HashMap<StateId, usize>,
+    /// Caller-assigned annotation of each accepting state, such as a lexer token ID
+    pub annotations: HashMap<StateId, u32>,
+    /// Quantifier of each loop state, the state an unbounded repetition returns to
+    pub quantifiers: HashMap<StateId, QuantifierInfo>,
     /// Next available state ID
     next_id: StateId,
 }
@@ -147,7 +486,12 @@ impl NFA {
         let mut nfa = Self {
             states: Vec::new(),
             start: 2, // Start from state 2, since 0 and 1 are reserved
-            accepting: HashSet::new(),
+            accepting: BTreeSet::new(),
+            pattern_ids: HashMap::new(),
+            group_names: vec![None],
+            capture_slots: HashMap::new(),
+            annotations: HashMap::new(),
+            quantifiers: HashMap::new(),
             next_id: 0,
         };
         
@@ -163,6 +507,23 @@ impl NFA {
         nfa
     }
     
+    /// Create an NFA from already-numbered states, such as when decoding a saved NFA
+    ///
+    /// Unlike `new`, no states are reserved; `states` is taken as the full state list.
+    pub(crate) fn from_states(states: Vec<State>, start: StateId, accepting: BTreeSet<StateId>) -> Self {
+        Self {
+            next_id: states.len(),
+            states,
+            start,
+            accepting,
+            pattern_ids: HashMap::new(),
+            group_names: vec![None],
+            capture_slots: HashMap::new(),
+            annotations: HashMap::new(),
+            quantifiers: HashMap::new(),
+        }
+    }
+    
     /// Add a new state and return its ID
     pub fn add_state(&mut self, state: State) -> StateId {
         let id = self.next_id;
@@ -176,6 +537,11 @@ impl NFA {
         self.add_state(State::Epsilon { next })
     }
     
+    /// Create a zero-width assertion state
+    pub fn assertion(&mut self, kind: AssertionKind, next: StateId) -> StateId {
+        self.add_state(State::Assertion { kind, next })
+    }
+    
     /// Create a split state with multiple targets
     pub fn split(&mut self, targets: Vec<StateId>) -> StateId {
         self.add_state(State::Split { targets })
@@ -203,7 +569,143 @@ impl NFA {
         1 // Always return the reserved rejected state
     }
     
+    /// Create an additional accepting state for the given pattern ID
+    pub fn add_match_state(&mut self, pattern_id: usize) -> StateId {
+        let id = self.add_state(State::Match);
+        self.accepting.insert(id);
+        self.pattern_ids.insert(id, pattern_id);
+        id
+    }
+    
+    /// Get the pattern ID of an accepting state, or `None` if the state isn't accepting
+    pub fn pattern_id(&self, state: StateId) -> Option<usize> {
+        if !self.accepting.contains(&state) {
+            return None;
+        }
+        Some(self.pattern_ids.get(&state).copied().unwrap_or(0))
+    }
+    
+    /// Attach an annotation to an accepting state, reported by matches that end there
+    ///
+    /// Returns `false` and leaves the NFA unchanged if the state isn't accepting.
+    pub fn set_annotation(&mut self, state: StateId, annotation: u32) -> bool {
+        if !self.accepting.contains(&state) {
+            return false;
+        }
+        self.annotations.insert(state, annotation);
+        true
+    }
+    
+    /// Get the annotation of an accepting state, if one was set
+    pub fn annotation(&self, state: StateId) -> Option<u32> {
+        self.annotations.get(&state).copied()
+    }
+    
+    /// Get the quantifier a loop state was compiled from
+    ///
+    /// Only the state each iteration of an unbounded repetition returns to carries one:
+    /// the split of a greedy or lazy loop, or the first state of a possessive loop's body.
+    pub fn quantifier_at(&self, state: StateId) -> Option<QuantifierInfo> {
+        self.quantifiers.get(&state).copied()
+    }
+    
+    /// Classify the entire input, returning the ID of the pattern that accepts all of it
+    ///
+    /// When several patterns accept the input the lowest pattern ID wins.
+    pub fn classify(&self, input: &str) -> Option<usize> {
+        crate::matcher::Matcher::new(self).classify(input)
+    }
+    
+    /// Count states by kind and summarize transitions
+    pub fn stats(&self) -> NfaStats {
+        let mut stats = NfaStats {
+            accepting_states: self.accepting.len(),
+            ..NfaStats::default()
+        };
+        
+        for state in &self.states {
+            match state {
+                State::Transitions { transitions } => {
+                    stats.transition_states += 1;
+                    stats.transitions += transitions.len();
+                    stats.lookahead_transitions += transitions.iter().filter(|t| t.lookahead.is_some()).count();
+                    stats.max_transitions_per_state = stats.max_transitions_per_state.max(transitions.len());
+                },
+                State::Epsilon { .. } => stats.epsilon_states += 1,
+                State::Split { .. } => stats.split_states += 1,
+                State::Match => stats.match_states += 1,
+                State::Rejected => stats.rejected_states += 1,
+                State::Assertion { .. } => stats.assertion_states += 1,
+            }
+        }
+        
+        stats
+    }
+    
+    /// Measure the graph complexity of the states reachable from the start
+    ///
+    /// Unlike `stats`, unreachable states (such as the reserved rejected state) don't
+    /// count, so patterns compiled into NFAs of the same shape score the same.
+    pub fn complexity(&self) -> Complexity {
+        let reachable = self.reachable_from(self.start);
+        let mut complexity = Complexity { states: reachable.len(), ..Complexity::default() };
+        
+        let mut edges = 0;
+        for &state_id in &reachable {
+            complexity.transitions += self.transitions_of(state_id).len();
+            edges += self.successors(state_id).len();
+        }
+        complexity.cyclomatic = edges + 2 - complexity.states;
+        
+        // Iterative depth-first walk; an edge back to a state still on the path closes a loop
+        let mut on_path = vec![false; self.states.len()];
+        let mut visited = vec![false; self.states.len()];
+        let mut stack: Vec<(StateId, Vec<StateId>)> = Vec::new();
+        if self.start < self.states.len() {
+            visited[self.start] = true;
+            on_path[self.start] = true;
+            stack.push((self.start, self.successors(self.start).into_iter().map(|(target, _)| target).collect()));
+        }
+        while let Some((state_id, pending)) = stack.last_mut() {
+            match pending.pop() {
+                Some(target) if on_path[target] => complexity.loops += 1,
+                Some(target) if !visited[target] => {
+                    visited[target] = true;
+                    on_path[target] = true;
+                    let successors = self.successors(target).into_iter().map(|(target, _)| target).collect();
+                    stack.push((target, successors));
+                },
+                Some(_) => {},
+                None => {
+                    on_path[*state_id] = false;
+                    stack.pop();
+                },
+            }
+        }
+        
+        complexity
+    }
+    
+    /// Get the number of capture groups, including the implicit group 0
+    pub fn group_count(&self) -> usize {
+        self.group_names.len()
+    }
+    
+    /// Get the name of a capture group, or `None` if it is unnamed or doesn't exist
+    pub fn group_name(&self, index: usize) -> Option<&str> {
+        self.group_names.get(index)?.as_deref()
+    }
+    
+    /// Get the index of the capture group with the given name
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.group_names.iter().position(|group| group.as_deref() == Some(name))
+    }
+    
     /// Connect two states with an epsilon transition
+    ///
+    /// An epsilon or assertion has its target replaced and a transitions state has its
+    /// unpatched targets filled in. A split gains `to` as its last, lowest-priority target
+    /// unless it already has it, so connecting the same pair twice changes nothing.
     pub fn connect(&mut self, from: StateId, to: StateId) {
         if from >= self.states.len() {
             return;
@@ -211,7 +713,11 @@ impl NFA {
         
         match &mut self.states[from] {
             State::Epsilon { next } => *next = to,
-            State::Split { targets } => targets.push(to),
+            State::Split { targets } => {
+                if !targets.contains(&to) {
+                    targets.push(to);
+                }
+            },
             State::Transitions { transitions } => {
                 // Update all transitions that have a target of usize::MAX (unpatched) to point to 'to'
                 for transition in transitions {
@@ -222,10 +728,14 @@ impl NFA {
             },
             State::Match => {}, // Match states don't have outgoing transitions
             State::Rejected => {}, // Rejected states don't have outgoing transitions
+            State::Assertion { next, .. } => *next = to,
         }
     }
     
     /// Get epsilon closure of a set of states
+    ///
+    /// Assertions are followed as if they hold, since no input position is known here;
+    /// matchers check them against the input instead of using this closure.
     pub fn epsilon_closure(&self, states: &HashSet<StateId>) -> HashSet<StateId> {
         let mut closure = states.clone();
         let mut stack: Vec<StateId> = states.iter().cloned().collect();
@@ -236,7 +746,7 @@ impl NFA {
             }
             
             match &self.states[state_id] {
-                State::Epsilon { next } => {
+                State::Epsilon { next } | State::Assertion { next, .. } => {
                     if closure.insert(*next) {
                         stack.push(*next);
                     }
@@ -260,26 +770,1874 @@ impl NFA {
         states.iter().any(|&state| self.accepting.contains(&state))
     }
 
-    /// Get all possible two-character transitions from a set of states
-    pub fn get_two_char_transitions(&self, states: &HashSet<StateId>) -> Vec<TwoCharTransition> {
-        let mut transitions = Vec::new();
+    /// Remove rejected states and every edge that only routes into them
+    ///
+    /// Rejected states never accept, so dropping the edges into them leaves the accepted
+    /// language unchanged. The reserved rejected state (1) is kept so state IDs 0 and 1
+    /// keep their meaning; any other rejected states are deleted and the remaining states
+    /// renumbered.
+    pub fn prune_rejected(&mut self) {
+        let rejected: HashSet<StateId> = self.states.iter()
+            .enumerate()
+            .filter(|(_, state)| matches!(state, State::Rejected))
+            .map(|(id, _)| id)
+            .collect();
         
-        for &state_id in states {
-            if state_id >= self.states.len() {
+        if rejected.is_empty() {
+            return;
+        }
+        
+        for state in &mut self.states {
+            match state {
+                State::Transitions { transitions } => {
+                    transitions.retain(|t| !rejected.contains(&t.target));
+                },
+                State::Split { targets } => {
+                    targets.retain(|target| !rejected.contains(target));
+                },
+                State::Epsilon { next } | State::Assertion { next, .. } if rejected.contains(next) => {
+                    // A dead end that no longer routes anywhere
+                    *state = State::Split { targets: Vec::new() };
+                },
+                _ => {},
+            }
+        }
+        
+        let keep: Vec<bool> = (0..self.states.len())
+            .map(|id| id == 1 || !rejected.contains(&id))
+            .collect();
+        self.retain_states(&keep);
+    }
+    
+    /// Route every edge into the epsilon state `id` straight to its target and delete it
+    ///
+    /// The remaining states are renumbered. Does nothing if `id` isn't an epsilon, is the
+    /// start, or records a capture slot.
+    pub(crate) fn bypass_epsilon(&mut self, id: StateId) {
+        let next = match self.states.get(id) {
+            Some(State::Epsilon { next }) if id != self.start && !self.capture_slots.contains_key(&id) => *next,
+            _ => return,
+        };
+        let redirect = |target: &mut StateId| if *target == id { *target = next };
+        
+        for state in &mut self.states {
+            match state {
+                State::Transitions { transitions } => {
+                    transitions.iter_mut().for_each(|transition| redirect(&mut transition.target));
+                },
+                State::Split { targets } => {
+                    targets.iter_mut().for_each(redirect);
+                    // Keep only the first copy of a target, so priorities stay as they were
+                    let mut seen = HashSet::new();
+                    targets.retain(|&target| seen.insert(target));
+                },
+                State::Epsilon { next } | State::Assertion { next, .. } => redirect(next),
+                State::Match | State::Rejected => {},
+            }
+        }
+        
+        let keep: Vec<bool> = (0..self.states.len()).map(|state_id| state_id != id).collect();
+        self.retain_states(&keep);
+    }
+    
+    /// List states that can never take part in a match, for diagnosing miscompilations
+    ///
+    /// Returns the states unreachable from `start`, then the states from which no
+    /// accepting state is reachable, each in ascending order. A state can appear in both.
+    /// The reserved rejected state (1) is dead by design and is never reported.
+    pub fn dead_states(&self) -> (Vec<StateId>, Vec<StateId>) {
+        let reachable = self.reachable_from(self.start);
+        let reaching_accept = self.states_reaching_accept();
+        let reported = |&id: &StateId| id != 1;
+        
+        let unreachable = (0..self.states.len())
+            .filter(reported)
+            .filter(|id| !reachable.contains(id))
+            .collect();
+        let cannot_accept = (0..self.states.len())
+            .filter(reported)
+            .filter(|id| !reaching_accept.contains(id))
+            .collect();
+        
+        (unreachable, cannot_accept)
+    }
+    
+    /// Copy the states reachable from `root` into a standalone NFA starting at `root`
+    ///
+    /// The copy is renumbered in the original order and keeps the reserved states 0 and
+    /// 1, along with the accepting set, pattern IDs, capture slots and annotations of the
+    /// states it contains. An out-of-range `root` leaves the start unpatched (`usize::MAX`),
+    /// so the copy matches nothing.
+    pub fn subgraph_from(&self, root: StateId) -> NFA {
+        let reachable = self.reachable_from(root);
+        let keep: Vec<bool> = (0..self.states.len())
+            .map(|id| id <= 1 || reachable.contains(&id))
+            .collect();
+        
+        let mut subgraph = self.clone();
+        subgraph.start = root;
+        subgraph.retain_states(&keep);
+        subgraph
+    }
+    
+    /// Drop every state whose `keep` entry is false and renumber the rest in order
+    ///
+    /// Edges into dropped states are removed; the start state must be kept.
+    fn retain_states(&mut self, keep: &[bool]) {
+        let mut remap = vec![usize::MAX; self.states.len()];
+        let mut next_id = 0;
+        for (old_id, &kept) in keep.iter().enumerate() {
+            if kept {
+                remap[old_id] = next_id;
+                next_id += 1;
+            }
+        }
+        self.renumber(&remap);
+    }
+    
+    /// Move each state to its `remap` entry, dropping states mapped to `usize::MAX`
+    ///
+    /// The kept states must map onto `0..kept` without gaps. Edges into dropped states
+    /// are removed; the start state must be kept.
+    fn renumber(&mut self, remap: &[StateId]) {
+        let dropped = |id: StateId| id < remap.len() && remap[id] == usize::MAX;
+        // Unpatched (usize::MAX) and dangling targets stay out of range
+        let map = |target: StateId| remap.get(target).copied().unwrap_or(usize::MAX);
+        
+        let mut renumbered: Vec<Option<State>> = vec![None; remap.iter().filter(|&&id| id != usize::MAX).count()];
+        let states = std::mem::take(&mut self.states);
+        for (old_id, mut state) in states.into_iter().enumerate() {
+            if dropped(old_id) {
                 continue;
             }
-            
-            if let State::Transitions { transitions: state_transitions } = &self.states[state_id] {
-                transitions.extend(state_transitions.clone());
+            match &mut state {
+                State::Transitions { transitions } => {
+                    transitions.retain(|t| !dropped(t.target));
+                    for transition in transitions.iter_mut() {
+                        transition.target = map(transition.target);
+                    }
+                },
+                State::Split { targets } => {
+                    targets.retain(|&target| !dropped(target));
+                    for target in targets.iter_mut() {
+                        *target = map(*target);
+                    }
+                },
+                State::Epsilon { next } | State::Assertion { next, .. } => *next = map(*next),
+                State::Match | State::Rejected => {},
             }
+            renumbered[remap[old_id]] = Some(state);
         }
+        self.states = renumbered.into_iter().map(|state| state.unwrap_or(State::Rejected)).collect();
         
-        transitions
+        self.start = map(self.start);
+        self.accepting = self.accepting.iter()
+            .filter(|&&id| !dropped(id))
+            .map(|&id| map(id))
+            .collect();
+        self.pattern_ids = self.pattern_ids.iter()
+            .filter(|(&id, _)| !dropped(id))
+            .map(|(&id, &pattern_id)| (map(id), pattern_id))
+            .collect();
+        self.capture_slots = self.capture_slots.iter()
+            .filter(|(&id, _)| !dropped(id))
+            .map(|(&id, &slot)| (map(id), slot))
+            .collect();
+        self.annotations = self.annotations.iter()
+            .filter(|(&id, _)| !dropped(id))
+            .map(|(&id, &annotation)| (map(id), annotation))
+            .collect();
+        self.quantifiers = self.quantifiers.iter()
+            .filter(|(&id, _)| !dropped(id))
+            .map(|(&id, &quantifier)| (map(id), quantifier))
+            .collect();
+        self.next_id = self.states.len();
     }
-}
-
-impl Default for NFA {
-    fn default() -> Self {
-        Self::new()
+    
+    /// Renumber the states in breadth-first order from the start, for comparing NFAs
+    ///
+    /// The reserved states 0 and 1 keep their IDs; every other state reachable from the
+    /// start is numbered from 2 in the order a breadth-first walk first reaches it,
+    /// following edges in priority order. Unreachable states are dropped. Two NFAs with
+    /// the same shape then compare equal however their states were created.
+    pub fn canonicalize(&self) -> NFA {
+        let mut remap = vec![usize::MAX; self.states.len()];
+        let mut next_id = 0;
+        let mut queue: VecDeque<StateId> = (0..self.states.len().min(2)).chain([self.start]).collect();
+        
+        while let Some(state_id) = queue.pop_front() {
+            if state_id >= self.states.len() || remap[state_id] != usize::MAX {
+                continue;
+            }
+            remap[state_id] = next_id;
+            next_id += 1;
+            queue.extend(self.successors(state_id).into_iter().map(|(target, _)| target));
+        }
+        
+        let mut canonical = self.clone();
+        canonical.renumber(&remap);
+        canonical
+    }
+    
+    /// Compute the FIRST set: the characters that can be consumed first by a match
+    ///
+    /// Unions the `current` predicates of every transition in the epsilon closure of the
+    /// start state, treating assertions as if they hold. When the start state can accept
+    /// without consuming anything every position can start a match, so the result is `Any`.
+    /// An NFA that can't consume anything from its start gets an empty `CharSet`.
+    pub fn first_set(&self) -> CharacterPredicate {
+        let closure = self.epsilon_closure(&std::iter::once(self.start).collect());
+        if self.is_accepting(&closure) {
+            return CharacterPredicate::Any;
+        }
+        
+        let mut chars = HashSet::new();
+        let mut ranges = Vec::new();
+        for &state_id in &closure {
+            for transition in self.transitions_of(state_id) {
+                match &transition.current {
+                    CharacterPredicate::Any => return CharacterPredicate::Any,
+                    CharacterPredicate::NotCharSet(excluded) => ranges.extend(subtract_chars(&[('\0', char::MAX)], excluded)),
+                    CharacterPredicate::EndOfInput => {},
+                    current => match predicate_chars(current) {
+                        Some(current_chars) => chars.extend(current_chars),
+                        None => ranges.extend(current.ranges().unwrap_or_default()),
+                    },
+                }
+            }
+        }
+        
+        // Keep small literal sets as sets; anything with ranges becomes ranges
+        if ranges.is_empty() {
+            return set_predicate(chars).unwrap_or_else(|| CharacterPredicate::CharSet(HashSet::new()));
+        }
+        ranges.extend(chars.into_iter().map(|ch| (ch, ch)));
+        match CharacterPredicate::from_ranges(ranges) {
+            Some(CharacterPredicate::Range('\0', char::MAX)) => CharacterPredicate::Any,
+            Some(predicate) => predicate,
+            None => CharacterPredicate::CharSet(HashSet::new()),
+        }
+    }
+    
+    /// Find literal strings that every match must contain
+    ///
+    /// A choke point is a state every path from the start to an accepting state passes
+    /// through. Choke points that consume a single `Char` are chained while the only
+    /// consuming state right after one is the next, and each maximal chain becomes one
+    /// string, in the order a match meets them. Assertions are treated as if they hold,
+    /// so a literal may be missed but is never reported when a match could avoid it.
+    pub fn required_literals(&self) -> Vec<String> {
+        let useful: HashSet<StateId> = self.states_reaching_accept()
+            .intersection(&self.reachable_from(self.start))
+            .copied()
+            .collect();
+        
+        // A state is a choke point when no accepting state is reachable without it
+        let reaches_accept_avoiding = |avoid: StateId| {
+            let mut visited = HashSet::new();
+            let mut stack = vec![self.start];
+            while let Some(state_id) = stack.pop() {
+                if state_id == avoid || !visited.insert(state_id) {
+                    continue;
+                }
+                if self.accepting.contains(&state_id) {
+                    return true;
+                }
+                stack.extend(self.successors(state_id).into_iter().map(|(target, _)| target));
+            }
+            false
+        };
+        let chokes: HashMap<StateId, (char, StateId)> = useful.iter()
+            .filter_map(|&state_id| match self.transitions_of(state_id) {
+                [TwoCharTransition { current: CharacterPredicate::Char(ch), target, .. }] => Some((state_id, (*ch, *target))),
+                _ => None,
+            })
+            .filter(|&(state_id, _)| !reaches_accept_avoiding(state_id))
+            .collect();
+        
+        // Link each choke point to the one that always consumes the next character
+        let mut next = HashMap::new();
+        for (&state_id, &(_, target)) in &chokes {
+            let closure = self.epsilon_closure(&std::iter::once(target).collect());
+            if self.is_accepting(&closure) {
+                continue;
+            }
+            let consuming: Vec<StateId> = closure.into_iter()
+                .filter(|id| useful.contains(id) && !self.transitions_of(*id).is_empty())
+                .collect();
+            if let [following] = consuming[..] {
+                if chokes.contains_key(&following) {
+                    next.insert(state_id, following);
+                }
+            }
+        }
+        
+        // Choke points all lie on every accepting path, so search order is match order
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = std::collections::VecDeque::from([self.start]);
+        while let Some(state_id) = queue.pop_front() {
+            if !visited.insert(state_id) {
+                continue;
+            }
+            order.push(state_id);
+            queue.extend(self.successors(state_id).into_iter().map(|(target, _)| target));
+        }
+        
+        let linked: HashSet<StateId> = next.values().copied().collect();
+        let mut literals = Vec::new();
+        for state_id in order {
+            if !chokes.contains_key(&state_id) || linked.contains(&state_id) {
+                continue;
+            }
+            let mut literal = String::new();
+            let mut current = Some(state_id);
+            let mut seen = HashSet::new();
+            while let Some(id) = current.filter(|&id| seen.insert(id)) {
+                literal.push(chokes[&id].0);
+                current = next.get(&id).copied();
+            }
+            literals.push(literal);
+        }
+        
+        literals
+    }
+    
+    /// Compute the minimum and maximum number of characters consumed on any accepting path
+    ///
+    /// The maximum is `None` when an accepting path can pass through a loop that consumes
+    /// input. An NFA that cannot accept anything reports `(0, Some(0))`.
+    pub fn match_length_bounds(&self) -> (usize, Option<usize>) {
+        // Only states on some path from start to an accepting state matter
+        let reachable = self.reachable_from(self.start);
+        let useful: HashSet<StateId> = self.states_reaching_accept()
+            .intersection(&reachable)
+            .copied()
+            .collect();
+        
+        if !useful.contains(&self.start) {
+            return (0, Some(0));
+        }
+        
+        // Shortest path: 0-1 BFS where consuming transitions cost one character
+        let mut min_dist = vec![usize::MAX; self.states.len()];
+        let mut deque = std::collections::VecDeque::new();
+        min_dist[self.start] = 0;
+        deque.push_back(self.start);
+        
+        while let Some(state_id) = deque.pop_front() {
+            for (target, consumes) in self.successors(state_id) {
+                if !useful.contains(&target) {
+                    continue;
+                }
+                let dist = min_dist[state_id] + consumes as usize;
+                if dist < min_dist[target] {
+                    min_dist[target] = dist;
+                    if consumes {
+                        deque.push_back(target);
+                    } else {
+                        deque.push_front(target);
+                    }
+                }
+            }
+        }
+        
+        let min = useful.iter()
+            .filter(|state_id| self.accepting.contains(state_id))
+            .map(|&state_id| min_dist[state_id])
+            .min()
+            .unwrap_or(0);
+        
+        // Longest path: Bellman-Ford style relaxation, where still improving after
+        // |useful| rounds means a loop that consumes input
+        let mut max_dist: Vec<Option<usize>> = vec![None; self.states.len()];
+        max_dist[self.start] = Some(0);
+        
+        for _ in 0..=useful.len() {
+            let mut changed = false;
+            for &state_id in &useful {
+                let Some(dist) = max_dist[state_id] else { continue };
+                for (target, consumes) in self.successors(state_id) {
+                    if !useful.contains(&target) {
+                        continue;
+                    }
+                    let candidate = dist + consumes as usize;
+                    if max_dist[target].is_none_or(|current| candidate > current) {
+                        max_dist[target] = Some(candidate);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                let max = useful.iter()
+                    .filter(|state_id| self.accepting.contains(state_id))
+                    .filter_map(|&state_id| max_dist[state_id])
+                    .max()
+                    .unwrap_or(0);
+                return (min, Some(max));
+            }
+        }
+        
+        (min, None)
     }
-}
\ No newline at end of file
+    
+    /// Get the successors of a state, flagging whether each edge consumes a character
+    fn successors(&self, state_id: StateId) -> Vec<(StateId, bool)> {
+        let successors = match self.states.get(state_id) {
+            Some(State::Transitions { transitions }) => {
+                transitions.iter().map(|t| (t.target, true)).collect()
+            },
+            Some(State::Epsilon { next }) | Some(State::Assertion { next, .. }) => vec![(*next, false)],
+            Some(State::Split { targets }) => targets.iter().map(|&t| (t, false)).collect(),
+            _ => Vec::new(),
+        };
+        
+        // Drop unpatched or dangling targets
+        successors.into_iter()
+            .filter(|&(target, _)| target < self.states.len())
+            .collect()
+    }
+    
+    /// Get all states reachable from a state, including itself
+    fn reachable_from(&self, root: StateId) -> HashSet<StateId> {
+        let mut visited = HashSet::new();
+        let mut stack = vec![root];
+        
+        while let Some(state_id) = stack.pop() {
+            if state_id >= self.states.len() || !visited.insert(state_id) {
+                continue;
+            }
+            stack.extend(self.successors(state_id).into_iter().map(|(target, _)| target));
+        }
+        
+        visited
+    }
+    
+    /// Get all states from which some accepting state is reachable
+    fn states_reaching_accept(&self) -> HashSet<StateId> {
+        let mut predecessors: Vec<Vec<StateId>> = vec![Vec::new(); self.states.len()];
+        for state_id in 0..self.states.len() {
+            for (target, _) in self.successors(state_id) {
+                predecessors[target].push(state_id);
+            }
+        }
+        
+        let mut visited = HashSet::new();
+        let mut stack: Vec<StateId> = self.accepting.iter().copied().collect();
+        
+        while let Some(state_id) = stack.pop() {
+            if state_id >= self.states.len() || !visited.insert(state_id) {
+                continue;
+            }
+            stack.extend(predecessors[state_id].iter().copied());
+        }
+        
+        visited
+    }
+    
+    /// Merge transitions of a state that share a lookahead and target into one
+    ///
+    /// `Char` and `CharSet` predicates going to the same target under the same lookahead
+    /// are unioned into a single `CharSet` (or kept as `Char` if only one character
+    /// remains). A transition is only folded into an earlier one when no transition in
+    /// between could fire on the same character, so the priority order of overlapping
+    /// transitions is unchanged.
+    pub fn merge_transitions(&mut self) {
+        for state in &mut self.states {
+            let State::Transitions { transitions } = state else { continue };
+            let mut merged: Vec<TwoCharTransition> = Vec::with_capacity(transitions.len());
+            
+            for transition in transitions.drain(..) {
+                let Some(chars) = predicate_chars(&transition.current) else {
+                    merged.push(transition);
+                    continue;
+                };
+                
+                // Look back for a compatible transition, stopping at one that overlaps
+                let mut into = None;
+                for (index, earlier) in merged.iter().enumerate().rev() {
+                    if earlier.target == transition.target
+                        && earlier.lookahead == transition.lookahead
+                        && predicate_chars(&earlier.current).is_some()
+                    {
+                        into = Some(index);
+                        break;
+                    }
+                    if earlier.current.intersects(&transition.current) {
+                        break;
+                    }
+                }
+                
+                match into {
+                    Some(index) => {
+                        let mut union = predicate_chars(&merged[index].current).unwrap_or_default();
+                        union.extend(chars);
+                        if let Some(current) = set_predicate(union) {
+                            merged[index].current = current;
+                        }
+                    },
+                    None => merged.push(transition),
+                }
+            }
+            
+            *transitions = merged;
+        }
+    }
+    
+    /// Build an NFA accepting exactly the inputs accepted by both `self` and `other`
+    ///
+    /// Uses the product construction: each product state pairs a state of either NFA,
+    /// epsilon moves and assertions advance one side at a time, and two consuming states
+    /// step together on the intersection of their current and lookahead predicates. A
+    /// product state accepts only when both of its components do. Pattern IDs and capture
+    /// groups are not carried over.
+    ///
+    /// Returns `TooComplex` if the product grows past `MAX_PRODUCT_STATES` states.
+    pub fn intersect(&self, other: &NFA) -> CompileResult<NFA> {
+        let mut product = NFA::new();
+        let mut ids: HashMap<(StateId, StateId), StateId> = HashMap::new();
+        let mut worklist = Vec::new();
+        
+        // Allocate a placeholder for a pair the first time it's seen
+        let mut id_of = |pair: (StateId, StateId), product: &mut NFA, worklist: &mut Vec<_>| {
+            if let Some(&id) = ids.get(&pair) {
+                return Ok(id);
+            }
+            if ids.len() >= MAX_PRODUCT_STATES {
+                return Err(CompileError::TooComplex);
+            }
+            let id = product.add_state(State::Rejected);
+            ids.insert(pair, id);
+            worklist.push((pair, id));
+            Ok(id)
+        };
+        
+        product.start = id_of((self.start, other.start), &mut product, &mut worklist)?;
+        
+        while let Some(((a, b), id)) = worklist.pop() {
+            let state = match (self.states.get(a), other.states.get(b)) {
+                (Some(State::Epsilon { next }), _) => State::Epsilon {
+                    next: id_of((*next, b), &mut product, &mut worklist)?,
+                },
+                (Some(State::Split { targets }), _) => State::Split {
+                    targets: targets.iter()
+                        .map(|&target| id_of((target, b), &mut product, &mut worklist))
+                        .collect::<CompileResult<_>>()?,
+                },
+                (Some(State::Assertion { kind, next }), _) => State::Assertion {
+                    kind: kind.clone(),
+                    next: id_of((*next, b), &mut product, &mut worklist)?,
+                },
+                (_, Some(State::Epsilon { next })) => State::Epsilon {
+                    next: id_of((a, *next), &mut product, &mut worklist)?,
+                },
+                (_, Some(State::Split { targets })) => State::Split {
+                    targets: targets.iter()
+                        .map(|&target| id_of((a, target), &mut product, &mut worklist))
+                        .collect::<CompileResult<_>>()?,
+                },
+                (_, Some(State::Assertion { kind, next })) => State::Assertion {
+                    kind: kind.clone(),
+                    next: id_of((a, *next), &mut product, &mut worklist)?,
+                },
+                (Some(_), Some(_)) => {
+                    let mut transitions = Vec::new();
+                    for first in self.transitions_of(a) {
+                        for second in other.transitions_of(b) {
+                            let Some(current) = intersect_predicates(&first.current, &second.current) else {
+                                continue;
+                            };
+                            let lookahead = match (&first.lookahead, &second.lookahead) {
+                                (None, lookahead) | (lookahead, None) => lookahead.clone(),
+                                (Some(x), Some(y)) => match intersect_lookaheads(x, y)? {
+                                    Some(lookahead) => Some(lookahead),
+                                    None => continue,
+                                },
+                            };
+                            let target = id_of((first.target, second.target), &mut product, &mut worklist)?;
+                            transitions.push(TwoCharTransition::predicate(current, lookahead, target));
+                        }
+                    }
+                    
+                    let accepting = self.accepting.contains(&a) && other.accepting.contains(&b);
+                    if accepting {
+                        product.accepting.insert(id);
+                    }
+                    match (transitions.is_empty(), accepting) {
+                        (false, _) => State::Transitions { transitions },
+                        (true, true) => State::Match,
+                        // A dead end that routes nowhere
+                        (true, false) => State::Split { targets: Vec::new() },
+                    }
+                },
+                // Unpatched or out-of-range targets never match
+                _ => State::Split { targets: Vec::new() },
+            };
+            product.states[id] = state;
+        }
+        
+        Ok(product)
+    }
+    
+    /// Find the most states that are live at once over all inputs of up to `max_len`
+    /// characters
+    ///
+    /// Live states are the ones that hold a thread between characters: states with
+    /// transitions and accepting states. The sets reachable after each prefix are explored
+    /// breadth-first, one character per alphabet class, with each lookahead committing the
+    /// next character read. Assertions are taken to hold, so the count is an upper bound
+    /// for NFAs with assertions. This bounds the width of a one-hot state register.
+    pub fn max_live_states(&self, max_len: usize) -> usize {
+        let live = |targets: HashSet<StateId>| -> BTreeSet<StateId> {
+            self.epsilon_closure(&targets)
+                .into_iter()
+                .filter(|&id| matches!(self.states.get(id), Some(State::Transitions { .. })) || self.accepting.contains(&id))
+                .collect()
+        };
+        let chars: Vec<char> = self.alphabet_classes().into_iter().map(|(start, _)| start).collect();
+        
+        let initial = live(std::iter::once(self.start).collect());
+        let mut max = initial.len();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([(initial, None, 0)]);
+        
+        while let Some((states, required, depth)) = queue.pop_front() {
+            if depth == max_len {
+                continue;
+            }
+            let currents: Vec<usize> = match required {
+                Some(index) => vec![index],
+                None => (0..chars.len()).collect(),
+            };
+            for current in currents {
+                for lookahead in (0..chars.len()).map(Some).chain([None]) {
+                    let targets = self.get_two_char_transitions(&states.iter().copied().collect())
+                        .into_iter()
+                        .filter(|transition| transition.matches(chars[current], lookahead.map(|index| chars[index])))
+                        .map(|transition| transition.target)
+                        .collect();
+                    let next = live(targets);
+                    max = max.max(next.len());
+                    if lookahead.is_some() && !next.is_empty() && visited.insert((next.clone(), lookahead)) {
+                        queue.push_back((next, lookahead, depth + 1));
+                    }
+                }
+            }
+        }
+        
+        max
+    }
+    
+    /// Build an equivalent NFA without epsilon or split states
+    ///
+    /// Each state that can be entered by consuming a character (and the start) becomes
+    /// one state holding every transition in its epsilon closure, in priority order, each
+    /// going straight to the state for its target. It accepts if its closure contains an
+    /// accepting state, whose pattern ID and annotation it takes. The result accepts the
+    /// same whole inputs, but the choice between accepting and consuming more is no longer
+    /// ordered, so leftmost-first spans can differ. Capture slots and loop quantifiers are
+    /// not carried over.
+    ///
+    /// Assertions depend on the position, so closures stop at them and they are kept; a
+    /// state whose closure reaches one becomes a split between its own transitions and the
+    /// assertions. Only NFAs without assertions come out entirely epsilon-free.
+    pub fn remove_epsilons(&self) -> NFA {
+        let mut result = NFA::new();
+        result.group_names = self.group_names.clone();
+        let mut ids: HashMap<StateId, StateId> = HashMap::new();
+        let mut worklist = Vec::new();
+        
+        // Allocate a placeholder for a state the first time it's entered
+        let mut id_of = |state_id: StateId, result: &mut NFA, worklist: &mut Vec<(StateId, StateId)>| {
+            if state_id >= self.states.len() {
+                return usize::MAX;
+            }
+            *ids.entry(state_id).or_insert_with(|| {
+                let id = result.add_state(State::Rejected);
+                worklist.push((state_id, id));
+                id
+            })
+        };
+        
+        result.start = id_of(self.start, &mut result, &mut worklist);
+        while let Some((state_id, id)) = worklist.pop() {
+            let closure = self.ordered_closure(state_id);
+            
+            let mut transitions = Vec::new();
+            let mut assertions = Vec::new();
+            for &member in &closure {
+                match &self.states[member] {
+                    State::Transitions { transitions: consuming } => {
+                        for transition in consuming {
+                            let target = id_of(transition.target, &mut result, &mut worklist);
+                            transitions.push(TwoCharTransition::predicate(transition.current.clone(), transition.lookahead.clone(), target));
+                        }
+                    },
+                    State::Assertion { kind, next } => {
+                        let next = id_of(*next, &mut result, &mut worklist);
+                        assertions.push(result.assertion(kind.clone(), next));
+                    },
+                    _ => {},
+                }
+            }
+            
+            // The first accepting state decides the pattern, as it would when matching
+            let accept = closure.iter().copied().find(|member| self.accepting.contains(member));
+            let own = match (transitions.is_empty(), accept) {
+                (true, Some(_)) => State::Match,
+                (true, None) => State::Rejected,
+                (false, _) => State::Transitions { transitions },
+            };
+            let own_id = if assertions.is_empty() {
+                result.states[id] = own;
+                id
+            } else {
+                let own_id = result.add_state(own);
+                assertions.insert(0, own_id);
+                result.states[id] = State::Split { targets: assertions };
+                own_id
+            };
+            
+            if let Some(accept) = accept {
+                result.accepting.insert(own_id);
+                if let Some(&pattern_id) = self.pattern_ids.get(&accept) {
+                    result.pattern_ids.insert(own_id, pattern_id);
+                }
+                if let Some(&annotation) = self.annotations.get(&accept) {
+                    result.annotations.insert(own_id, annotation);
+                }
+            }
+        }
+        
+        result
+    }
+    
+    /// List the epsilon closure of a state in priority order, without passing assertions
+    fn ordered_closure(&self, state_id: StateId) -> Vec<StateId> {
+        let mut seen = HashSet::new();
+        let mut order = Vec::new();
+        let mut stack = vec![state_id];
+        
+        while let Some(state_id) = stack.pop() {
+            if state_id >= self.states.len() || !seen.insert(state_id) {
+                continue;
+            }
+            order.push(state_id);
+            match &self.states[state_id] {
+                State::Epsilon { next } => stack.push(*next),
+                // Pushed in reverse so the first target is visited first
+                State::Split { targets } => stack.extend(targets.iter().rev().copied()),
+                _ => {},
+            }
+        }
+        
+        order
+    }
+    
+    /// Build an equivalent deterministic automaton by subset construction
+    ///
+    /// Lookaheads are resolved along the way: a transition with a lookahead moves to its
+    /// target carrying the lookahead as a pending check on the next character, or on the
+    /// end of input for lookaheads that accept it. The result therefore has no lookaheads,
+    /// and at most one transition from each state accepts any given character. Pattern IDs
+    /// and capture groups are not carried over.
+    ///
+    /// Returns `UnsupportedFeature` for NFAs with assertions, whose outcome depends on the
+    /// position rather than on the characters read, and `TooComplex` if the result grows
+    /// past `MAX_DFA_STATES` states.
+    pub fn determinize(&self) -> CompileResult<NFA> {
+        if self.states.iter().any(|state| matches!(state, State::Assertion { .. })) {
+            return Err(CompileError::UnsupportedFeature("determinizing an NFA with assertions".to_string()));
+        }
+        
+        let classes = self.alphabet_classes();
+        let mut lookaheads: Vec<CharacterPredicate> = Vec::new();
+        let mut dfa = NFA::new();
+        let mut ids: HashMap<BTreeSet<(StateId, Option<usize>)>, StateId> = HashMap::new();
+        let mut worklist = Vec::new();
+        
+        // Allocate a placeholder for a subset the first time it's seen
+        let mut id_of = |subset: BTreeSet<(StateId, Option<usize>)>, dfa: &mut NFA, worklist: &mut Vec<_>| {
+            if let Some(&id) = ids.get(&subset) {
+                return Ok(id);
+            }
+            if ids.len() >= MAX_DFA_STATES {
+                return Err(CompileError::TooComplex);
+            }
+            let id = dfa.add_state(State::Rejected);
+            ids.insert(subset.clone(), id);
+            worklist.push((subset, id));
+            Ok(id)
+        };
+        
+        let start = self.subset_closure(std::iter::once((self.start, None)));
+        dfa.start = id_of(start, &mut dfa, &mut worklist)?;
+        
+        while let Some((subset, id)) = worklist.pop() {
+            let accepting = subset.iter().any(|&(state_id, pending)| {
+                self.accepting.contains(&state_id) && pending.is_none_or(|index| lookahead_accepts_end(&lookaheads[index]))
+            });
+            if accepting {
+                dfa.accepting.insert(id);
+            }
+            
+            // Characters of a class all behave alike, so its first one stands for it
+            let mut targets: BTreeMap<StateId, Vec<(char, char)>> = BTreeMap::new();
+            for &(start, end) in &classes {
+                let mut next = Vec::new();
+                for &(state_id, pending) in &subset {
+                    if pending.is_some_and(|index| !lookaheads[index].matches(start)) {
+                        continue;
+                    }
+                    for transition in self.transitions_of(state_id).iter().filter(|t| t.current.matches(start)) {
+                        let pending = transition.lookahead.as_ref().map(|lookahead| {
+                            lookaheads.iter().position(|known| known == lookahead).unwrap_or_else(|| {
+                                lookaheads.push(lookahead.clone());
+                                lookaheads.len() - 1
+                            })
+                        });
+                        next.push((transition.target, pending));
+                    }
+                }
+                if next.is_empty() {
+                    continue;
+                }
+                let target = id_of(self.subset_closure(next), &mut dfa, &mut worklist)?;
+                targets.entry(target).or_default().push((start, end));
+            }
+            
+            let transitions = targets.into_iter()
+                .filter_map(|(target, ranges)| {
+                    let current = match CharacterPredicate::from_ranges(ranges)? {
+                        CharacterPredicate::Range('\0', char::MAX) => CharacterPredicate::Any,
+                        current => current,
+                    };
+                    Some(TwoCharTransition::predicate(current, None, target))
+                })
+                .collect();
+            dfa.states[id] = State::Transitions { transitions };
+        }
+        
+        Ok(dfa)
+    }
+    
+    /// Build an automaton accepting exactly the inputs this one rejects
+    ///
+    /// The alphabet is every Unicode scalar value, and an input is accepted when the whole
+    /// of it matches, as `Matcher::is_match` checks. The NFA is determinized first, so the
+    /// same restrictions as `determinize` apply. The result is then completed: every
+    /// character a state has no transition for leads to a dead state that loops on any
+    /// character. Finally accepting and non-accepting states swap, which makes the dead
+    /// state accepting.
+    pub fn complement(&self) -> CompileResult<NFA> {
+        let mut dfa = self.determinize()?;
+        let dead = dfa.add_state(State::Rejected);
+        dfa.states[dead] = State::Transitions {
+            transitions: vec![TwoCharTransition::predicate(CharacterPredicate::Any, None, dead)],
+        };
+        
+        for state in &mut dfa.states {
+            if let State::Transitions { transitions } = state {
+                let mut covered: Vec<(char, char)> = transitions.iter()
+                    .flat_map(|transition| predicate_ranges(&transition.current))
+                    .collect();
+                covered.sort_unstable();
+                if let Some(missing) = CharacterPredicate::from_ranges(complement_ranges(&covered)) {
+                    transitions.push(TwoCharTransition::predicate(missing, None, dead));
+                }
+            }
+        }
+        
+        // State 0 stays the reserved (unreachable) match state
+        let flipped: BTreeSet<StateId> = (2..dfa.states.len())
+            .filter(|id| !dfa.accepting.contains(id))
+            .chain(std::iter::once(0))
+            .collect();
+        dfa.accepting = flipped;
+        
+        Ok(dfa)
+    }
+    
+    /// Close a set of (state, pending lookahead) pairs over epsilon edges, keeping only the
+    /// states that consume or accept
+    fn subset_closure(&self, pairs: impl IntoIterator<Item = (StateId, Option<usize>)>) -> BTreeSet<(StateId, Option<usize>)> {
+        let mut subset = BTreeSet::new();
+        for (state_id, pending) in pairs {
+            for id in self.epsilon_closure(&std::iter::once(state_id).collect()) {
+                if matches!(self.states.get(id), Some(State::Transitions { .. })) || self.accepting.contains(&id) {
+                    subset.insert((id, pending));
+                }
+            }
+        }
+        subset
+    }
+    
+    /// Split every character into ranges that no predicate of the NFA tells apart
+    fn alphabet_classes(&self) -> Vec<(char, char)> {
+        let mut boundaries = BTreeSet::from(['\0']);
+        for state in &self.states {
+            let State::Transitions { transitions } = state else {
+                continue;
+            };
+            for transition in transitions {
+                for predicate in std::iter::once(&transition.current).chain(&transition.lookahead) {
+                    for (start, end) in predicate_ranges(predicate) {
+                        boundaries.insert(start);
+                        boundaries.extend(next_char(end));
+                    }
+                }
+            }
+        }
+        
+        let boundaries: Vec<char> = boundaries.into_iter().collect();
+        boundaries.iter()
+            .enumerate()
+            .filter_map(|(i, &start)| match boundaries.get(i + 1) {
+                Some(&next) => prev_char(next).map(|end| (start, end)),
+                None => Some((start, char::MAX)),
+            })
+            .collect()
+    }
+    
+    /// Freeze the NFA into an immutable form laid out for matching
+    pub fn freeze(self) -> crate::frozen::FrozenNfa {
+        crate::frozen::FrozenNfa::new(&self)
+    }
+    
+    /// Check whether every step of the NFA has at most one way to consume a character
+    ///
+    /// For each state, the consuming transitions reachable through its epsilon closure are
+    /// compared pairwise. Two transitions to different targets whose `(current, lookahead)`
+    /// predicates can both fire on the same input make the NFA nondeterministic, which
+    /// means hardware has to track a set of live states rather than a single one.
+    pub fn is_deterministic(&self) -> bool {
+        for state_id in 0..self.states.len() {
+            let mut start = HashSet::new();
+            start.insert(state_id);
+            let closure = self.epsilon_closure(&start);
+            
+            let transitions = self.get_two_char_transitions(&closure);
+            for (i, first) in transitions.iter().enumerate() {
+                for second in &transitions[i + 1..] {
+                    if first.target != second.target
+                        && first.current.intersects(&second.current)
+                        && lookaheads_overlap(first.lookahead.as_ref(), second.lookahead.as_ref())
+                    {
+                        return false;
+                    }
+                }
+            }
+        }
+        
+        true
+    }
+    
+    /// Get all possible two-character transitions from a set of states
+    pub fn get_two_char_transitions(&self, states: &HashSet<StateId>) -> Vec<TwoCharTransition> {
+        let mut transitions = Vec::new();
+        
+        for &state_id in states {
+            transitions.extend_from_slice(self.transitions_of(state_id));
+        }
+        
+        transitions
+    }
+    
+    /// Render the NFA as canonical text for golden-file tests
+    ///
+    /// States are listed in ID order, one per line, with each state's transitions on
+    /// indented lines below it sorted by predicate text and then target, and set members
+    /// sorted, so the output only changes when the automaton does. Because of the sort,
+    /// the listed order of a state's transitions doesn't show their priority; split
+    /// targets keep their order. Unpatched targets print as `?`.
+    pub fn snapshot(&self) -> String {
+        let target = |id: StateId| if id == usize::MAX { "?".to_string() } else { id.to_string() };
+        
+        let mut accepting: Vec<StateId> = self.accepting.iter().copied().collect();
+        accepting.sort_unstable();
+        let accepting: Vec<String> = accepting.into_iter()
+            .map(|id| match self.pattern_id(id) {
+                Some(pattern_id) if pattern_id != 0 => format!("{} (pattern {})", id, pattern_id),
+                _ => id.to_string(),
+            })
+            .collect();
+        let mut out = format!("start: {}\naccepting: {}\n", target(self.start), accepting.join(", "));
+        
+        for (id, state) in self.states.iter().enumerate() {
+            let text = match state {
+                State::Transitions { transitions } => {
+                    let mut lines: Vec<(String, StateId)> = transitions.iter()
+                        .map(|t| {
+                            let mut text = predicate_snapshot(&t.current);
+                            if let Some(lookahead) = &t.lookahead {
+                                text.push_str(" / ");
+                                text.push_str(&predicate_snapshot(lookahead));
+                            }
+                            (text, t.target)
+                        })
+                        .collect();
+                    lines.sort();
+                    let mut text = "transitions".to_string();
+                    for (predicate, next) in lines {
+                        text.push_str(&format!("\n    {} -> {}", predicate, target(next)));
+                    }
+                    text
+                },
+                State::Epsilon { next } => format!("epsilon -> {}", target(*next)),
+                State::Split { targets } => {
+                    let targets: Vec<String> = targets.iter().map(|&next| target(next)).collect();
+                    format!("split -> [{}]", targets.join(", "))
+                },
+                State::Match => "match".to_string(),
+                State::Rejected => "rejected".to_string(),
+                State::Assertion { kind, next } => format!("assert {} -> {}", kind, target(*next)),
+            };
+            out.push_str(&format!("{}: {}", id, text));
+            if let Some(slot) = self.capture_slots.get(&id) {
+                out.push_str(&format!(" (slot {})", slot));
+            }
+            out.push('\n');
+        }
+        
+        out
+    }
+    
+    /// List every edge of the NFA as a flat table, for tools outside this crate
+    ///
+    /// Rows come in state order, and a state's rows keep the priority order of its
+    /// transitions or split targets. Match and rejected states have no rows. Capture
+    /// slots, pattern IDs and annotations aren't part of the table, and assertions have
+    /// no row form, so an NFA with assertions is `UnsupportedFeature`.
+    pub fn transition_table(&self) -> CompileResult<Vec<TransitionRow>> {
+        let mut rows = Vec::new();
+        let epsilon = |from: StateId, to: StateId| TransitionRow { from, current: None, lookahead: None, to };
+        
+        for (from, state) in self.states.iter().enumerate() {
+            match state {
+                State::Transitions { transitions } => {
+                    rows.extend(transitions.iter().map(|transition| TransitionRow {
+                        from,
+                        current: Some(transition.current.clone()),
+                        lookahead: transition.lookahead.clone(),
+                        to: transition.target,
+                    }));
+                },
+                State::Epsilon { next } => rows.push(epsilon(from, *next)),
+                State::Split { targets } => rows.extend(targets.iter().map(|&to| epsilon(from, to))),
+                State::Match | State::Rejected => {},
+                State::Assertion { kind, .. } => {
+                    return Err(CompileError::UnsupportedFeature(format!("assertion {} in a transition table", kind)));
+                },
+            }
+        }
+        
+        Ok(rows)
+    }
+    
+    /// Build an NFA from a transition table, the inverse of `transition_table`
+    ///
+    /// State IDs are kept as given, and the NFA has one state per ID up to the largest
+    /// one mentioned. A state with one epsilon row becomes an epsilon, one with several a
+    /// split in row order, and one with consuming rows a transitions state. A state
+    /// without rows matches if it is accepting and is rejected otherwise.
+    ///
+    /// Returns `CompileError::Internal` when a state mixes epsilon and consuming rows, an
+    /// epsilon row has a lookahead, an ID is unpatched (`usize::MAX`), or a predicate is
+    /// malformed: an empty or reversed range, unsorted or overlapping ranges, or end of
+    /// input as the consumed character. More than `MAX_TABLE_STATES` states is `TooComplex`.
+    pub fn from_transition_table(rows: &[TransitionRow], start: StateId, accepting: &[StateId]) -> CompileResult<NFA> {
+        let ids = rows.iter().flat_map(|row| [row.from, row.to]).chain([start]).chain(accepting.iter().copied());
+        let mut state_count = 0;
+        for id in ids {
+            if id == usize::MAX {
+                return Err(CompileError::Internal("transition table has an unpatched state ID".to_string()));
+            }
+            state_count = state_count.max(id + 1);
+        }
+        if state_count > MAX_TABLE_STATES {
+            return Err(CompileError::TooComplex);
+        }
+        
+        let accepting: BTreeSet<StateId> = accepting.iter().copied().collect();
+        let mut states: Vec<State> = (0..state_count)
+            .map(|id| if accepting.contains(&id) { State::Match } else { State::Rejected })
+            .collect();
+        
+        for (index, row) in rows.iter().enumerate() {
+            let malformed = |what: &str| CompileError::Internal(format!("row {}: {}", index, what));
+            for predicate in row.current.iter().chain(&row.lookahead) {
+                if !is_well_formed(predicate) {
+                    return Err(malformed(&format!("malformed predicate {:?}", predicate)));
+                }
+            }
+            
+            let state = &mut states[row.from];
+            match (&row.current, &mut *state) {
+                (Some(CharacterPredicate::EndOfInput), _) => return Err(malformed("end of input can't be consumed")),
+                (Some(current), State::Transitions { transitions }) => {
+                    transitions.push(TwoCharTransition::predicate(current.clone(), row.lookahead.clone(), row.to));
+                },
+                (Some(current), State::Match | State::Rejected) => {
+                    *state = State::Transitions {
+                        transitions: vec![TwoCharTransition::predicate(current.clone(), row.lookahead.clone(), row.to)],
+                    };
+                },
+                (None, _) if row.lookahead.is_some() => return Err(malformed("epsilon row with a lookahead")),
+                (None, State::Epsilon { next }) => *state = State::Split { targets: vec![*next, row.to] },
+                (None, State::Split { targets }) => targets.push(row.to),
+                (None, State::Match | State::Rejected) => *state = State::Epsilon { next: row.to },
+                _ => return Err(malformed(&format!("state {} mixes epsilon and consuming rows", row.from))),
+            }
+        }
+        
+        Ok(NFA::from_states(states, start, accepting))
+    }
+    
+    /// Advance a set of states over one character, for driving the NFA by hand
+    ///
+    /// Fires every transition of `states` that accepts `current` with `lookahead` as
+    /// the following character (`None` at end of input) and returns the epsilon closure
+    /// of their targets. Like `epsilon_closure`, assertions are taken to hold, so start
+    /// from the closure of `start` and check assertions yourself if the NFA has any.
+    pub fn step(&self, states: &HashSet<StateId>, current: char, lookahead: Option<char>) -> HashSet<StateId> {
+        let targets = self.get_two_char_transitions(states)
+            .into_iter()
+            .filter(|transition| transition.matches(current, lookahead))
+            .map(|transition| transition.target)
+            .collect();
+        
+        self.epsilon_closure(&targets)
+    }
+    
+    /// Borrow the two-character transitions of a single state
+    ///
+    /// Returns an empty slice for states that don't consume input (epsilon, split, match,
+    /// rejected) and for out-of-range IDs.
+    pub fn transitions_of(&self, state: StateId) -> &[TwoCharTransition] {
+        match self.states.get(state) {
+            Some(State::Transitions { transitions }) => transitions,
+            _ => &[],
+        }
+    }
+}
+
+/// Largest number of product states `NFA::intersect` will build
+pub const MAX_PRODUCT_STATES: usize = 100_000;
+
+/// Largest number of states `NFA::determinize` will build
+pub const MAX_DFA_STATES: usize = 100_000;
+
+/// Largest number of states `NFA::from_transition_table` will build
+pub const MAX_TABLE_STATES: usize = 1_000_000;
+
+/// Check that a predicate's ranges are non-empty, in order and don't overlap
+fn is_well_formed(predicate: &CharacterPredicate) -> bool {
+    match predicate {
+        CharacterPredicate::Range(start, end) => start <= end,
+        CharacterPredicate::Ranges(ranges) => {
+            !ranges.is_empty()
+                && ranges.iter().all(|(start, end)| start <= end)
+                && ranges.windows(2).all(|pair| pair[0].1 < pair[1].0)
+        },
+        _ => true,
+    }
+}
+
+/// Get the sorted ranges of characters a predicate matches
+fn predicate_ranges(predicate: &CharacterPredicate) -> Vec<(char, char)> {
+    match predicate {
+        CharacterPredicate::Any => vec![('\0', char::MAX)],
+        CharacterPredicate::NotCharSet(excluded) => subtract_chars(&[('\0', char::MAX)], excluded),
+        CharacterPredicate::EndOfInput => Vec::new(),
+        predicate => match predicate_chars(predicate) {
+            Some(chars) => {
+                let mut chars: Vec<char> = chars.into_iter().collect();
+                chars.sort_unstable();
+                chars.into_iter().map(|ch| (ch, ch)).collect()
+            },
+            None => predicate.ranges().unwrap_or_default(),
+        },
+    }
+}
+
+/// Check whether a lookahead is satisfied at the end of input
+fn lookahead_accepts_end(lookahead: &CharacterPredicate) -> bool {
+    matches!(lookahead, CharacterPredicate::NotCharSet(_) | CharacterPredicate::EndOfInput)
+}
+
+/// Render a predicate for `NFA::snapshot`, with set members in order
+fn predicate_snapshot(predicate: &CharacterPredicate) -> String {
+    let escaped = |ch: char| ch.escape_debug().to_string();
+    let sorted = |set: &HashSet<char>| {
+        let mut chars: Vec<char> = set.iter().copied().collect();
+        chars.sort_unstable();
+        chars.into_iter().map(escaped).collect::<String>()
+    };
+    let ranges = |ranges: &[(char, char)]| {
+        ranges.iter()
+            .map(|&(start, end)| if start == end { escaped(start) } else { format!("{}-{}", escaped(start), escaped(end)) })
+            .collect::<String>()
+    };
+    
+    match predicate {
+        CharacterPredicate::Any => "ANY".to_string(),
+        CharacterPredicate::Char(ch) => format!("'{}'", escaped(*ch)),
+        CharacterPredicate::CharSet(set) => format!("[{}]", sorted(set)),
+        CharacterPredicate::NotCharSet(set) => format!("[^{}]", sorted(set)),
+        CharacterPredicate::EndOfInput => "EOI".to_string(),
+        CharacterPredicate::Range(start, end) => format!("[{}]", ranges(&[(*start, *end)])),
+        CharacterPredicate::Ranges(list) => format!("[{}]", ranges(list)),
+    }
+}
+
+/// Get the characters of a finite predicate (`Char` or `CharSet`)
+fn predicate_chars(predicate: &CharacterPredicate) -> Option<HashSet<char>> {
+    match predicate {
+        CharacterPredicate::Char(ch) => Some(std::iter::once(*ch).collect()),
+        CharacterPredicate::CharSet(set) => Some(set.clone()),
+        _ => None,
+    }
+}
+
+/// Build the predicate matching exactly one of a set of characters, or `None` if empty
+fn set_predicate(chars: HashSet<char>) -> Option<CharacterPredicate> {
+    match chars.len() {
+        0 => None,
+        1 => chars.into_iter().next().map(CharacterPredicate::Char),
+        _ => Some(CharacterPredicate::CharSet(chars)),
+    }
+}
+
+/// Build the predicate for characters satisfying both, or `None` if there are none
+fn intersect_predicates(a: &CharacterPredicate, b: &CharacterPredicate) -> Option<CharacterPredicate> {
+    use CharacterPredicate::*;
+    
+    match (a, b) {
+        (EndOfInput, _) | (_, EndOfInput) => None,
+        (Char(ch), other) | (other, Char(ch)) => other.matches(*ch).then_some(Char(*ch)),
+        (CharSet(set), other) | (other, CharSet(set)) => {
+            set_predicate(set.iter().copied().filter(|&ch| other.matches(ch)).collect())
+        },
+        (Any, other) | (other, Any) => Some(other.clone()),
+        (NotCharSet(first), NotCharSet(second)) => Some(NotCharSet(first.union(second).copied().collect())),
+        (NotCharSet(excluded), other) | (other, NotCharSet(excluded)) => {
+            CharacterPredicate::from_ranges(subtract_chars(&other.ranges()?, excluded))
+        },
+        (first, second) => CharacterPredicate::from_ranges(intersect_ranges(&first.ranges()?, &second.ranges()?)),
+    }
+}
+
+/// Intersect two sorted lists of non-overlapping inclusive ranges
+fn intersect_ranges(first: &[(char, char)], second: &[(char, char)]) -> Vec<(char, char)> {
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < first.len() && j < second.len() {
+        let start = first[i].0.max(second[j].0);
+        let end = first[i].1.min(second[j].1);
+        if start <= end {
+            result.push((start, end));
+        }
+        if first[i].1 < second[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+/// Get the ranges of every character not in a sorted list of non-overlapping ranges
+pub(crate) fn complement_ranges(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut result = Vec::new();
+    let mut from = Some('\0');
+    for &(start, end) in ranges {
+        if let (Some(low), Some(high)) = (from, prev_char(start)) {
+            if low <= high {
+                result.push((low, high));
+            }
+        }
+        from = next_char(end);
+    }
+    if let Some(low) = from {
+        result.push((low, char::MAX));
+    }
+    result
+}
+
+/// Remove a set of characters from a list of inclusive ranges
+fn subtract_chars(ranges: &[(char, char)], excluded: &HashSet<char>) -> Vec<(char, char)> {
+    let mut excluded: Vec<char> = excluded.iter().copied().collect();
+    excluded.sort_unstable();
+    
+    let mut result = Vec::new();
+    for &(start, end) in ranges {
+        let mut from = Some(start);
+        for &ch in excluded.iter().filter(|&&ch| start <= ch && ch <= end) {
+            if let (Some(low), Some(high)) = (from, prev_char(ch)) {
+                if low <= high {
+                    result.push((low, high));
+                }
+            }
+            from = next_char(ch);
+        }
+        if let Some(low) = from.filter(|&low| low <= end) {
+            result.push((low, end));
+        }
+    }
+    result
+}
+
+/// Build the lookahead satisfied by exactly the lookaheads (characters or end of input)
+/// satisfying both, or `None` if nothing does
+///
+/// Fails when the result would need to reject end of input while accepting every
+/// character outside a set, which no single predicate can express.
+pub(crate) fn intersect_lookaheads(a: &CharacterPredicate, b: &CharacterPredicate) -> CompileResult<Option<CharacterPredicate>> {
+    let accepts_end = |p: &CharacterPredicate| matches!(p, CharacterPredicate::NotCharSet(_) | CharacterPredicate::EndOfInput);
+    
+    match (a, b) {
+        (CharacterPredicate::EndOfInput, other) | (other, CharacterPredicate::EndOfInput) => {
+            Ok(accepts_end(other).then_some(CharacterPredicate::EndOfInput))
+        },
+        _ => match intersect_predicates(a, b) {
+            Some(CharacterPredicate::NotCharSet(_)) if !(accepts_end(a) && accepts_end(b)) => Err(
+                CompileError::UnsupportedFeature("intersecting a negated lookahead with one that needs a character".to_string()),
+            ),
+            predicate => Ok(predicate),
+        },
+    }
+}
+
+/// Check whether some lookahead (a character or end of input) satisfies both constraints
+fn lookaheads_overlap(a: Option<&CharacterPredicate>, b: Option<&CharacterPredicate>) -> bool {
+    let accepts_end = |p: &CharacterPredicate| matches!(p, CharacterPredicate::NotCharSet(_) | CharacterPredicate::EndOfInput);
+    
+    match (a, b) {
+        (None, _) | (_, None) => true,
+        (Some(a), Some(b)) => a.intersects(b) || (accepts_end(a) && accepts_end(b)),
+    }
+}
+
+impl Default for NFA {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use regex_syntax::ParserBuilder;
+    
+    fn compile(pattern: &str) -> NFA {
+        let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+        Compiler::new().compile(&hir).unwrap()
+    }
+    
+    #[test]
+    fn test_negate() {
+        let at = |predicate: &CharacterPredicate, next: Option<char>| {
+            TwoCharTransition::predicate(CharacterPredicate::Any, Some(predicate.clone()), 0).matches('x', next)
+        };
+        let predicates = [
+            CharacterPredicate::Any,
+            CharacterPredicate::Char('a'),
+            CharacterPredicate::CharSet(HashSet::from(['a', 'c'])),
+            CharacterPredicate::NotCharSet(HashSet::from(['b'])),
+            CharacterPredicate::EndOfInput,
+            CharacterPredicate::Range('b', 'd'),
+            CharacterPredicate::Ranges(vec![('a', 'a'), ('c', 'e')]),
+        ];
+        
+        // As lookaheads, a predicate and its negation disagree on every character
+        for predicate in &predicates {
+            let negated = predicate.negate();
+            for next in ['a', 'b', 'c', 'f'] {
+                assert_ne!(at(predicate, Some(next)), at(&negated, Some(next)), "{:?} on {:?}", predicate, next);
+            }
+        }
+        assert_eq!(CharacterPredicate::Char('a').negate(), CharacterPredicate::NotCharSet(HashSet::from(['a'])));
+        assert_eq!(CharacterPredicate::Range('b', 'd').negate(), CharacterPredicate::Ranges(vec![('\0', 'a'), ('e', char::MAX)]));
+    }
+    
+    #[test]
+    fn test_match_length_bounds() {
+        assert_eq!(compile("abc").match_length_bounds(), (3, Some(3)));
+        assert_eq!(compile("a+").match_length_bounds(), (1, None));
+        assert_eq!(compile("a?").match_length_bounds(), (0, Some(1)));
+        assert_eq!(compile("ab|c").match_length_bounds(), (1, Some(2)));
+        assert_eq!(compile("a{2,4}").match_length_bounds(), (2, Some(4)));
+    }
+    
+    #[test]
+    fn test_prune_rejected() {
+        use crate::matcher::Matcher;
+        
+        // Hand-built rejection routing: 'x' and 'y' lead to rejected states
+        let mut nfa = NFA::new();
+        let extra_rejected = nfa.add_state(State::Rejected);
+        let class_state = nfa.transitions_state(vec![
+            TwoCharTransition::char('x', 1),
+            TwoCharTransition::char('y', extra_rejected),
+            TwoCharTransition::char('b', 0),
+        ]);
+        nfa.start = class_state;
+        
+        let before: Vec<bool> = ["b", "x", "y", ""].iter().map(|i| Matcher::new(&nfa).is_match(i)).collect();
+        nfa.prune_rejected();
+        let after: Vec<bool> = ["b", "x", "y", ""].iter().map(|i| Matcher::new(&nfa).is_match(i)).collect();
+        
+        assert_eq!(before, after);
+        assert_eq!(nfa.states.len(), 3);
+        assert_eq!(nfa.start, 2);
+        assert_eq!(nfa.states[1], State::Rejected);
+        assert_eq!(nfa.states[2], State::Transitions { transitions: vec![TwoCharTransition::char('b', 0)] });
+        
+        // Negated classes compile to a single `NotCharSet` transition rather than routing
+        // the excluded characters to rejected states, so there is nothing to prune
+        let mut nfa = compile("[^a]");
+        let start = nfa.transitions_of(nfa.start);
+        assert!(matches!(start[0].current, CharacterPredicate::NotCharSet(_)));
+        assert!(start.iter().all(|t| t.target != 1));
+        
+        let unpruned = nfa.clone();
+        nfa.prune_rejected();
+        assert_eq!(nfa, unpruned);
+        
+        // An empty keyword set starts at the reserved rejected state, which survives
+        let mut nfa = Compiler::new().compile_keywords(&[]).unwrap();
+        nfa.prune_rejected();
+        
+        assert_eq!(nfa.start, 1);
+        assert_eq!(nfa.states[1], State::Rejected);
+        assert!(!Matcher::new(&nfa).is_match(""));
+    }
+    
+    #[test]
+    fn test_dead_states() {
+        let mut nfa = NFA::new();
+        let orphan = nfa.transition_state(TwoCharTransition::char('z', 0));
+        let stuck = nfa.epsilon(usize::MAX);
+        nfa.start = nfa.transitions_state(vec![
+            TwoCharTransition::char('a', 0),
+            TwoCharTransition::char('b', stuck),
+        ]);
+        
+        assert_eq!(nfa.dead_states(), (vec![orphan], vec![stuck]));
+        
+        // Compiled patterns have none
+        assert_eq!(compile("a(?:b|c)*d").dead_states(), (Vec::new(), Vec::new()));
+    }
+    
+    #[test]
+    fn test_canonicalize() {
+        assert_eq!(compile("(a|bc)*d").canonicalize(), compile("(a|bc)*d").canonicalize());
+        
+        // The same chain built back to front and front to back
+        let mut forward = NFA::new();
+        let second = forward.transition_state(TwoCharTransition::char('b', 0));
+        forward.start = forward.transition_state(TwoCharTransition::char('a', second));
+        let mut backward = NFA::new();
+        let first = backward.transition_state(TwoCharTransition::char('a', usize::MAX));
+        let second = backward.transition_state(TwoCharTransition::char('b', 0));
+        backward.connect(first, second);
+        backward.start = first;
+        backward.epsilon(0);
+        
+        assert_ne!(forward, backward);
+        let canonical = backward.canonicalize();
+        assert_eq!(forward.canonicalize(), canonical);
+        assert_eq!(canonical.start, 2);
+        assert_eq!(canonical.states.len(), 4);
+        assert_eq!(canonical.classify("ab"), Some(0));
+    }
+    
+    #[test]
+    fn test_subgraph_from() {
+        use crate::matcher::Matcher;
+        
+        let nfa = compile("ab");
+        let second = nfa.transitions_of(nfa.start)[0].target;
+        let subgraph = nfa.subgraph_from(second);
+        
+        assert_eq!(subgraph.states.len(), 3);
+        assert_eq!(subgraph.start, 2);
+        assert_eq!(subgraph.dead_states(), (Vec::new(), Vec::new()));
+        
+        let matcher = Matcher::new(&subgraph);
+        assert!(matcher.is_match("b"));
+        assert!(!matcher.is_match("ab"));
+        assert!(!matcher.is_match(""));
+        
+        // Capture slots inside the subgraph come along
+        let nfa = compile("x(y)");
+        let second = nfa.transitions_of(nfa.start)[0].target;
+        let result = Matcher::new(&nfa.subgraph_from(second)).captures("y").unwrap();
+        assert_eq!(result.span(1), Some((0, 1)));
+    }
+    
+    #[test]
+    fn test_is_deterministic() {
+        assert!(compile("a|b").is_deterministic());
+        assert!(compile("abc").is_deterministic());
+        assert!(compile("a*b").is_deterministic());
+        assert!(!compile("ab|ac").is_deterministic());
+        assert!(!compile("a*a").is_deterministic());
+        
+        // regex-syntax folds `a|a` into a single literal, so build the split by hand
+        let mut nfa = NFA::new();
+        let first = nfa.transition_state(TwoCharTransition::char('a', 0));
+        let second = nfa.transition_state(TwoCharTransition::char('a', usize::MAX));
+        let end = nfa.epsilon(0);
+        nfa.connect(second, end);
+        nfa.start = nfa.split(vec![first, second]);
+        
+        assert!(!nfa.is_deterministic());
+    }
+    
+    #[test]
+    fn test_transitions_of() {
+        let mut nfa = NFA::new();
+        let ab_state = nfa.transitions_state(vec![
+            TwoCharTransition::char('a', 0),
+            TwoCharTransition::char('b', 0),
+        ]);
+        let split_state = nfa.split(vec![ab_state, 0]);
+        
+        assert_eq!(nfa.transitions_of(ab_state), &[TwoCharTransition::char('a', 0), TwoCharTransition::char('b', 0)]);
+        assert!(nfa.transitions_of(split_state).is_empty());
+        assert!(nfa.transitions_of(0).is_empty());
+        assert!(nfa.transitions_of(usize::MAX).is_empty());
+    }
+    
+    #[test]
+    fn test_step() {
+        let nfa = compile("ab");
+        let start = nfa.epsilon_closure(&std::iter::once(nfa.start).collect());
+        
+        let after_a = nfa.step(&start, 'a', Some('b'));
+        assert_eq!(after_a.len(), 1);
+        assert!(!nfa.is_accepting(&after_a));
+        
+        let after_b = nfa.step(&after_a, 'b', None);
+        assert!(nfa.is_accepting(&after_b));
+        assert!(nfa.step(&start, 'b', None).is_empty());
+        
+        // The lookahead decides which transitions fire
+        let mut nfa = NFA::new();
+        nfa.start = nfa.transition_state(TwoCharTransition::char_with_lookahead('a', 'b', 0));
+        let start: HashSet<StateId> = std::iter::once(nfa.start).collect();
+        assert!(nfa.is_accepting(&nfa.step(&start, 'a', Some('b'))));
+        assert!(nfa.step(&start, 'a', Some('c')).is_empty());
+        assert!(nfa.step(&start, 'a', None).is_empty());
+    }
+    
+    #[test]
+    fn test_transition_table_round_trip() {
+        let nfa = compile("ab");
+        let rows = nfa.transition_table().unwrap();
+        let accepting: Vec<StateId> = nfa.accepting.iter().copied().collect();
+        let rebuilt = NFA::from_transition_table(&rows, nfa.start, &accepting).unwrap();
+        
+        assert_eq!(rebuilt.states, nfa.states);
+        assert!(crate::Matcher::new(&rebuilt).is_match("ab"));
+        assert!(!crate::Matcher::new(&rebuilt).is_match("a"));
+        assert_eq!(rebuilt.transition_table().unwrap(), rows);
+        
+        // Splits keep their priority order
+        let nfa = compile("a+?");
+        let accepting: Vec<StateId> = nfa.accepting.iter().copied().collect();
+        let rebuilt = NFA::from_transition_table(&nfa.transition_table().unwrap(), nfa.start, &accepting).unwrap();
+        assert_eq!(rebuilt.states, nfa.states);
+        
+        assert!(compile("^a").transition_table().is_err());
+    }
+    
+    #[test]
+    fn test_transition_table_validation() {
+        let row = |from, current: Option<CharacterPredicate>, lookahead, to| TransitionRow { from, current, lookahead, to };
+        let build = |rows: &[TransitionRow]| NFA::from_transition_table(rows, 2, &[0]);
+        
+        assert!(build(&[row(2, Some(CharacterPredicate::Char('a')), None, 0)]).is_ok());
+        assert!(build(&[row(2, Some(CharacterPredicate::Range('b', 'a')), None, 0)]).is_err());
+        assert!(build(&[row(2, Some(CharacterPredicate::Ranges(vec![('a', 'f'), ('c', 'z')])), None, 0)]).is_err());
+        assert!(build(&[row(2, Some(CharacterPredicate::EndOfInput), None, 0)]).is_err());
+        assert!(build(&[row(2, None, Some(CharacterPredicate::Char('a')), 0)]).is_err());
+        assert!(build(&[row(2, None, None, 0), row(2, Some(CharacterPredicate::Any), None, 0)]).is_err());
+        assert!(build(&[row(2, Some(CharacterPredicate::Any), None, usize::MAX)]).is_err());
+        assert_eq!(build(&[row(2, None, None, usize::MAX - 1)]), Err(CompileError::TooComplex));
+    }
+    
+    #[test]
+    fn test_max_live_states() {
+        let deterministic = compile("abc");
+        assert_eq!(deterministic.max_live_states(5), 1);
+        
+        // The loop and its exit are live together
+        assert!(compile("(a|a)*").max_live_states(5) > deterministic.max_live_states(5));
+        
+        // After an `a`, `a*ab` may still be looping or already at the `b`
+        let nfa = compile("a*ab");
+        assert_eq!(nfa.max_live_states(0), 2);
+        assert_eq!(nfa.max_live_states(1), 3);
+        assert_eq!(nfa.max_live_states(4), 3);
+    }
+    
+    #[test]
+    fn test_connect_split_is_idempotent() {
+        let mut nfa = NFA::new();
+        let a = nfa.epsilon(0);
+        let b = nfa.epsilon(0);
+        let split = nfa.split(vec![a]);
+        
+        nfa.connect(split, b);
+        nfa.connect(split, b);
+        nfa.connect(split, a);
+        assert_eq!(nfa.states[split], State::Split { targets: vec![a, b] });
+    }
+    
+    #[test]
+    fn test_snapshot() {
+        assert_eq!(
+            compile("a|b").snapshot(),
+            "start: 2\naccepting: 0\n0: match\n1: rejected\n2: transitions\n    [a-b] -> 0\n"
+        );
+    
+        // Transitions and set members come out sorted whatever order they were built in
+        let mut nfa = NFA::new();
+        nfa.start = nfa.transitions_state(vec![
+            TwoCharTransition { current: CharacterPredicate::CharSet(['z', 'x', 'y'].into_iter().collect()), lookahead: None, target: 0 },
+            TwoCharTransition::char_with_lookahead('a', 'b', usize::MAX),
+            TwoCharTransition::char('a', 0),
+        ]);
+        assert_eq!(
+            nfa.snapshot(),
+            "start: 2\naccepting: 0\n0: match\n1: rejected\n2: transitions\n    'a' -> 0\n    'a' / 'b' -> ?\n    [xyz] -> 0\n"
+        );
+    }
+    
+    #[test]
+    fn test_complexity() {
+        let single = compile("a").complexity();
+        assert_eq!(single, Complexity { states: 2, transitions: 1, cyclomatic: 1, loops: 0 });
+        
+        // Classes are interval predicates, so a range costs one transition, not one per letter
+        let class = compile("[a-z]{3}").complexity();
+        assert_eq!((class.states, class.transitions, class.cyclomatic, class.loops), (4, 3, 1, 0));
+        
+        let looped = compile("(?:a|bc)*d").complexity();
+        assert_eq!(looped.loops, 1);
+        assert!(looped.cyclomatic > 1);
+        assert_eq!(compile("a*b*").complexity().loops, 2);
+    }
+    
+    #[test]
+    fn test_stats() {
+        let stats = compile("[abc]").stats();
+        
+        assert_eq!(stats.transition_states, 1);
+        assert_eq!(stats.transitions, 1);
+        assert_eq!(stats.lookahead_transitions, 0);
+        assert_eq!(stats.max_transitions_per_state, 1);
+        assert_eq!(stats.match_states, 1);
+        assert_eq!(stats.rejected_states, 1);
+        assert_eq!(stats.accepting_states, 1);
+        
+        let stats = compile("a*b").stats();
+        assert_eq!(stats.transition_states, 2);
+        assert!(stats.split_states >= 1);
+    }
+    
+    #[test]
+    fn test_intersect() {
+        let nfa = compile("[a-c]+").intersect(&compile("[b-d]+")).unwrap();
+        let matcher = crate::matcher::Matcher::new(&nfa);
+        
+        for input in ["b", "c", "bc", "cbbc"] {
+            assert!(matcher.is_match(input), "{:?}", input);
+        }
+        for input in ["", "a", "d", "ab", "cd", "bcd"] {
+            assert!(!matcher.is_match(input), "{:?}", input);
+        }
+    }
+    
+    #[test]
+    fn test_determinize() {
+        let inputs = ["", "a", "ab", "ac", "aab", "aaab", "aa", "b", "abc"];
+        for pattern in ["ab|ac", "a*a", "a++b", "(?:a|ab)c?", "[^b]+"] {
+            let nfa = compile(pattern);
+            let dfa = nfa.determinize().unwrap();
+            assert!(dfa.is_deterministic(), "{}", pattern);
+            
+            let (original, determinized) = (crate::matcher::Matcher::new(&nfa), crate::matcher::Matcher::new(&dfa));
+            for input in inputs {
+                assert_eq!(determinized.is_match(input), original.is_match(input), "{} on {:?}", pattern, input);
+            }
+        }
+        
+        assert!(matches!(compile("^a|b$").determinize(), Err(CompileError::UnsupportedFeature(_))));
+    }
+    
+    #[test]
+    fn test_remove_epsilons() {
+        let is_epsilon_free = |nfa: &NFA| !nfa.states.iter().any(|state| matches!(state, State::Epsilon { .. } | State::Split { .. }));
+        
+        let nfa = compile("(?:a|b)c").remove_epsilons();
+        assert!(is_epsilon_free(&nfa));
+        let matcher = crate::matcher::Matcher::new(&nfa);
+        assert!(matcher.is_match("ac") && matcher.is_match("bc"));
+        assert!(!matcher.is_match("c") && !matcher.is_match("abc"));
+        
+        let inputs = ["", "a", "ab", "ac", "aab", "aaab", "aa", "b", "abc", "bc", "cab"];
+        for pattern in ["ab|ac", "a*a", "a++b", "(?:a|ab)c?", "[^b]+", "(a*)*b", "a?b?c?", "(?:ab)+|c"] {
+            let nfa = compile(pattern);
+            let epsilon_free = nfa.remove_epsilons();
+            assert!(is_epsilon_free(&epsilon_free), "{}", pattern);
+            
+            let (original, rewritten) = (crate::matcher::Matcher::new(&nfa), crate::matcher::Matcher::new(&epsilon_free));
+            for input in inputs {
+                assert_eq!(rewritten.is_match(input), original.is_match(input), "{} on {:?}", pattern, input);
+            }
+        }
+        
+        // Assertions are kept behind a split
+        let anchored = compile("^a|b$").remove_epsilons();
+        let matcher = crate::matcher::Matcher::new(&anchored);
+        assert!(matcher.is_match("a") && matcher.is_match("b"));
+        assert_eq!(matcher.find("xab").map(|m| (m.start, m.end)), Some((2, 3)));
+    }
+    
+    #[test]
+    fn test_complement() {
+        let complement = compile("abc").complement().unwrap();
+        let matcher = crate::matcher::Matcher::new(&complement);
+        
+        assert!(!matcher.is_match("abc"));
+        for input in ["abx", "", "ab", "abcd", "xabc", "\u{10FFFF}"] {
+            assert!(matcher.is_match(input), "{:?}", input);
+        }
+        
+        // Lookaheads are honoured, and complementing twice gives back the language
+        let nfa = compile("a++b|[^a]");
+        let complement = nfa.complement().unwrap();
+        let twice = complement.complement().unwrap();
+        let (original, complement, twice) = (
+            crate::matcher::Matcher::new(&nfa),
+            crate::matcher::Matcher::new(&complement),
+            crate::matcher::Matcher::new(&twice),
+        );
+        for input in ["", "a", "b", "ab", "aab", "aaba", "c", "cc", "ba"] {
+            assert_eq!(complement.is_match(input), !original.is_match(input), "{:?}", input);
+            assert_eq!(twice.is_match(input), original.is_match(input), "{:?}", input);
+        }
+    }
+    
+    #[test]
+    fn test_first_set() {
+        assert_eq!(compile("abc|xyz").first_set(), CharacterPredicate::CharSet(['a', 'x'].into_iter().collect()));
+        assert_eq!(compile("a+b").first_set(), CharacterPredicate::Char('a'));
+        assert_eq!(compile("(?:[0-9]|_)x").first_set(), CharacterPredicate::Ranges(vec![('0', '9'), ('_', '_')]));
+        assert_eq!(compile("[^a]|a").first_set(), CharacterPredicate::Any);
+        
+        // Patterns that can match empty can start anywhere
+        assert_eq!(compile("a*").first_set(), CharacterPredicate::Any);
+        assert_eq!(compile("(?:x|)y?").first_set(), CharacterPredicate::Any);
+    }
+    
+    #[test]
+    fn test_required_literals() {
+        assert_eq!(compile(".*foo.*").required_literals(), vec!["foo".to_string()]);
+        assert_eq!(compile("a*foobar").required_literals(), vec!["foobar".to_string()]);
+        assert_eq!(compile("x[0-9]+yz").required_literals(), vec!["x".to_string(), "yz".to_string()]);
+        assert_eq!(compile("(?:ab)+").required_literals(), vec!["ab".to_string()]);
+        assert!(compile("foo|bar").required_literals().is_empty());
+        assert!(compile("(?:foo)?").required_literals().is_empty());
+    }
+    
+    #[test]
+    fn test_merge_transitions() {
+        let mut nfa = NFA::new();
+        let state = nfa.transitions_state(vec![
+            TwoCharTransition::char('a', 0),
+            TwoCharTransition::char('b', 0),
+            TwoCharTransition::char('c', 0),
+        ]);
+        nfa.start = state;
+        
+        nfa.merge_transitions();
+        
+        let abc: HashSet<char> = ['a', 'b', 'c'].into_iter().collect();
+        assert_eq!(
+            nfa.transitions_of(state),
+            &[TwoCharTransition::predicate(CharacterPredicate::CharSet(abc), None, 0)]
+        );
+        
+        let matcher = crate::matcher::Matcher::new(&nfa);
+        for input in ["a", "b", "c"] {
+            assert!(matcher.is_match(input), "{:?}", input);
+        }
+        assert!(!matcher.is_match("d"));
+    }
+    
+    #[test]
+    fn test_range_predicates() {
+        use CharacterPredicate::*;
+        
+        assert_eq!(CharacterPredicate::from_ranges(vec![('d', 'f'), ('a', 'c')]), Some(Range('a', 'f')));
+        assert_eq!(CharacterPredicate::from_ranges(vec![('x', 'x')]), Some(Char('x')));
+        assert_eq!(CharacterPredicate::from_ranges(Vec::new()), None);
+        
+        let ranges = Ranges(vec![('0', '9'), ('a', 'f')]);
+        assert!(ranges.matches('5') && ranges.matches('a') && ranges.matches('f'));
+        assert!(!ranges.matches('g') && !ranges.matches(':'));
+        
+        assert_eq!(intersect_predicates(&ranges, &Range('5', 'b')), Some(Ranges(vec![('5', '9'), ('a', 'b')])));
+        let excluded: HashSet<char> = ['b', 'c'].into_iter().collect();
+        assert_eq!(intersect_predicates(&Range('a', 'd'), &NotCharSet(excluded)), Some(Ranges(vec![('a', 'a'), ('d', 'd')])));
+        assert!(!ranges.intersects(&Range('g', 'z')));
+    }
+    
+    #[test]
+    fn test_predicate_intersection() {
+        use CharacterPredicate::*;
+        let set = |chars: &str| -> HashSet<char> { chars.chars().collect() };
+        
+        assert_eq!(Any.intersection(&Range('a', 'f')), Range('a', 'f'));
+        assert_eq!(Range('a', 'f').intersection(&Char('c')), Char('c'));
+        assert!(!Range('a', 'f').intersects(&Char('z')));
+        assert_eq!(NotCharSet(set("ab")).intersection(&NotCharSet(set("bc"))), NotCharSet(set("abc")));
+        assert_eq!(CharSet(set("abx")).intersection(&Range('a', 'f')), CharSet(set("ab")));
+        assert_eq!(Char('a').intersection(&Char('b')), CharSet(HashSet::new()));
+        assert_eq!(EndOfInput.intersection(&Any), CharSet(HashSet::new()));
+        assert!(!Any.intersects(&CharSet(HashSet::new())));
+        
+        // Every pairing agrees with testing both predicates character by character
+        let predicates = [
+            Any,
+            Char('b'),
+            CharSet(set("bdx")),
+            CharSet(HashSet::new()),
+            NotCharSet(set("abc")),
+            NotCharSet(set("xyz")),
+            EndOfInput,
+            Range('a', 'c'),
+            Range('w', 'y'),
+            Ranges(vec![('0', '9'), ('c', 'e')]),
+        ];
+        let samples: Vec<char> = ('0'..='9').chain('a'..='z').chain(['\0', char::MAX]).collect();
+        for a in &predicates {
+            for b in &predicates {
+                let intersection = a.intersection(b);
+                let mut shared = false;
+                for &ch in &samples {
+                    let both = a.matches(ch) && b.matches(ch);
+                    assert_eq!(intersection.matches(ch), both, "{:?} & {:?} on {:?}", a, b, ch);
+                    shared |= both;
+                }
+                assert_eq!(a.intersects(b), shared, "{:?} & {:?}", a, b);
+                assert_eq!(a.intersects(b), b.intersects(a), "{:?} & {:?}", a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::matcher::MatchResult;
+use crate::nfa::{NFA, State, StateId};
+use std::collections::HashSet;
+
+/// Capture slots carried by a thread: `2 * group` is a group's start, `2 * group + 1` its end
+type Slots = Vec<Option<usize>>;
+
+/// A Pike VM that runs a two-character Thompson NFA with submatch tracking
+///
+/// Threads are kept in split-priority order and each one carries its own capture
+/// slots. The first thread to reach an accepting state cuts off every thread of lower
+/// priority, which yields leftmost-first matches with greedy and lazy quantifiers
+/// honoured, along with the span of every capture group.
+pub struct PikeMatcher<'a> {
+    nfa: &'a NFA,
+}
+
+impl<'a> PikeMatcher<'a> {
+    /// Create a new Pike VM for the given NFA
+    pub fn new(nfa: &'a NFA) -> Self {
+        Self { nfa }
+    }
+    
+    /// Find the leftmost-first match, reporting the span of every capture group
+    pub fn find(&self, input: &str) -> Option<MatchResult> {
+        let chars: Vec<char> = input.chars().collect();
+        
+        for start in 0..=chars.len() {
+            if let Some((accept, slots)) = self.match_at(&chars, start) {
+                return Some(self.to_match_result(accept, &slots));
+            }
+        }
+        
+        None
+    }
+    
+    /// Run the VM from a single start position, returning the accepting state and slots
+    /// of the winning thread
+    fn match_at(&self, chars: &[char], start: usize) -> Option<(StateId, Slots)> {
+        let mut threads = Vec::new();
+        let mut seen = HashSet::new();
+        let mut slots = vec![None; 2 * self.nfa.group_count()];
+        slots[0] = Some(start);
+        self.add_thread(&mut threads, &mut seen, self.nfa.start, slots, chars, start);
+        
+        let mut matched = None;
+        let mut position = start;
+        
+        loop {
+            if let Some(index) = threads.iter().position(|(state_id, _)| self.nfa.accepting.contains(state_id)) {
+                threads.truncate(index + 1);
+                if let Some((accept, mut slots)) = threads.pop() {
+                    slots[1] = Some(position);
+                    matched = Some((accept, slots));
+                }
+            }
+            
+            if threads.is_empty() || position >= chars.len() {
+                break;
+            }
+            
+            let current_char = chars[position];
+            let next_char = chars.get(position + 1).copied();
+            let mut next_threads = Vec::new();
+            seen.clear();
+            
+            for (state_id, slots) in &threads {
+                for transition in self.nfa.transitions_of(*state_id) {
+                    if transition.matches(current_char, next_char) {
+                        self.add_thread(&mut next_threads, &mut seen, transition.target, slots.clone(), chars, position + 1);
+                    }
+                }
+            }
+            
+            threads = next_threads;
+            position += 1;
+        }
+        
+        matched
+    }
+    
+    /// Follow epsilon states from `state_id` in priority order, recording capture slots
+    ///
+    /// Assertions are only followed where they hold at `position`.
+    fn add_thread(
+        &self,
+        threads: &mut Vec<(StateId, Slots)>,
+        seen: &mut HashSet<StateId>,
+        state_id: StateId,
+        slots: Slots,
+        chars: &[char],
+        position: usize,
+    ) {
+        let mut stack = vec![(state_id, slots)];
+        
+        while let Some((state_id, mut slots)) = stack.pop() {
+            if state_id >= self.nfa.states.len() || !seen.insert(state_id) {
+                continue;
+            }
+            if let Some(&slot) = self.nfa.capture_slots.get(&state_id) {
+                slots[slot] = Some(position);
+            }
+            
+            match &self.nfa.states[state_id] {
+                State::Epsilon { next } => stack.push((*next, slots)),
+                State::Assertion { kind, next } => {
+                    if kind.holds(chars, position) {
+                        stack.push((*next, slots));
+                    }
+                },
+                State::Split { targets } => {
+                    // Push in reverse so the first target is explored first
+                    for &target in targets.iter().rev() {
+                        stack.push((target, slots.clone()));
+                    }
+                },
+                _ => threads.push((state_id, slots)),
+            }
+        }
+    }
+    
+    /// Turn the winning thread's slots into a match result with group spans
+    fn to_match_result(&self, accept: StateId, slots: &[Option<usize>]) -> MatchResult {
+        let groups: Vec<_> = slots.chunks(2)
+            .map(|pair| match (pair[0], pair[1]) {
+                (Some(start), Some(end)) => Some((start, end)),
+                _ => None,
+            })
+            .collect();
+        let (start, end) = groups[0].unwrap_or_default();
+        
+        MatchResult {
+            groups,
+            group_names: self.nfa.group_names.clone(),
+            annotation: self.nfa.annotation(accept),
+            ..MatchResult::new(start, end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use regex_syntax::ParserBuilder;
+    
+    fn compile(pattern: &str) -> NFA {
+        let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+        Compiler::new().compile(&hir).unwrap()
+    }
+    
+    #[test]
+    fn test_greedy_and_lazy_priority() {
+        let span = |pattern: &str, input: &str| {
+            let nfa = compile(pattern);
+            PikeMatcher::new(&nfa).find(input).map(|m| (m.start, m.end))
+        };
+        
+        assert_eq!(span("a+", "aaa"), Some((0, 3)));
+        assert_eq!(span("a+?", "aaa"), Some((0, 1)));
+        assert_eq!(span("a*?b", "xaab"), Some((1, 4)));
+        assert_eq!(span("a??", "a"), Some((0, 0)));
+        assert_eq!(span("b", "aaa"), None);
+        
+        // An empty iteration leaves the loop before a longer one is tried
+        assert_eq!(span("(.*?)*", "baa"), Some((0, 0)));
+        assert_eq!(span("a|(?:c??)*(?:.){0,2}?(?:[^a])", "caba"), Some((0, 1)));
+    }
+    
+    #[test]
+    fn test_capture_extraction() {
+        let nfa = compile("(a+)(a+)");
+        let result = PikeMatcher::new(&nfa).find("aaaa").unwrap();
+        
+        assert_eq!((result.start, result.end), (0, 4));
+        assert_eq!(result.group(0), Some((0, 4)));
+        assert_eq!(result.group(1), Some((0, 3)));
+        assert_eq!(result.group(2), Some((3, 4)));
+        
+        let nfa = compile("(a+?)(a+)");
+        let result = PikeMatcher::new(&nfa).find("aaaa").unwrap();
+        
+        assert_eq!(result.group(1), Some((0, 1)));
+        assert_eq!(result.group(2), Some((1, 4)));
+        
+        // A group on a branch that didn't match has no span
+        let nfa = compile("(x)|(a)");
+        let result = PikeMatcher::new(&nfa).find("a").unwrap();
+        
+        assert_eq!(result.group(1), None);
+        assert_eq!(result.group(2), Some((0, 1)));
+    }
+}
@@ -0,0 +1,596 @@
+use crate::nfa::{AssertionKind, CharacterPredicate, NFA, QuantifierInfo, QuantifierKind, State, StateId, TwoCharTransition};
+use crate::{CompileError, CompileResult};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Leading bytes identifying a serialized NFA
+const MAGIC: &[u8; 4] = b"TNFA";
+
+/// Version of the binary layout, bumped whenever the encoding changes
+const VERSION: u8 = 1;
+
+/// Encoded form of an unpatched (`usize::MAX`) state ID
+const UNPATCHED: u32 = u32::MAX;
+
+/// Encoded form of a quantifier without a maximum
+const UNBOUNDED: u32 = u32::MAX;
+
+// State tags
+const STATE_TRANSITIONS: u8 = 0;
+const STATE_EPSILON: u8 = 1;
+const STATE_SPLIT: u8 = 2;
+const STATE_MATCH: u8 = 3;
+const STATE_REJECTED: u8 = 4;
+const STATE_ASSERTION: u8 = 5;
+
+// Assertion tags
+const ASSERTION_LOOK_BEHIND: u8 = 0;
+const ASSERTION_END_TEXT: u8 = 1;
+const ASSERTION_START_TEXT: u8 = 2;
+const ASSERTION_START_LINE: u8 = 3;
+const ASSERTION_END_LINE: u8 = 4;
+const ASSERTION_WORD_BOUNDARY: u8 = 5;
+const ASSERTION_NOT_FOLLOWED_BY: u8 = 6;
+
+// Predicate tags (`PREDICATE_NONE` marks a transition without lookahead)
+const PREDICATE_ANY: u8 = 0;
+const PREDICATE_CHAR: u8 = 1;
+const PREDICATE_CHAR_SET: u8 = 2;
+const PREDICATE_NOT_CHAR_SET: u8 = 3;
+const PREDICATE_END_OF_INPUT: u8 = 4;
+const PREDICATE_RANGE: u8 = 5;
+const PREDICATE_RANGES: u8 = 6;
+const PREDICATE_NONE: u8 = 0xFF;
+
+// Quantifier kind tags
+const QUANTIFIER_GREEDY: u8 = 0;
+const QUANTIFIER_LAZY: u8 = 1;
+const QUANTIFIER_POSSESSIVE: u8 = 2;
+
+impl NFA {
+    /// Serialize the NFA to a compact, versioned binary layout
+    ///
+    /// The layout is the magic bytes `TNFA`, a version byte, the start state, the
+    /// states with their transitions, and then the accepting states, pattern IDs, group
+    /// names, capture slots, annotations and loop quantifiers. Integers are little-endian `u32`s, and sets are written
+    /// in sorted order so the same NFA always encodes to the same bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.bytes.push(VERSION);
+        writer.state_id(self.start);
+        
+        writer.len(self.states.len());
+        for state in &self.states {
+            match state {
+                State::Transitions { transitions } => {
+                    writer.bytes.push(STATE_TRANSITIONS);
+                    writer.len(transitions.len());
+                    for transition in transitions {
+                        writer.predicate(&transition.current);
+                        match &transition.lookahead {
+                            Some(lookahead) => writer.predicate(lookahead),
+                            None => writer.bytes.push(PREDICATE_NONE),
+                        }
+                        writer.state_id(transition.target);
+                    }
+                },
+                State::Epsilon { next } => {
+                    writer.bytes.push(STATE_EPSILON);
+                    writer.state_id(*next);
+                },
+                State::Split { targets } => {
+                    writer.bytes.push(STATE_SPLIT);
+                    writer.len(targets.len());
+                    for &target in targets {
+                        writer.state_id(target);
+                    }
+                },
+                State::Match => writer.bytes.push(STATE_MATCH),
+                State::Rejected => writer.bytes.push(STATE_REJECTED),
+                State::Assertion { kind, next } => {
+                    writer.bytes.push(STATE_ASSERTION);
+                    writer.assertion(kind);
+                    writer.state_id(*next);
+                },
+            }
+        }
+        
+        writer.len(self.accepting.len());
+        for &state_id in &self.accepting {
+            writer.state_id(state_id);
+        }
+        
+        writer.sorted_pairs(&self.pattern_ids);
+        
+        writer.len(self.group_names.len());
+        for name in &self.group_names {
+            match name {
+                Some(name) => {
+                    writer.bytes.push(1);
+                    writer.len(name.len());
+                    writer.bytes.extend_from_slice(name.as_bytes());
+                },
+                None => writer.bytes.push(0),
+            }
+        }
+        
+        writer.sorted_pairs(&self.capture_slots);
+        
+        let mut annotations: Vec<_> = self.annotations.iter().map(|(&state_id, &annotation)| (state_id, annotation)).collect();
+        annotations.sort_unstable();
+        writer.len(annotations.len());
+        for (state_id, annotation) in annotations {
+            writer.state_id(state_id);
+            writer.u32(annotation);
+        }
+        
+        let mut quantifiers: Vec<_> = self.quantifiers.iter().map(|(&state_id, &quantifier)| (state_id, quantifier)).collect();
+        quantifiers.sort_unstable_by_key(|&(state_id, _)| state_id);
+        writer.len(quantifiers.len());
+        for (state_id, quantifier) in quantifiers {
+            writer.state_id(state_id);
+            writer.bytes.push(match quantifier.kind {
+                QuantifierKind::Greedy => QUANTIFIER_GREEDY,
+                QuantifierKind::Lazy => QUANTIFIER_LAZY,
+                QuantifierKind::Possessive => QUANTIFIER_POSSESSIVE,
+            });
+            writer.u32(quantifier.min);
+            writer.u32(quantifier.max.unwrap_or(UNBOUNDED));
+        }
+        
+        writer.bytes
+    }
+    
+    /// Deserialize an NFA written by `to_bytes`
+    ///
+    /// Returns `CompileError::Internal` for a wrong magic or version and for truncated
+    /// or malformed input, including state IDs past the state list (other than the
+    /// unpatched marker) and capture slots past the last group.
+    pub fn from_bytes(bytes: &[u8]) -> CompileResult<NFA> {
+        let mut reader = Reader { bytes, position: 0 };
+        
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(CompileError::Internal("not a serialized NFA".to_string()));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(CompileError::Internal(format!(
+                "unsupported NFA format version {} (expected {})", version, VERSION
+            )));
+        }
+        let start = reader.state_id()?;
+        
+        let state_count = reader.len()?;
+        let mut states = Vec::new();
+        for _ in 0..state_count {
+            let state = match reader.u8()? {
+                STATE_TRANSITIONS => {
+                    let count = reader.len()?;
+                    let mut transitions = Vec::new();
+                    for _ in 0..count {
+                        let current = reader.predicate()?;
+                        let lookahead = reader.optional_predicate()?;
+                        let target = reader.state_id()?;
+                        transitions.push(TwoCharTransition::predicate(current, lookahead, target));
+                    }
+                    State::Transitions { transitions }
+                },
+                STATE_EPSILON => State::Epsilon { next: reader.state_id()? },
+                STATE_SPLIT => {
+                    let count = reader.len()?;
+                    let mut targets = Vec::new();
+                    for _ in 0..count {
+                        targets.push(reader.state_id()?);
+                    }
+                    State::Split { targets }
+                },
+                STATE_MATCH => State::Match,
+                STATE_REJECTED => State::Rejected,
+                STATE_ASSERTION => {
+                    let kind = reader.assertion()?;
+                    State::Assertion { kind, next: reader.state_id()? }
+                },
+                tag => return Err(CompileError::Internal(format!("unknown state tag {}", tag))),
+            };
+            states.push(state);
+        }
+        
+        let accepting_count = reader.len()?;
+        let mut accepting = BTreeSet::new();
+        for _ in 0..accepting_count {
+            accepting.insert(reader.state_id()?);
+        }
+        
+        let mut nfa = NFA::from_states(states, start, accepting);
+        nfa.pattern_ids = reader.pairs()?;
+        
+        let group_count = reader.len()?;
+        nfa.group_names = Vec::new();
+        for _ in 0..group_count {
+            let name = match reader.u8()? {
+                0 => None,
+                _ => {
+                    let len = reader.len()?;
+                    let name = std::str::from_utf8(reader.take(len)?)
+                        .map_err(|_| CompileError::Internal("group name is not UTF-8".to_string()))?;
+                    Some(name.to_string())
+                },
+            };
+            nfa.group_names.push(name);
+        }
+        
+        nfa.capture_slots = reader.pairs()?;
+        
+        let annotation_count = reader.len()?;
+        for _ in 0..annotation_count {
+            let state_id = reader.state_id()?;
+            nfa.annotations.insert(state_id, reader.u32()?);
+        }
+        
+        let quantifier_count = reader.len()?;
+        for _ in 0..quantifier_count {
+            let state_id = reader.state_id()?;
+            let kind = match reader.u8()? {
+                QUANTIFIER_GREEDY => QuantifierKind::Greedy,
+                QUANTIFIER_LAZY => QuantifierKind::Lazy,
+                QUANTIFIER_POSSESSIVE => QuantifierKind::Possessive,
+                tag => return Err(CompileError::Internal(format!("unknown quantifier tag {}", tag))),
+            };
+            let min = reader.u32()?;
+            let max = Some(reader.u32()?).filter(|&max| max != UNBOUNDED);
+            nfa.quantifiers.insert(state_id, QuantifierInfo { kind, min, max });
+        }
+        
+        if reader.position != bytes.len() {
+            return Err(CompileError::Internal("trailing bytes after serialized NFA".to_string()));
+        }
+        
+        validate(&nfa)?;
+        Ok(nfa)
+    }
+}
+
+/// Check that a decoded NFA's state IDs and capture slots point at something
+///
+/// Unpatched (`usize::MAX`) targets are allowed, as `to_bytes` writes them as they are.
+/// Group 0, the whole match, must be present since the matchers always record it.
+fn validate(nfa: &NFA) -> CompileResult<()> {
+    if nfa.group_names.is_empty() {
+        return Err(CompileError::Internal("no capture groups, not even the whole match".to_string()));
+    }
+    
+    let in_range = |state_id: StateId| state_id < nfa.states.len() || state_id == usize::MAX;
+    let targets = nfa.states.iter().flat_map(|state| match state {
+        State::Transitions { transitions } => transitions.iter().map(|transition| transition.target).collect(),
+        State::Epsilon { next } | State::Assertion { next, .. } => vec![*next],
+        State::Split { targets } => targets.clone(),
+        State::Match | State::Rejected => Vec::new(),
+    });
+    for state_id in std::iter::once(nfa.start).chain(nfa.accepting.iter().copied()).chain(targets) {
+        if !in_range(state_id) {
+            return Err(CompileError::Internal(format!("state {} is out of range", state_id)));
+        }
+    }
+    
+    let slot_count = 2 * nfa.group_names.len();
+    if let Some(&slot) = nfa.capture_slots.values().find(|&&slot| slot >= slot_count) {
+        return Err(CompileError::Internal(format!("capture slot {} is out of range", slot)));
+    }
+    Ok(())
+}
+
+/// Appends encoded values to a byte buffer
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+    
+    fn state_id(&mut self, state_id: StateId) {
+        self.u32(if state_id == usize::MAX { UNPATCHED } else { state_id as u32 });
+    }
+    
+    fn chars(&mut self, set: &HashSet<char>) {
+        let mut chars: Vec<_> = set.iter().copied().collect();
+        chars.sort_unstable();
+        self.len(chars.len());
+        for ch in chars {
+            self.u32(ch as u32);
+        }
+    }
+    
+    fn predicate(&mut self, predicate: &CharacterPredicate) {
+        match predicate {
+            CharacterPredicate::Any => self.bytes.push(PREDICATE_ANY),
+            CharacterPredicate::Char(ch) => {
+                self.bytes.push(PREDICATE_CHAR);
+                self.u32(*ch as u32);
+            },
+            CharacterPredicate::CharSet(set) => {
+                self.bytes.push(PREDICATE_CHAR_SET);
+                self.chars(set);
+            },
+            CharacterPredicate::NotCharSet(set) => {
+                self.bytes.push(PREDICATE_NOT_CHAR_SET);
+                self.chars(set);
+            },
+            CharacterPredicate::EndOfInput => self.bytes.push(PREDICATE_END_OF_INPUT),
+            CharacterPredicate::Range(start, end) => {
+                self.bytes.push(PREDICATE_RANGE);
+                self.u32(*start as u32);
+                self.u32(*end as u32);
+            },
+            CharacterPredicate::Ranges(ranges) => {
+                self.bytes.push(PREDICATE_RANGES);
+                self.len(ranges.len());
+                for &(start, end) in ranges {
+                    self.u32(start as u32);
+                    self.u32(end as u32);
+                }
+            },
+        }
+    }
+    
+    fn assertion(&mut self, kind: &AssertionKind) {
+        match kind {
+            AssertionKind::LookBehind { body, width } => {
+                self.bytes.push(ASSERTION_LOOK_BEHIND);
+                self.len(*width);
+                let body = body.to_bytes();
+                self.len(body.len());
+                self.bytes.extend_from_slice(&body);
+            },
+            AssertionKind::StartText => self.bytes.push(ASSERTION_START_TEXT),
+            AssertionKind::StartLine { crlf } => {
+                self.bytes.push(ASSERTION_START_LINE);
+                self.bytes.push(*crlf as u8);
+            },
+            AssertionKind::EndLine { crlf } => {
+                self.bytes.push(ASSERTION_END_LINE);
+                self.bytes.push(*crlf as u8);
+            },
+            AssertionKind::EndText { allow_trailing_newline } => {
+                self.bytes.push(ASSERTION_END_TEXT);
+                self.bytes.push(*allow_trailing_newline as u8);
+            },
+            AssertionKind::WordBoundary { ascii, negated } => {
+                self.bytes.push(ASSERTION_WORD_BOUNDARY);
+                self.bytes.push(*ascii as u8);
+                self.bytes.push(*negated as u8);
+            },
+            AssertionKind::NotFollowedBy { sequences } => {
+                self.bytes.push(ASSERTION_NOT_FOLLOWED_BY);
+                self.len(sequences.len());
+                for sequence in sequences {
+                    self.len(sequence.len());
+                    for predicate in sequence {
+                        self.predicate(predicate);
+                    }
+                }
+            },
+        }
+    }
+    
+    fn sorted_pairs(&mut self, map: &HashMap<StateId, usize>) {
+        let mut pairs: Vec<_> = map.iter().map(|(&key, &value)| (key, value)).collect();
+        pairs.sort_unstable();
+        self.len(pairs.len());
+        for (key, value) in pairs {
+            self.state_id(key);
+            self.len(value);
+        }
+    }
+}
+
+/// Reads encoded values back, failing instead of panicking on truncated input
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> CompileResult<&'a [u8]> {
+        let end = self.position.checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| CompileError::Internal("serialized NFA is truncated".to_string()))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+    
+    fn u8(&mut self) -> CompileResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+    
+    fn u32(&mut self) -> CompileResult<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    
+    fn len(&mut self) -> CompileResult<usize> {
+        Ok(self.u32()? as usize)
+    }
+    
+    fn state_id(&mut self) -> CompileResult<StateId> {
+        let value = self.u32()?;
+        Ok(if value == UNPATCHED { usize::MAX } else { value as usize })
+    }
+    
+    fn char(&mut self) -> CompileResult<char> {
+        let value = self.u32()?;
+        char::from_u32(value)
+            .ok_or_else(|| CompileError::Internal(format!("invalid character {:#x}", value)))
+    }
+    
+    fn chars(&mut self) -> CompileResult<HashSet<char>> {
+        let count = self.len()?;
+        let mut set = HashSet::new();
+        for _ in 0..count {
+            set.insert(self.char()?);
+        }
+        Ok(set)
+    }
+    
+    fn optional_predicate(&mut self) -> CompileResult<Option<CharacterPredicate>> {
+        if self.bytes.get(self.position) == Some(&PREDICATE_NONE) {
+            self.position += 1;
+            return Ok(None);
+        }
+        self.predicate().map(Some)
+    }
+    
+    fn predicate(&mut self) -> CompileResult<CharacterPredicate> {
+        match self.u8()? {
+            PREDICATE_ANY => Ok(CharacterPredicate::Any),
+            PREDICATE_CHAR => Ok(CharacterPredicate::Char(self.char()?)),
+            PREDICATE_CHAR_SET => Ok(CharacterPredicate::CharSet(self.chars()?)),
+            PREDICATE_NOT_CHAR_SET => Ok(CharacterPredicate::NotCharSet(self.chars()?)),
+            PREDICATE_END_OF_INPUT => Ok(CharacterPredicate::EndOfInput),
+            PREDICATE_RANGE => Ok(CharacterPredicate::Range(self.char()?, self.char()?)),
+            PREDICATE_RANGES => {
+                let len = self.len()?;
+                let ranges = (0..len)
+                    .map(|_| Ok((self.char()?, self.char()?)))
+                    .collect::<CompileResult<_>>()?;
+                Ok(CharacterPredicate::Ranges(ranges))
+            },
+            tag => Err(CompileError::Internal(format!("unknown predicate tag {}", tag))),
+        }
+    }
+    
+    fn assertion(&mut self) -> CompileResult<AssertionKind> {
+        match self.u8()? {
+            ASSERTION_LOOK_BEHIND => {
+                let width = self.len()?;
+                let len = self.len()?;
+                let body = Box::new(NFA::from_bytes(self.take(len)?)?);
+                Ok(AssertionKind::LookBehind { body, width })
+            },
+            ASSERTION_END_TEXT => Ok(AssertionKind::EndText { allow_trailing_newline: self.u8()? != 0 }),
+            ASSERTION_START_TEXT => Ok(AssertionKind::StartText),
+            ASSERTION_START_LINE => Ok(AssertionKind::StartLine { crlf: self.u8()? != 0 }),
+            ASSERTION_END_LINE => Ok(AssertionKind::EndLine { crlf: self.u8()? != 0 }),
+            ASSERTION_WORD_BOUNDARY => Ok(AssertionKind::WordBoundary { ascii: self.u8()? != 0, negated: self.u8()? != 0 }),
+            ASSERTION_NOT_FOLLOWED_BY => {
+                let len = self.len()?;
+                let sequences = (0..len)
+                    .map(|_| {
+                        let len = self.len()?;
+                        (0..len).map(|_| self.predicate()).collect()
+                    })
+                    .collect::<CompileResult<_>>()?;
+                Ok(AssertionKind::NotFollowedBy { sequences })
+            },
+            tag => Err(CompileError::Internal(format!("unknown assertion tag {}", tag))),
+        }
+    }
+    
+    fn pairs(&mut self) -> CompileResult<HashMap<StateId, usize>> {
+        let count = self.len()?;
+        let mut map = HashMap::new();
+        for _ in 0..count {
+            let key = self.state_id()?;
+            map.insert(key, self.len()?);
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::matcher::Matcher;
+    use regex_syntax::ParserBuilder;
+    
+    fn compile(pattern: &str) -> NFA {
+        let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+        Compiler::new().compile(&hir).unwrap()
+    }
+    
+    #[test]
+    fn test_round_trip() {
+        for pattern in ["abc", "a+?b", "[^xy]*", "(?P<word>[a-c]+)|z", "a++b", "a*+b", "(?:ab|a)*+c", "(?s).x", "ab$", "[0-9a-f]+", "^a|b$", "(?m)^x$", "(?Rm)x$", "\\bx\\B"] {
+            let nfa = compile(pattern);
+            let bytes = nfa.to_bytes();
+            let decoded = NFA::from_bytes(&bytes).unwrap();
+            
+            assert_eq!(decoded, nfa, "{}", pattern);
+            assert_eq!(decoded.to_bytes(), bytes, "{}", pattern);
+            
+            for input in ["abc", "aab", "zz", "\nx", "qq"] {
+                assert_eq!(
+                    Matcher::new(&decoded).find(input).map(|m| (m.start, m.end)),
+                    Matcher::new(&nfa).find(input).map(|m| (m.start, m.end)),
+                    "{} on {:?}", pattern, input
+                );
+            }
+        }
+    }
+    
+    #[test]
+    fn test_malformed_input_errors() {
+        let bytes = compile("(?P<x>ab)|c").to_bytes();
+        
+        // Every strict prefix is truncated somewhere and must error rather than panic
+        for len in 0..bytes.len() {
+            assert!(NFA::from_bytes(&bytes[..len]).is_err(), "prefix of {} bytes", len);
+        }
+        
+        let mut wrong_version = bytes.clone();
+        wrong_version[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(NFA::from_bytes(&wrong_version), Err(CompileError::Internal(_))));
+        
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(NFA::from_bytes(&wrong_magic).is_err());
+        
+        // Out-of-range slots and targets would panic the matchers later
+        let mut nfa = compile("(a)");
+        let open = *nfa.capture_slots.keys().next().unwrap();
+        nfa.capture_slots.insert(open, 105);
+        assert!(matches!(NFA::from_bytes(&nfa.to_bytes()), Err(CompileError::Internal(_))));
+        
+        let mut nfa = compile("ab");
+        let start = nfa.start;
+        if let State::Transitions { transitions } = &mut nfa.states[start] {
+            transitions[0].target = 50;
+        }
+        assert!(matches!(NFA::from_bytes(&nfa.to_bytes()), Err(CompileError::Internal(_))));
+        
+        let mut nfa = compile("ab");
+        nfa.start = 50;
+        assert!(NFA::from_bytes(&nfa.to_bytes()).is_err());
+        
+        let mut nfa = compile("ab");
+        nfa.group_names.clear();
+        assert!(matches!(NFA::from_bytes(&nfa.to_bytes()), Err(CompileError::Internal(_))));
+    }
+    
+    #[test]
+    fn test_output_is_reproducible() {
+        let compile_many = || {
+            let hirs: Vec<_> = ["[a-f]+", "[^x]y", "(?P<n>[0-9])z"].iter()
+                .map(|pattern| ParserBuilder::new().build().parse(pattern).unwrap())
+                .collect();
+            Compiler::new().compile_many(&hirs).unwrap()
+        };
+        
+        // Separately built NFAs have independently seeded hash sets inside
+        let first = compile_many();
+        let second = compile_many();
+        
+        assert_eq!(first.to_bytes(), second.to_bytes());
+        // Decoding fills fresh hash sets, which encode to the same bytes again
+        assert_eq!(NFA::from_bytes(&first.to_bytes()).unwrap().to_bytes(), second.to_bytes());
+        assert_eq!(format!("{:?}", first.accepting), format!("{:?}", second.accepting));
+    }
+}
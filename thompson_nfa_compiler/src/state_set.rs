@@ -0,0 +1,93 @@
+use crate::nfa::StateId;
+
+/// Bits per word of a `StateSet`
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A set of state IDs backed by a fixed-size bitset
+///
+/// Sized once to the NFA's state count, so inserting and testing a state is a bit
+/// operation with no hashing or allocation. IDs at or past the capacity are never
+/// members. Iteration yields IDs in ascending order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateSet {
+    words: Vec<u64>,
+    capacity: usize,
+}
+
+impl StateSet {
+    /// Create an empty set that can hold IDs below `capacity`
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            words: vec![0; capacity.div_ceil(WORD_BITS)],
+            capacity,
+        }
+    }
+    
+    /// Insert a state, returning whether it was newly added
+    ///
+    /// IDs at or past the capacity are rejected and return `false`.
+    pub fn insert(&mut self, state_id: StateId) -> bool {
+        if state_id >= self.capacity {
+            return false;
+        }
+        let (word, bit) = (state_id / WORD_BITS, 1 << (state_id % WORD_BITS));
+        let added = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        added
+    }
+    
+    /// Check whether a state is in the set
+    pub fn contains(&self, state_id: StateId) -> bool {
+        state_id < self.capacity && self.words[state_id / WORD_BITS] & (1 << (state_id % WORD_BITS)) != 0
+    }
+    
+    /// Remove every state, keeping the capacity
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+    
+    /// Count the states in the set
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+    
+    /// Check whether the set has no states
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+    
+    /// Iterate over the states in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = StateId> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..WORD_BITS)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index * WORD_BITS + bit)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_insert_contains_iter() {
+        let mut set = StateSet::new(130);
+        assert!(set.is_empty());
+        
+        for id in [129, 0, 64, 63, 5] {
+            assert!(set.insert(id));
+        }
+        assert!(!set.insert(64));
+        assert!(!set.insert(130));
+        
+        assert!(set.contains(63) && set.contains(129));
+        assert!(!set.contains(1) && !set.contains(130) && !set.contains(usize::MAX));
+        assert_eq!(set.len(), 5);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![0, 5, 63, 64, 129]);
+        
+        set.clear();
+        assert!(set.is_empty());
+        assert!(set.insert(64));
+    }
+}